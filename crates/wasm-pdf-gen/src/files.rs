@@ -1,50 +1,21 @@
 use crate::pdf::create;
+use crate::pdf::decoders;
 use crate::pdf::json::JsDocument;
 use crate::pdf::json::JsParamValue;
+use crate::pdf::json::JsSignature;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{BufReader, Write};
 use std::path::Path;
 
-/// Parse a jpg file to get width and height. Currently only jpg images are supported.
+/// Read a jpg or png file to get width and height.
 fn read_file(path: &Path, bytes: &mut Vec<u8>) -> Result<(f32, f32), String> {
-    let mut input_file = File::open(path).map_err(|err| format!("Error opening jpg: {}", err))?;
-    let len = input_file.read_to_end(bytes).unwrap();
-    let mut pos: usize = 0;
-
-    if bytes[pos] == 0xFF
-        && bytes[pos + 1] == 0xD8
-        && bytes[pos + 2] == 0xFF
-        && bytes[pos + 3] == 0xE0
-    {
-        pos += 4;
-        if bytes[pos + 2] == b'J'
-            && bytes[pos + 3] == b'F'
-            && bytes[pos + 4] == b'I'
-            && bytes[pos + 5] == b'F'
-            && bytes[pos + 6] == 0x00
-        {
-            let mut block_len: usize = bytes[pos] as usize * 256 + bytes[pos + 1] as usize;
-            while pos < len {
-                pos += block_len as usize;
-                if pos >= len {
-                    return Err("EOF.".to_string());
-                }
-                if bytes[pos] != 0xFF {
-                    return Err("Entered another block.".to_string());
-                }
-                if bytes[pos + 1] == 0xC0 {
-                    let height = bytes[pos + 5] as usize * 256 + bytes[pos + 6] as usize;
-                    let width = bytes[pos + 7] as usize * 256 + bytes[pos + 8] as usize;
-                    return Ok((width as f32, height as f32));
-                } else {
-                    pos += 2;
-                    block_len = bytes[pos] as usize * 256 + bytes[pos + 1] as usize;
-                }
-            }
-        }
-    }
-    Err("Invalid jpg format.".to_string())
+    let mut input_file = File::open(path).map_err(|err| format!("Error opening image: {}", err))?;
+    input_file
+        .read_to_end(bytes)
+        .map_err(|err| format!("Error reading image: {}", err))?;
+    let (width, height) = decoders::read_size(bytes)?;
+    Ok((width as f32, height as f32))
 }
 
 fn add_image_data_to_js(src: &str, base_path: &Path) -> Result<(String, f32, f32), String> {
@@ -56,8 +27,31 @@ fn add_image_data_to_js(src: &str, base_path: &Path) -> Result<(String, f32, f32
     Ok((image_data, width, height))
 }
 
+/// Signing key and certificates given as files, PKCS#12 or PEM.
+#[derive(Debug, Default)]
+pub struct SigningFiles {
+    pub pkcs12: String,
+    pub password: String,
+    pub private_key: String,
+    pub certificates: String,
+}
+
+fn read_text_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|err| format!("Error reading '{}': {}", path, err))
+}
+
 /// Process JSON and save PDF.
 pub fn process(input_fname: &str, output_fname: &str) -> Result<(), String> {
+    process_signed(input_fname, output_fname, None)
+}
+
+/// Process JSON and save PDF, signed with the key and certificates of the files if given.
+/// The files replace the signing keys of the JSON document.
+pub fn process_signed(
+    input_fname: &str,
+    output_fname: &str,
+    signing: Option<&SigningFiles>,
+) -> Result<(), String> {
     let input_file =
         File::open(input_fname).map_err(|err| format!("Error opening input: {}", err))?;
     let reader = BufReader::new(input_file);
@@ -135,6 +129,48 @@ pub fn process(input_fname: &str, output_fname: &str) -> Result<(), String> {
             _ => (),
         }
     }
+    let mut stationary_sources: Vec<String> = Vec::new();
+    for element in &js_doc.stationary {
+        if let "image" = element.obj_type.to_lowercase().as_str() {
+            if let Some(JsParamValue::Text(src)) = element.params.get("src") {
+                stationary_sources.push(src.to_owned());
+            }
+        }
+    }
+    if let Some(watermark) = &js_doc.template.watermark {
+        if let Some(JsParamValue::Text(src)) = watermark.get("src") {
+            stationary_sources.push(src.to_owned());
+        }
+    }
+    for src in stationary_sources {
+        if !js_doc.image_data.contains_key(&src) {
+            let (img_data, width, height) = add_image_data_to_js(&src, base_path)?;
+            js_doc.image_data.insert(src.clone(), img_data);
+            js_doc.image_heights.insert(src.clone(), height);
+            js_doc.image_widths.insert(src, width);
+        }
+    }
+    for attachment in &mut js_doc.attachments {
+        if attachment.data.is_empty() && !attachment.src.is_empty() {
+            let path = base_path.join(&attachment.src);
+            let data = std::fs::read(&path)
+                .map_err(|err| format!("Error reading '{}': {}", path.display(), err))?;
+            attachment.data = base64::encode(&data);
+        }
+    }
+    if let Some(signing) = signing {
+        let signature = js_doc.signature.get_or_insert_with(JsSignature::default);
+        if signing.pkcs12.is_empty() {
+            signature.pkcs12 = String::new();
+            signature.private_key = read_text_file(&signing.private_key)?;
+            signature.certificates = read_text_file(&signing.certificates)?;
+        } else {
+            let data = std::fs::read(&signing.pkcs12)
+                .map_err(|err| format!("Error reading '{}': {}", signing.pkcs12, err))?;
+            signature.pkcs12 = base64::encode(&data);
+            signature.password = signing.password.clone();
+        }
+    }
 
     let bytes = match create(&js_doc) {
         Ok(b) => b,
@@ -2,16 +2,24 @@
 //!
 //! This program can be compiled to a non-browser CLI application,
 //! and also be used with wasmtime and other apps with WASI capability.
+//!
+//! The document is signed when a key is given, either as PKCS#12:
+//! `--pkcs12 <file.p12> --password <password>`, or as PEM files:
+//! `--key <key.pem> --cert <certificates.pem>`.
 
 use std::env;
-use wasm_pdf_gen::files::process;
+use wasm_pdf_gen::files::{process_signed, SigningFiles};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     if args.len() < 3 {
-        eprintln!("{} <input.json> <output.pdf>", program);
+        eprintln!(
+            "{} <input.json> <output.pdf> [--pkcs12 <file.p12> --password <password>] \
+             [--key <key.pem> --cert <certificates.pem>]",
+            program
+        );
         return;
     }
 
@@ -25,7 +33,39 @@ fn main() {
         return;
     }
 
-    if let Err(err) = process(&args[1], &args[2]) {
+    let mut signing = SigningFiles::default();
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        let value = match options.next() {
+            Some(value) => value.clone(),
+            None => {
+                eprintln!("Missing value for {}.", option);
+                return;
+            }
+        };
+        match option.as_str() {
+            "--pkcs12" => signing.pkcs12 = value,
+            "--password" => signing.password = value,
+            "--key" => signing.private_key = value,
+            "--cert" => signing.certificates = value,
+            _ => {
+                eprintln!("Unknown option {}.", option);
+                return;
+            }
+        }
+    }
+    let has_pem = !signing.private_key.is_empty() || !signing.certificates.is_empty();
+    if has_pem && (signing.private_key.is_empty() || signing.certificates.is_empty()) {
+        eprintln!("Both --key and --cert are needed for signing with PEM files.");
+        return;
+    }
+    let signing = if signing.pkcs12.is_empty() && !has_pem {
+        None
+    } else {
+        Some(&signing)
+    };
+
+    if let Err(err) = process_signed(&args[1], &args[2], signing) {
         eprintln!("{}", err)
     }
 }
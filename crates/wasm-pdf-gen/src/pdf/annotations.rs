@@ -0,0 +1,546 @@
+use std::io::Write;
+
+use regex::Regex;
+
+use super::font::{get_font, Font};
+use super::forms::write_form_xobject;
+use super::json::{get_bool_from_js, get_text_from_js, to_js_string, JsParamValue};
+use super::metadata::encode_text_string;
+use super::objects::{encode_name, PDFFile, PDFObject};
+use super::styles::Color;
+use super::text::TextSpan;
+
+/// Size of sticky note icons
+const ICON_SIZE: f32 = 20.0;
+/// Font size and padding of free text boxes
+const FREE_TEXT_SIZE: f32 = 8.0;
+const FREE_TEXT_PADDING: f32 = 3.0;
+/// Size of the pop-up window showing the comment
+const POPUP_SIZE: (f32, f32) = (180.0, 100.0);
+
+/// Annotation subtype. Links are added from paragraph links and the table
+/// of contents, the others are review comments (markup annotations).
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnotationKind {
+    /// URL or '#name' of a named destination
+    Link {
+        url: String,
+    },
+    /// Sticky note shown as an icon in the margin
+    Text,
+    /// Comment written in a box in the margin
+    FreeText,
+    Highlight,
+    Underline,
+    /// Rectangle around the text
+    Square,
+    /// Paperclip icon in the margin opening one of the attached files
+    FileAttachment {
+        file: String,
+    },
+}
+
+impl AnnotationKind {
+    pub fn from_name(name: &str) -> Result<AnnotationKind, String> {
+        match name.to_lowercase().as_str() {
+            "" | "text" | "note" => Ok(AnnotationKind::Text),
+            "freetext" => Ok(AnnotationKind::FreeText),
+            "highlight" => Ok(AnnotationKind::Highlight),
+            "underline" => Ok(AnnotationKind::Underline),
+            "square" => Ok(AnnotationKind::Square),
+            "file" | "fileattachment" => Ok(AnnotationKind::FileAttachment {
+                file: String::new(),
+            }),
+            _ => Err(format!(
+                "Invalid annotation type '{}', expected \"text\", \"freetext\", \"highlight\", \"underline\", \"square\" or \"file\".",
+                name
+            )),
+        }
+    }
+    fn get_subtype(&self) -> &'static str {
+        match self {
+            AnnotationKind::Link { .. } => "Link",
+            AnnotationKind::Text => "Text",
+            AnnotationKind::FreeText => "FreeText",
+            AnnotationKind::Highlight => "Highlight",
+            AnnotationKind::Underline => "Underline",
+            AnnotationKind::Square => "Square",
+            AnnotationKind::FileAttachment { .. } => "FileAttachment",
+        }
+    }
+}
+
+/// Review comment attached to a paragraph or a span of text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Markup {
+    pub kind: AnnotationKind,
+    pub author: String,
+    pub contents: String,
+    pub color: Color,
+    /// Pop-up window is open when the document is opened
+    pub open: bool,
+}
+
+impl Markup {
+    /// Parse `{"type": "highlight", "author": "...", "contents": "...", "color": "#ffff00", "open": false}`,
+    /// file attachments give the name of the attached file in `"file"`.
+    pub fn from_param(value: &JsParamValue) -> Result<Markup, String> {
+        let params = match value {
+            JsParamValue::Object(params) => params,
+            _ => {
+                return Err(format!(
+                    "Invalid annotation {}: expected an object.",
+                    to_js_string(value)
+                ))
+            }
+        };
+        let color = match params.get("color") {
+            Some(color) => Color::from_param(color)?,
+            None => default_color(),
+        };
+        let mut markup = Markup {
+            kind: AnnotationKind::from_name(&get_text_from_js(params.get("type"), ""))?,
+            author: get_text_from_js(params.get("author"), ""),
+            contents: get_text_from_js(params.get("contents"), ""),
+            color,
+            open: get_bool_from_js(params.get("open"), false),
+        };
+        markup.set_file(&get_text_from_js(params.get("file"), ""))?;
+        Ok(markup)
+    }
+    /// Parse attributes of an inline annotation tag,
+    /// e.g. `type='highlight' author='Jane' contents='Check this' color='#ffcc00' open='true'`
+    /// or `type='file' file='invoice.xml'`.
+    pub fn from_attributes(attributes: &str) -> Result<Markup, String> {
+        lazy_static! {
+            static ref ATTRIBUTE: Regex = Regex::new(r"(\w+)='([^']*)'").unwrap();
+        }
+        let mut markup = Markup {
+            kind: AnnotationKind::Text,
+            author: String::new(),
+            contents: String::new(),
+            color: default_color(),
+            open: false,
+        };
+        let mut file = String::new();
+        for capture in ATTRIBUTE.captures_iter(attributes) {
+            let value = &capture[2];
+            match &capture[1] {
+                "type" => markup.kind = AnnotationKind::from_name(value)?,
+                "author" => markup.author = String::from(value),
+                "contents" => markup.contents = String::from(value),
+                "color" => markup.color = Color::from_param(&JsParamValue::Text(value.into()))?,
+                "open" => markup.open = value == "true",
+                "file" => file = String::from(value),
+                name => return Err(format!("Unknown annotation attribute '{}'.", name)),
+            }
+        }
+        markup.set_file(&file)?;
+        Ok(markup)
+    }
+    /// Set the attached file of file attachments, which is required.
+    fn set_file(&mut self, name: &str) -> Result<(), String> {
+        if let AnnotationKind::FileAttachment { file } = &mut self.kind {
+            if name.is_empty() {
+                return Err(String::from(
+                    "File attachment annotation needs a file name.",
+                ));
+            }
+            *file = String::from(name);
+        }
+        Ok(())
+    }
+}
+
+/// Review comments are yellow unless given another colour
+fn default_color() -> Color {
+    Color::new(1.0, 1.0, 0.0)
+}
+
+/// Annotation placed on a page, written to the page /Annots with its pop-up window.
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    kind: AnnotationKind,
+    author: String,
+    contents: String,
+    color: Option<Color>,
+    open: bool,
+    /// Annotation rectangle (x1, y1, x2, y2)
+    rect: [f32; 4],
+    /// Annotated text, a rectangle for each line
+    quads: Vec<[f32; 4]>,
+    popup_rect: [f32; 4],
+    object_id: u16,
+    popup_id: u16,
+}
+
+impl Annotation {
+    pub fn link(url: &str, x1: f32, y1: f32, x2: f32, y2: f32) -> Annotation {
+        Annotation {
+            kind: AnnotationKind::Link { url: url.into() },
+            author: String::new(),
+            contents: String::new(),
+            color: None,
+            open: false,
+            rect: [x1, y1, x2, y2],
+            quads: Vec::new(),
+            popup_rect: [0.0; 4],
+            object_id: 0,
+            popup_id: 0,
+        }
+    }
+    /// Comment on the text areas `quads`. Notes, free text and pop-ups are placed
+    /// in the margin between the right edge of the frame and the page edge.
+    pub fn markup(markup: &Markup, quads: Vec<[f32; 4]>, margin: (f32, f32)) -> Annotation {
+        let x1 = quads.iter().map(|quad| quad[0]).fold(f32::MAX, f32::min);
+        let y1 = quads.iter().map(|quad| quad[1]).fold(f32::MAX, f32::min);
+        let x2 = quads.iter().map(|quad| quad[2]).fold(f32::MIN, f32::max);
+        let y2 = quads.iter().map(|quad| quad[3]).fold(f32::MIN, f32::max);
+        let (frame_right, page_width) = margin;
+        let rect = match markup.kind {
+            AnnotationKind::Text | AnnotationKind::FileAttachment { .. } => {
+                let x = (frame_right + 2.0).min(page_width - ICON_SIZE);
+                [x, y2 - ICON_SIZE, x + ICON_SIZE, y2]
+            }
+            AnnotationKind::FreeText => {
+                let x = (frame_right + 2.0).min(page_width - 60.0);
+                let lines = wrap_text(&markup.contents, page_width - 2.0 - x);
+                let height = lines.len() as f32 * (FREE_TEXT_SIZE + 2.0) + 2.0 * FREE_TEXT_PADDING;
+                [x, y2 - height, page_width - 2.0, y2]
+            }
+            _ => [x1, y1, x2, y2],
+        };
+        let popup_x = frame_right.min(page_width - POPUP_SIZE.0);
+        Annotation {
+            kind: markup.kind.clone(),
+            author: markup.author.clone(),
+            contents: markup.contents.clone(),
+            color: Some(markup.color.clone()),
+            open: markup.open,
+            rect,
+            quads,
+            popup_rect: [popup_x, y2 - POPUP_SIZE.1, popup_x + POPUP_SIZE.0, y2],
+            object_id: 0,
+            popup_id: 0,
+        }
+    }
+    /// Map the annotated area with the matrix [a b c d e f] of transformed content.
+    /// Rectangles become the bounding boxes of the transformed ones, notes and
+    /// free text stay in the margin.
+    pub fn transform(&mut self, matrix: [f32; 6]) {
+        match self.kind {
+            AnnotationKind::Text
+            | AnnotationKind::FreeText
+            | AnnotationKind::FileAttachment { .. } => {}
+            _ => {
+                self.rect = transform_rect(self.rect, matrix);
+                for quad in &mut self.quads {
+                    *quad = transform_rect(*quad, matrix);
+                }
+            }
+        }
+    }
+    /// Annotation ids listed in the page /Annots
+    pub fn get_ids(&self) -> Vec<u16> {
+        match self.kind {
+            AnnotationKind::Link { .. } => vec![self.object_id],
+            _ => vec![self.object_id, self.popup_id],
+        }
+    }
+    /// False for links to '#name' when there is no such named destination.
+    pub fn has_target(&self, anchors: &[(String, usize, f32)]) -> bool {
+        match &self.kind {
+            AnnotationKind::Link { url } => match url.strip_prefix('#') {
+                Some(name) => anchors.iter().any(|(anchor, _, _)| anchor == name),
+                None => true,
+            },
+            _ => true,
+        }
+    }
+    /// Write the annotation, its pop-up and appearance stream. File attachments
+    /// must refer to one of the attached `files` (name and file specification id).
+    pub fn write(
+        &mut self,
+        pdf: &mut PDFFile,
+        font_id: u16,
+        files: &[(&str, u16)],
+    ) -> Result<(), &'static str> {
+        self.object_id = pdf.get_new_object_id();
+        let [x1, y1, x2, y2] = self.rect;
+        if let AnnotationKind::Link { url } = &self.kind {
+            let action = if let Some(name) = url.strip_prefix('#') {
+                format!("<< /S /GoTo /D /{} >>", encode_name(name))
+            } else {
+                format!("<< /S /URI /Type /Action /URI ({}) >>", url)
+            };
+            pdf.add_object(&PDFObject::new(
+                &format!(
+                    "/A {} /Border [ 0 0 0 ] /Rect [ {} {} {} {} ] /Subtype /Link /Type /Annot",
+                    action, x1, y1, x2, y2
+                ),
+                self.object_id,
+            ))?;
+            return Ok(());
+        }
+        self.popup_id = pdf.get_new_object_id();
+        let appearance = self.get_appearance(font_id);
+        let appearance_id = if appearance.is_some() {
+            pdf.get_new_object_id()
+        } else {
+            0
+        };
+        let mut entries = format!(
+            "/Type /Annot /Subtype /{} /F 4 /Rect [ {} {} {} {} ] /Popup {} 0 R",
+            self.kind.get_subtype(),
+            x1,
+            y1,
+            x2,
+            y2,
+            self.popup_id
+        );
+        if !self.author.is_empty() {
+            entries += &format!(" /T {}", encode_text_string(&self.author));
+        }
+        if !self.contents.is_empty() {
+            entries += &format!(" /Contents {}", encode_text_string(&self.contents));
+        }
+        if let Some(color) = &self.color {
+            let (_, components) = color.get_device_components();
+            let components: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+            entries += &format!(" /C [ {} ]", components.join(" "));
+        }
+        match &self.kind {
+            AnnotationKind::Text => {
+                entries += &format!(" /Name /Comment /Open {}", self.open);
+            }
+            AnnotationKind::FileAttachment { file } => {
+                let (_, filespec_id) = files
+                    .iter()
+                    .find(|(name, _)| name == file)
+                    .ok_or("File attachment annotation refers to a file that isn't attached.")?;
+                entries += &format!(" /FS {} 0 R /Name /Paperclip", filespec_id);
+            }
+            AnnotationKind::FreeText => {
+                entries += &format!(
+                    " /DA (/{} {} Tf 0 g)",
+                    get_free_text_font().get_ref(),
+                    FREE_TEXT_SIZE
+                );
+            }
+            AnnotationKind::Highlight | AnnotationKind::Underline => {
+                let points: Vec<String> = self
+                    .quads
+                    .iter()
+                    .map(|[x1, y1, x2, y2]| {
+                        format!("{} {} {} {} {} {} {} {}", x1, y2, x2, y2, x1, y1, x2, y1)
+                    })
+                    .collect();
+                entries += &format!(" /QuadPoints [ {} ]", points.join(" "));
+            }
+            _ => {}
+        }
+        if appearance.is_some() {
+            entries += &format!(" /AP << /N {} 0 R >>", appearance_id);
+        }
+        pdf.add_object(&PDFObject::new(&entries, self.object_id))?;
+        let [px1, py1, px2, py2] = self.popup_rect;
+        pdf.add_object(&PDFObject::new(
+            &format!(
+                "/Type /Annot /Subtype /Popup /Parent {} 0 R /Rect [ {} {} {} {} ] /Open {}",
+                self.object_id, px1, py1, px2, py2, self.open
+            ),
+            self.popup_id,
+        ))?;
+        if let Some((resources, contents)) = appearance {
+            pdf.add_bytes(&write_form_xobject(
+                appearance_id,
+                x2 - x1,
+                y2 - y1,
+                &resources,
+                &contents,
+            ))?;
+        }
+        Ok(())
+    }
+    /// Resources and contents of the appearance stream, in coordinates relative to the
+    /// rectangle. Viewers draw their own icons for sticky notes.
+    fn get_appearance(&self, font_id: u16) -> Option<(String, Vec<u8>)> {
+        let [left, bottom, right, top] = self.rect;
+        let (width, height) = (right - left, top - bottom);
+        let color = self.color.as_ref()?;
+        let (space, components) = color.get_device_components();
+        let components: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+        let operator = match space {
+            "DeviceCMYK" => "k",
+            "DeviceGray" => "g",
+            _ => "rg",
+        };
+        let fill = format!("{} {}", components.join(" "), operator);
+        let stroke = format!("{} {}", components.join(" "), operator.to_uppercase());
+        let mut output = Vec::new();
+        let resources = match self.kind {
+            AnnotationKind::Highlight => {
+                writeln!(output, "/GS0 gs {}", fill).unwrap();
+                for [x1, y1, x2, y2] in &self.quads {
+                    writeln!(
+                        output,
+                        "{} {} {} {} re",
+                        x1 - left,
+                        y1 - bottom,
+                        x2 - x1,
+                        y2 - y1
+                    )
+                    .unwrap();
+                }
+                writeln!(output, "f").unwrap();
+                String::from("/ExtGState << /GS0 << /Type /ExtGState /BM /Multiply >> >>")
+            }
+            AnnotationKind::Underline => {
+                writeln!(output, "{} 1 w", stroke).unwrap();
+                for [x1, y1, x2, _] in &self.quads {
+                    // just below the baseline
+                    let y = y1 - bottom + 1.0;
+                    writeln!(output, "{} {} m {} {} l", x1 - left, y, x2 - left, y).unwrap();
+                }
+                writeln!(output, "S").unwrap();
+                String::new()
+            }
+            AnnotationKind::Square => {
+                writeln!(
+                    output,
+                    "{} 1 w 0.5 0.5 {} {} re S",
+                    stroke,
+                    width - 1.0,
+                    height - 1.0
+                )
+                .unwrap();
+                String::new()
+            }
+            AnnotationKind::FreeText => {
+                let font = get_free_text_font();
+                writeln!(
+                    output,
+                    "{} 0 0 {} {} re f 0 G 0.5 w 0.25 0.25 {} {} re S",
+                    fill,
+                    width,
+                    height,
+                    width - 0.5,
+                    height - 0.5
+                )
+                .unwrap();
+                let lines = wrap_text(&self.contents, width);
+                write!(
+                    output,
+                    "BT /{} {} Tf 0 g {} TL {} {} Td",
+                    font.get_ref(),
+                    FREE_TEXT_SIZE,
+                    FREE_TEXT_SIZE + 2.0,
+                    FREE_TEXT_PADDING,
+                    height - FREE_TEXT_PADDING - FREE_TEXT_SIZE
+                )
+                .unwrap();
+                for line in lines {
+                    output.extend(TextSpan::encode_text(&line));
+                    write!(output, "T* ").unwrap();
+                }
+                writeln!(output, "ET").unwrap();
+                format!("/Font {} 0 R", font_id)
+            }
+            _ => return None,
+        };
+        Some((resources, output))
+    }
+}
+
+/// Bounding box of the rectangle (x1, y1, x2, y2) mapped with the matrix [a b c d e f].
+fn transform_rect(rect: [f32; 4], matrix: [f32; 6]) -> [f32; 4] {
+    let [a, b, c, d, e, f] = matrix;
+    let [x1, y1, x2, y2] = rect;
+    let corners = [(x1, y1), (x2, y1), (x1, y2), (x2, y2)];
+    let points: Vec<(f32, f32)> = corners
+        .iter()
+        .map(|&(x, y)| (a * x + c * y + e, b * x + d * y + f))
+        .collect();
+    [
+        points.iter().map(|p| p.0).fold(f32::MAX, f32::min),
+        points.iter().map(|p| p.1).fold(f32::MAX, f32::min),
+        points.iter().map(|p| p.0).fold(f32::MIN, f32::max),
+        points.iter().map(|p| p.1).fold(f32::MIN, f32::max),
+    ]
+}
+
+/// Font of free text comments, added to the document fonts when drawn
+pub fn get_free_text_font() -> &'static Font {
+    get_font("helvetica")
+}
+
+/// Lines of free text comment fitting in the box width.
+fn wrap_text(text: &str, width: f32) -> Vec<String> {
+    let font = get_free_text_font();
+    let available = width - 2.0 * FREE_TEXT_PADDING;
+    let mut lines: Vec<String> = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                String::from(word)
+            } else {
+                format!("{} {}", line, word)
+            };
+            if !line.is_empty() && font.get_width(FREE_TEXT_SIZE, &candidate) > available {
+                lines.push(line);
+                line = String::from(word);
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markup() {
+        let markup = Markup::from_attributes(
+            " type='highlight' author='Jane' contents='Check the total' color='#ff0000' open='true'",
+        )
+        .unwrap();
+        assert_eq!(markup.kind, AnnotationKind::Highlight);
+        assert_eq!(markup.author, "Jane");
+        assert_eq!(markup.color, Color::new(1.0, 0.0, 0.0));
+        assert!(markup.open);
+        assert!(Markup::from_attributes("type='circle'").is_err());
+        assert!(Markup::from_attributes("colour='red'").is_err());
+        assert!(Markup::from_attributes("type='file'").is_err());
+        let attachment = Markup::from_attributes("file='invoice.xml' type='file'").unwrap();
+        assert_eq!(
+            attachment.kind,
+            AnnotationKind::FileAttachment {
+                file: String::from("invoice.xml")
+            }
+        );
+
+        let annotation = Annotation::markup(
+            &markup,
+            vec![[50.0, 700.0, 300.0, 714.0], [50.0, 686.0, 120.0, 700.0]],
+            (545.0, 595.0),
+        );
+        assert_eq!(annotation.rect, [50.0, 686.0, 300.0, 714.0]);
+        let note = Markup {
+            kind: AnnotationKind::Text,
+            ..markup
+        };
+        let annotation =
+            Annotation::markup(&note, vec![[50.0, 700.0, 300.0, 714.0]], (545.0, 595.0));
+        assert_eq!(annotation.rect, [547.0, 694.0, 567.0, 714.0]);
+        assert_eq!(annotation.popup_rect, [415.0, 614.0, 595.0, 714.0]);
+        // a quarter turn around the origin
+        let mut link = Annotation::link("https://example.com", 10.0, 20.0, 50.0, 30.0);
+        link.transform([0.0, 1.0, -1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(link.rect, [-30.0, 10.0, -20.0, 50.0]);
+    }
+}
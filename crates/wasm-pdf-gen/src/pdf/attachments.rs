@@ -0,0 +1,139 @@
+use std::io::Write;
+
+use super::encoders;
+use super::encryption::{md5, to_hex};
+use super::metadata::{encode_text_string, get_pdf_date};
+use super::objects::{encode_name, PDFFile, PDFObject};
+
+/// Relationship of an attached file to the document (/AFRelationship),
+/// e.g. the XML invoice of a ZUGFeRD/Factur-X document is an alternative of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relationship {
+    Source,
+    Data,
+    Alternative,
+    Supplement,
+    Unspecified,
+}
+
+impl Relationship {
+    pub fn from_name(name: &str) -> Result<Relationship, String> {
+        match name.to_lowercase().as_str() {
+            "" | "unspecified" => Ok(Relationship::Unspecified),
+            "source" => Ok(Relationship::Source),
+            "data" => Ok(Relationship::Data),
+            "alternative" => Ok(Relationship::Alternative),
+            "supplement" => Ok(Relationship::Supplement),
+            _ => Err(format!(
+                "Invalid attachment relationship '{}', expected \"source\", \"data\", \"alternative\", \"supplement\" or \"unspecified\".",
+                name
+            )),
+        }
+    }
+    fn get_name(self) -> &'static str {
+        match self {
+            Relationship::Source => "Source",
+            Relationship::Data => "Data",
+            Relationship::Alternative => "Alternative",
+            Relationship::Supplement => "Supplement",
+            Relationship::Unspecified => "Unspecified",
+        }
+    }
+}
+
+/// File embedded in the document, listed in the /EmbeddedFiles name tree.
+#[derive(Debug, Clone)]
+pub struct EmbeddedFile {
+    name: String,
+    data: Vec<u8>,
+    mime_type: String,
+    description: String,
+    relationship: Relationship,
+}
+
+impl EmbeddedFile {
+    pub fn new(
+        name: &str,
+        data: Vec<u8>,
+        mime_type: &str,
+        description: &str,
+        relationship: Relationship,
+    ) -> EmbeddedFile {
+        EmbeddedFile {
+            name: String::from(name),
+            data,
+            mime_type: String::from(mime_type),
+            description: String::from(description),
+            relationship,
+        }
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    /// Write the embedded file stream and its file specification,
+    /// returns the id of the file specification.
+    pub fn write(&self, pdf: &mut PDFFile, mod_date: &str) -> Result<u16, &'static str> {
+        let stream_id = pdf.get_new_object_id();
+        let stream = encoders::zlib::encode(&self.data).unwrap();
+        let stream = encoders::ascii85::encode(&stream).unwrap();
+        let subtype = if self.mime_type.is_empty() {
+            String::new()
+        } else {
+            format!(" /Subtype /{}", encode_name(&self.mime_type))
+        };
+        let date = match get_pdf_date(mod_date) {
+            Some(date) => format!(" /ModDate ({})", date),
+            None => String::new(),
+        };
+        let mut output = Vec::new();
+        writeln!(
+            output,
+            "{} 0 obj\n<<\n/Type /EmbeddedFile{} /Params << /Size {} /CheckSum <{}>{} >> \
+             /Filter [/ASCII85Decode /FlateDecode] /Length {}\n>>",
+            stream_id,
+            subtype,
+            self.data.len(),
+            to_hex(&md5(&self.data)),
+            date,
+            stream.len() + 2 // ~> + 2
+        )
+        .unwrap();
+        writeln!(output, "stream").unwrap();
+        output.write_all(&stream).unwrap();
+        write!(output, "~>").unwrap(); // ascii85 stream end marker
+        writeln!(output, "endstream").unwrap();
+        writeln!(output, "endobj").unwrap();
+        pdf.add_bytes(&output)?;
+        let filespec_id = pdf.get_new_object_id();
+        let name = encode_text_string(&self.name);
+        let mut entries = format!(
+            "/Type /Filespec /F {0} /UF {0} /EF << /F {1} 0 R /UF {1} 0 R >> /AFRelationship /{2}",
+            name,
+            stream_id,
+            self.relationship.get_name()
+        );
+        if !self.description.is_empty() {
+            entries += &format!(" /Desc {}", encode_text_string(&self.description));
+        }
+        pdf.add_object(&PDFObject::new(&entries, filespec_id))?;
+        Ok(filespec_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relationship() {
+        assert_eq!(
+            Relationship::from_name("Alternative").unwrap(),
+            Relationship::Alternative
+        );
+        assert_eq!(
+            Relationship::from_name("").unwrap().get_name(),
+            "Unspecified"
+        );
+        assert!(Relationship::from_name("original").is_err());
+    }
+}
@@ -1,25 +1,114 @@
+use std::collections::HashMap;
 use std::io::Write;
 use std::str;
 
+use regex::{Captures, Regex};
+
+use super::annotations::{get_free_text_font, Annotation, AnnotationKind, Markup};
+use super::attachments::EmbeddedFile;
+use super::encryption::Encryption;
 use super::font::{get_font, Font};
-use super::models::{Cell, Image, Paragraph, Path, Row, Spacer, Stationary, Table};
-use super::objects::{LinkAnnotation, PDFDocument, PDFImage, PDFPage};
-use super::styles::{Color, HorizontalAlign, VerticalAlign};
-use super::template::PageTemplate;
+use super::forms::{FieldKind, PDFFormField};
+use super::metadata::DocumentInfo;
+use super::models::{
+    Cell, Destination, FormField, Image, Paragraph, Path, Row, Spacer, Stationary, Table,
+    TableOfContents, Transformed,
+};
+use super::objects::{PDFColorSpace, PDFDocument, PDFGraphicsState, PDFPage, PDFShading, PageMode};
+use super::signature::Signer;
+use super::styles::{
+    Clip, Color, Fill, Gradient, GradientKind, HorizontalAlign, Transform, Transparency,
+    VerticalAlign,
+};
+use super::template::{NotesMode, PageTemplate};
 use super::units::{Line, Point, Rect};
-use crate::pdf::text::{Tag, TextSpan};
+use crate::pdf::text::{Tag, TextSpan, SUPERSCRIPT_RISE, SUPERSCRIPT_SCALE};
+
+/// Transparency of the element being drawn and the colour alphas combined with it.
+/// Tracked through save_state and restore_state to avoid redundant ExtGState changes.
+#[derive(Debug, Clone, Copy)]
+struct TransparencyState {
+    requested: Transparency,
+    fill_alpha: f32,
+    stroke_alpha: f32,
+    applied: Transparency,
+}
+
+impl TransparencyState {
+    fn new() -> TransparencyState {
+        TransparencyState {
+            requested: Transparency::new(),
+            fill_alpha: 1.0,
+            stroke_alpha: 1.0,
+            applied: Transparency::new(),
+        }
+    }
+}
+
+/// Document values shown in stationary text placeholders and the table of contents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageInfo {
+    pub title: String,
+    pub date: String,
+    /// Total page count, known after the first pass
+    pub total_pages: Option<u16>,
+    /// Outline entries of the previous pass, Some when the document has a table of contents
+    pub toc: Option<Vec<TocEntry>>,
+}
+
+/// Outline entry listed in the table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub level: u8,
+    pub title: String,
+    /// Page of the entry (1 is the first page of the document)
+    pub page: u16,
+}
+
+/// Size of note bodies relative to the text referring to them
+const NOTE_SCALE: f32 = 0.8;
+/// Space for the rule above footnotes
+const NOTE_SEPARATOR: f32 = 8.0;
+
+/// Anchor name of the nth outline entry, used by table of contents links.
+fn get_toc_anchor(index: usize) -> String {
+    format!("toc:{}", index + 1)
+}
 
 pub struct Canvas {
     output: Vec<u8>,
     cursor: (f32, f32),
     template: PageTemplate,
     doc: PDFDocument,
-    images: Vec<PDFImage>,
-    link_annotations: Vec<LinkAnnotation>,
+    images: Vec<u16>,
+    graphics_states: Vec<PDFGraphicsState>,
+    shadings: Vec<PDFShading>,
+    color_spaces: Vec<PDFColorSpace>,
+    annotations: Vec<Annotation>,
+    form_fields: Vec<PDFFormField>,
+    transparency: TransparencyState,
+    transparency_stack: Vec<TransparencyState>,
+    page_info: PageInfo,
+    /// Mark values carried over from previous pages
+    marks: HashMap<String, String>,
+    /// First and last values of marks set on the current page
+    page_marks: HashMap<String, (String, String)>,
+    /// Outline entries and anchors waiting for their content to be placed on a page
+    pending_outline: Vec<(u8, String)>,
+    pending_anchors: Vec<String>,
+    /// Number of the last note reference
+    note_number: u16,
+    /// Footnotes of the current page and the space reserved for them at the bottom of the frame
+    page_notes: Vec<Paragraph>,
+    notes_height: f32,
+    /// Endnotes not listed yet
+    endnotes: Vec<Paragraph>,
+    /// Content is drawn inside the `q ... Q` of a transformation, pages can't break
+    transformed: bool,
 }
 
 impl Canvas {
-    pub fn new(tpl: &PageTemplate) -> Canvas {
+    pub fn new(tpl: &PageTemplate, page_info: PageInfo) -> Canvas {
         let (fx, fy, _, _) = tpl.get_frame().get_rect();
         let top_left = (fx, fy);
         let doc = PDFDocument::new();
@@ -30,7 +119,23 @@ impl Canvas {
             template: tpl.clone(),
             doc,
             images: Vec::new(),
-            link_annotations: Vec::new(),
+            graphics_states: Vec::new(),
+            shadings: Vec::new(),
+            color_spaces: Vec::new(),
+            annotations: Vec::new(),
+            form_fields: Vec::new(),
+            transparency: TransparencyState::new(),
+            transparency_stack: Vec::new(),
+            page_info,
+            marks: HashMap::new(),
+            page_marks: HashMap::new(),
+            pending_outline: Vec::new(),
+            pending_anchors: Vec::new(),
+            note_number: 0,
+            page_notes: Vec::new(),
+            notes_height: 0.0,
+            endnotes: Vec::new(),
+            transformed: false,
         };
         canvas.write_preamble();
         canvas
@@ -41,14 +146,34 @@ impl Canvas {
     /// Save the current graphics state to be restored later by restore_state.
     pub fn save_state(&mut self) {
         writeln!(self.output, "q").unwrap();
+        self.transparency_stack.push(self.transparency);
     }
     pub fn restore_state(&mut self) {
         writeln!(self.output, "Q").unwrap();
+        if let Some(transparency) = self.transparency_stack.pop() {
+            self.transparency = transparency;
+        }
     }
     /// All canvas pages are initialized with preamble.
     pub fn write_preamble(&mut self) {
         writeln!(self.output, "1 0 0 1 0 0 cm  BT /F1 12 Tf 14.4 TL ET").unwrap();
-        for element in self.template.stationary() {
+        self.draw_stationary(false);
+    }
+    /// Stationary elements drawn above the page contents, before the page is saved.
+    /// Text with marks is drawn here as well, since marks are known at the end of the page.
+    fn write_overlay(&mut self) {
+        self.draw_stationary(true);
+    }
+    fn draw_stationary(&mut self, overlay: bool) {
+        for element in self.template.stationary(self.doc.page_number()) {
+            let is_overlay = match &element {
+                Stationary::Watermark { above, .. } => *above,
+                Stationary::Text { text, .. } => text.contains("{mark:"),
+                _ => false,
+            };
+            if is_overlay != overlay {
+                continue;
+            }
             match element {
                 Stationary::PageNumber {
                     font_size,
@@ -56,10 +181,25 @@ impl Canvas {
                     x,
                     y,
                     color,
+                    align,
+                    transparency,
+                    transform,
                 } => {
-                    let number = self.doc.page_number().to_string();
+                    let number = match self.get_page_number() {
+                        Some(number) => number.to_string(),
+                        None => continue,
+                    };
                     let point = Point { x, y };
-                    self.draw_text_line(&number, font_size, &font, point, color);
+                    self.draw_text_line(
+                        &number,
+                        font_size,
+                        font,
+                        point,
+                        align,
+                        &color,
+                        &transparency,
+                        &transform,
+                    );
                 }
                 Stationary::Text {
                     text,
@@ -68,33 +208,229 @@ impl Canvas {
                     x,
                     y,
                     color,
+                    align,
+                    transparency,
+                    transform,
                 } => {
+                    let text = self.replace_placeholders(&text);
                     let point = Point { x, y };
-                    self.draw_text_line(&text, font_size, &font, point, color);
+                    self.draw_text_line(
+                        &text,
+                        font_size,
+                        font,
+                        point,
+                        align,
+                        &color,
+                        &transparency,
+                        &transform,
+                    );
+                }
+                Stationary::Image {
+                    image,
+                    x,
+                    y,
+                    transform,
+                } => {
+                    let (width, height) = image.get_dimensions();
+                    self.save_state();
+                    self.transform_around(&transform, x + width / 2.0, y + height / 2.0);
+                    self.translate(x, y);
+                    self.paint_image(&image, width, height);
+                    self.restore_state();
+                }
+                Stationary::Line {
+                    line,
+                    color,
+                    width,
+                    transparency,
+                } => {
+                    self.save_state();
+                    self.set_transparency(&transparency);
+                    self.set_stroke_color(&color);
+                    self.set_line_width(width);
+                    self.draw_line(line);
+                    self.restore_state();
+                }
+                Stationary::Rect {
+                    rect,
+                    radius,
+                    stroke_color,
+                    stroke_width,
+                    fill,
+                    transparency,
+                    transform,
+                } => {
+                    let shape = if radius > 0.0 {
+                        Clip::Rounded(radius)
+                    } else {
+                        Clip::Rect
+                    };
+                    let path_ops = format!("n {}\n", shape.get_path_ops(rect));
+                    self.save_state();
+                    self.transform_around(&transform, rect.x + rect.w / 2.0, rect.y - rect.h / 2.0);
+                    self.set_transparency(&transparency);
+                    self.paint_shape(
+                        path_ops.as_bytes(),
+                        rect,
+                        stroke_color.as_ref(),
+                        stroke_width,
+                        fill.as_ref(),
+                    );
+                    self.restore_state();
+                }
+                Stationary::Path { path, transform } => {
+                    let bounds = path.get_bounds();
+                    self.save_state();
+                    self.transform_around(
+                        &transform,
+                        bounds.x + bounds.w / 2.0,
+                        bounds.y - bounds.h / 2.0,
+                    );
+                    self.paint_path(&path);
+                    self.restore_state();
+                }
+                Stationary::Watermark { .. } => self.draw_watermark(&element),
+            }
+        }
+    }
+    /// Set value of a named mark on the current page.
+    pub fn set_mark(&mut self, name: &str, text: &str) {
+        self.page_marks
+            .entry(name.to_owned())
+            .and_modify(|(_, last)| *last = text.to_owned())
+            .or_insert_with(|| (text.to_owned(), text.to_owned()));
+    }
+    /// First or last value of a mark on the current page,
+    /// the value from previous pages if the mark is not set on this page.
+    fn get_mark(&self, name: &str, last: bool) -> String {
+        match self.page_marks.get(name) {
+            Some((first_value, last_value)) => {
+                if last {
+                    last_value.clone()
+                } else {
+                    first_value.clone()
                 }
             }
+            None => self.marks.get(name).cloned().unwrap_or_default(),
         }
     }
-    /// Draw a single line of text to given position.
+    /// Draws watermark text or image centered on its position and rotated around it.
+    fn draw_watermark(&mut self, watermark: &Stationary) {
+        if let Stationary::Watermark {
+            text,
+            image,
+            font_size,
+            font,
+            color,
+            transparency,
+            transform,
+            center,
+            ..
+        } = watermark
+        {
+            let center = center.unwrap_or_else(|| {
+                let (width, height) = self.template.get_size();
+                Point {
+                    x: width / 2.0,
+                    y: height / 2.0,
+                }
+            });
+            self.save_state();
+            self.transform_around(transform, center.x, center.y);
+            if let Some(image) = image {
+                let (width, height) = image.get_dimensions();
+                self.translate(center.x - width / 2.0, center.y - height / 2.0);
+                self.paint_image(image, width, height);
+            } else {
+                // vertically centered on cap height
+                let point = Point {
+                    x: center.x - font.get_width(*font_size, text) / 2.0,
+                    y: center.y - font_size * 0.35,
+                };
+                self.draw_text_line(
+                    text,
+                    *font_size,
+                    font,
+                    point,
+                    HorizontalAlign::Left,
+                    color,
+                    transparency,
+                    &Transform::new(),
+                );
+            }
+            self.restore_state();
+        }
+    }
+    /// Applies the transform around the given point.
+    fn transform_around(&mut self, transform: &Transform, x: f32, y: f32) {
+        if !transform.is_identity() {
+            let [a, b, c, d] = transform.get_matrix();
+            self.concat_matrix([a, b, c, d, x - a * x - c * y, y - b * x - d * y]);
+        }
+    }
+    /// Replace {page}, {pages}, {title}, {date} and {mark:name:first|last} placeholders
+    /// in stationary text. Total page count is known on the second pass, see PageTemplate::build.
+    fn replace_placeholders(&self, text: &str) -> String {
+        lazy_static! {
+            static ref MARK: Regex = Regex::new(r"\{mark:([^:}]+)(?::(first|last))?\}").unwrap();
+        }
+        let text = MARK.replace_all(text, |captures: &Captures| {
+            let last = captures
+                .get(2)
+                .is_some_and(|position| position.as_str() == "last");
+            self.get_mark(&captures[1], last)
+        });
+        let numbering = self.template.get_page_numbering();
+        let pages = match self.page_info.total_pages {
+            Some(pages) => numbering
+                .get_number(pages)
+                .map_or_else(String::new, |pages| pages.to_string()),
+            None => String::from("{pages}"),
+        };
+        let page = self
+            .get_page_number()
+            .map_or_else(String::new, |page| page.to_string());
+        text.replace("{page}", &page)
+            .replace("{pages}", &pages)
+            .replace("{title}", &self.page_info.title)
+            .replace("{date}", &self.page_info.date)
+    }
+    /// Draw a single line of text to given position, transform is applied around the position.
+    /// Text starts, ends or is centered at the position depending on alignment.
+    #[allow(clippy::too_many_arguments)]
     fn draw_text_line(
         &mut self,
         text: &str,
         font_size: f32,
         font: &'static Font,
         point: Point,
-        color: Color,
+        align: HorizontalAlign,
+        color: &Color,
+        transparency: &Transparency,
+        transform: &Transform,
     ) {
         self.doc.add_font(font); // font gets added only if it doesn't exist yet
         self.save_state();
         self.translate(point.x, point.y);
+        if !transform.is_identity() {
+            let [a, b, c, d] = transform.get_matrix();
+            self.concat_matrix([a, b, c, d, 0.0, 0.0]);
+        }
         self.save_state();
-        self.set_fill_color(color.r, color.g, color.b);
+        self.set_transparency(transparency);
+        self.set_fill_color(color);
         let out_text: Vec<u8> = TextSpan::encode_text(text);
+        let offset = match align {
+            HorizontalAlign::Left => 0.0,
+            HorizontalAlign::Center => -font.get_width(font_size, text) / 2.0,
+            HorizontalAlign::Right => -font.get_width(font_size, text),
+        };
         let mut stream = Vec::new();
         let leading = font_size;
         write!(
             stream,
-            "BT 1 0 0 1 0 2 Tm /{} {} Tf {} TL ",
+            "BT 1 0 0 1 {} 2 Tm /{} {} Tf {} TL ",
+            offset,
             font.get_ref(),
             font_size,
             leading
@@ -115,30 +451,202 @@ impl Canvas {
     pub fn translate(&mut self, dx: f32, dy: f32) {
         self.transform("1", "0", "0", "1", &dx.to_string(), &dy.to_string());
     }
+    /// Concatenates matrix [a b c d e f] to the current transformation matrix.
+    pub fn concat_matrix(&mut self, matrix: [f32; 6]) {
+        let values: Vec<String> = matrix.iter().map(|value| value.to_string()).collect();
+        self.transform(
+            &values[0], &values[1], &values[2], &values[3], &values[4], &values[5],
+        );
+    }
     /// Sets the fill color
-    pub fn set_fill_color(&mut self, r: f32, g: f32, b: f32) {
-        writeln!(self.output, "{} {} {} rg", r, g, b).unwrap();
+    pub fn set_fill_color(&mut self, color: &Color) {
+        let operator = self.get_color_operator(color, false);
+        writeln!(self.output, "{}", operator).unwrap();
     }
     /// Sets the stroke color
-    pub fn set_stroke_color(&mut self, r: f32, g: f32, b: f32) {
-        writeln!(self.output, "{} {} {} RG", r, g, b).unwrap();
+    pub fn set_stroke_color(&mut self, color: &Color) {
+        let operator = self.get_color_operator(color, true);
+        writeln!(self.output, "{}", operator).unwrap();
+    }
+    /// Colour operator for the colour space of the color (rg, k, g or scn).
+    /// Spot colours are added to the page colour space resources and colour
+    /// alpha is combined with the opacity of the element.
+    fn get_color_operator(&mut self, color: &Color, stroke: bool) -> String {
+        if stroke {
+            self.transparency.stroke_alpha = color.get_alpha();
+        } else {
+            self.transparency.fill_alpha = color.get_alpha();
+        }
+        let operator = match color {
+            Color::Rgb { r, g, b, .. } => {
+                format!("{} {} {} {}", r, g, b, if stroke { "RG" } else { "rg" })
+            }
+            Color::Cmyk { c, m, y, k } => {
+                format!("{} {} {} {} {}", c, m, y, k, if stroke { "K" } else { "k" })
+            }
+            Color::Gray(gray) => format!("{} {}", gray, if stroke { "G" } else { "g" }),
+            Color::Spot { name, tint, cmyk } => {
+                // spot colours of the same name may have different fallbacks
+                let existing = self
+                    .color_spaces
+                    .iter()
+                    .find(|color_space| color_space.matches(name, cmyk))
+                    .map(|color_space| color_space.get_uid());
+                let color_space_name = match existing {
+                    Some(uid) => uid,
+                    None => {
+                        let color_space_id = self.doc.get_color_space_id();
+                        let color_space = PDFColorSpace::new(color_space_id, name, *cmyk);
+                        let uid = color_space.get_uid();
+                        self.color_spaces.push(color_space);
+                        uid
+                    }
+                };
+                if stroke {
+                    format!("/{} CS {} SCN", color_space_name, tint)
+                } else {
+                    format!("/{} cs {} scn", color_space_name, tint)
+                }
+            }
+        };
+        match self.apply_transparency() {
+            Some(state_operator) => format!("{} {}", state_operator, operator),
+            None => operator,
+        }
+    }
+    /// Sets opacity and blend mode through an ExtGState resource of the current page.
+    /// States are shared between elements with identical parameters.
+    pub fn set_transparency(&mut self, transparency: &Transparency) {
+        self.transparency.requested = *transparency;
+        if let Some(state_operator) = self.apply_transparency() {
+            writeln!(self.output, "{}", state_operator).unwrap();
+        }
+    }
+    /// Combine requested transparency with colour alphas, returns `gs` operator
+    /// if the graphics state has to change.
+    fn apply_transparency(&mut self) -> Option<String> {
+        let state = self.transparency;
+        let effective = Transparency {
+            fill_opacity: state.requested.fill_opacity * state.fill_alpha,
+            stroke_opacity: state.requested.stroke_opacity * state.stroke_alpha,
+            blend_mode: state.requested.blend_mode,
+        };
+        if effective == state.applied {
+            return None;
+        }
+        self.transparency.applied = effective;
+        let fill_opacity = effective.fill_opacity;
+        let stroke_opacity = effective.stroke_opacity;
+        let blend_mode = effective.blend_mode.get_name();
+        let existing = self
+            .graphics_states
+            .iter()
+            .find(|state| state.matches(fill_opacity, stroke_opacity, blend_mode))
+            .map(|state| state.get_uid());
+        let state_name = match existing {
+            Some(name) => name,
+            None => {
+                let state_id = self.doc.get_graphics_state_id();
+                let state =
+                    PDFGraphicsState::new(state_id, fill_opacity, stroke_opacity, blend_mode);
+                let name = state.get_uid();
+                self.graphics_states.push(state);
+                name
+            }
+        };
+        Some(format!("/{} gs", state_name))
     }
     /// Sets the line width
     pub fn set_line_width(&mut self, width: f32) {
         writeln!(self.output, "{} w", width).unwrap();
     }
-    /// Fills a rect with color
-    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+    /// Paints a gradient over the rect (top left corner at rect.x, rect.y),
+    /// limited by the current clipping path.
+    pub fn paint_gradient(&mut self, gradient: &Gradient, rect: Rect) {
+        let shading_type = match gradient.kind {
+            GradientKind::Linear => 2,
+            GradientKind::Radial => 3,
+        };
+        // Stops share one device colour space, mixed spaces fall back to RGB.
+        let mut color_space = "DeviceRGB";
+        let mut stops: Vec<(f32, Vec<f32>)> = Vec::new();
+        for (index, (offset, color)) in gradient.stops.iter().enumerate() {
+            let (stop_space, components) = color.get_device_components();
+            if index == 0 {
+                color_space = stop_space;
+            } else if stop_space != color_space {
+                color_space = "DeviceRGB";
+            }
+            stops.push((*offset, components));
+        }
+        if color_space == "DeviceRGB" {
+            stops = gradient
+                .stops
+                .iter()
+                .map(|(offset, color)| {
+                    let (r, g, b) = color.to_rgb();
+                    (*offset, vec![r, g, b])
+                })
+                .collect();
+        }
+        let shading_id = self.doc.get_shading_id();
+        let shading = PDFShading::new(
+            shading_id,
+            shading_type,
+            &gradient.coords,
+            &stops,
+            &format!("/{}", color_space),
+        );
+        // Share identical gradients within the page
+        let shading_name = match self.shadings.iter().find(|s| s.matches(&shading)) {
+            Some(existing) => existing.get_uid(),
+            None => {
+                let name = shading.get_uid();
+                self.shadings.push(shading);
+                name
+            }
+        };
         self.save_state();
-        self.set_fill_color(color.r, color.g, color.b);
+        // map gradient unit square to rect, y axis pointing down
         writeln!(
             self.output,
-            "n {} {} {} {} re f*",
-            rect.x, rect.y, rect.w, -rect.h
+            "{} 0 0 {} {} {} cm /{} sh",
+            rect.w, -rect.h, rect.x, rect.y, shading_name
         )
         .unwrap();
         self.restore_state();
     }
+    /// Fills a rect with color or gradient
+    pub fn fill_rect(&mut self, rect: Rect, fill: &Fill) {
+        self.save_state();
+        match fill {
+            Fill::Solid(color) => {
+                self.set_fill_color(color);
+                writeln!(
+                    self.output,
+                    "n {} {} {} {} re f*",
+                    rect.x, rect.y, rect.w, -rect.h
+                )
+                .unwrap();
+            }
+            Fill::Gradient(gradient) => {
+                writeln!(
+                    self.output,
+                    "n {} {} {} {} re W n",
+                    rect.x, rect.y, rect.w, -rect.h
+                )
+                .unwrap();
+                self.paint_gradient(gradient, rect);
+            }
+        }
+        self.restore_state();
+    }
+    /// Intersects the clipping path with the shape in the rect (top left corner at rect.x, rect.y).
+    /// Clipping lasts until the enclosing restore_state.
+    pub fn clip(&mut self, clip: &Clip, rect: Rect) {
+        writeln!(self.output, "{}", clip.get_path_ops(rect)).unwrap();
+        writeln!(self.output, "W n").unwrap();
+    }
     /// Draws a line
     pub fn draw_line(&mut self, line: Line) {
         writeln!(
@@ -150,14 +658,29 @@ impl Canvas {
     }
     /// Save page to the document and clear page data.
     pub fn save_page(&mut self) {
+        self.write_footnotes();
+        self.write_overlay();
+        for (name, (_, last)) in self.page_marks.drain() {
+            self.marks.insert(name, last);
+        }
         let mut page = PDFPage::new();
         page.set_contents(&self.output);
         page.set_images(&self.images);
-        page.set_link_annotations(&self.link_annotations);
+        page.set_graphics_states(&self.graphics_states);
+        page.set_shadings(&self.shadings);
+        page.set_color_spaces(&self.color_spaces);
+        page.set_annotations(&self.annotations);
+        page.set_form_fields(&self.form_fields);
         self.doc.add_page(page);
         self.output = Vec::new();
         self.images = Vec::new();
-        self.link_annotations = Vec::new();
+        self.graphics_states = Vec::new();
+        self.shadings = Vec::new();
+        self.color_spaces = Vec::new();
+        self.transparency = TransparencyState::new();
+        self.transparency_stack = Vec::new();
+        self.annotations = Vec::new();
+        self.form_fields = Vec::new();
         self.write_preamble();
         let (fx, fy, _, _) = self.template.get_frame().get_rect();
         let top_left = (fx, fy);
@@ -183,43 +706,80 @@ impl Canvas {
             HorizontalAlign::Center => self.cursor.0 + (available_width - path.get_width()) / 2.0,
             _ => self.cursor.0 + available_width - path.get_width(),
         };
+        self.place_destinations(self.cursor.1);
         self.save_state();
         self.translate(pos_x, self.cursor.1 - path.get_height());
-        let stroke_color = path.get_stroke_color();
+        self.paint_path(path);
+        self.restore_state();
+        self.set_cursor(self.cursor.0, self.cursor.1 - path.get_height());
+        Ok(())
+    }
+    /// Paints the path with its points in the current coordinate system.
+    fn paint_path(&mut self, path: &Path) {
+        self.set_transparency(&path.get_style().transparency);
+        let mut path_ops: Vec<u8> = Vec::new();
+        for (index, point) in path.get_points().iter().enumerate() {
+            if index == 0 {
+                writeln!(path_ops, "n {} {} m", point.x, point.y).unwrap();
+            } else {
+                writeln!(path_ops, "{} {} l", point.x, point.y).unwrap();
+            }
+        }
+        writeln!(path_ops, "h").unwrap(); // close path
+        self.paint_shape(
+            &path_ops,
+            path.get_bounds(),
+            path.get_stroke_color(),
+            path.get_stroke_width(),
+            path.get_fill_color(),
+        );
+    }
+    /// Fills and strokes the shape given as path operators. Gradients are painted
+    /// over the bounds (top left corner in rect x and y) clipped to the shape.
+    fn paint_shape(
+        &mut self,
+        path_ops: &[u8],
+        bounds: Rect,
+        stroke_color: Option<&Color>,
+        stroke_width: f32,
+        fill: Option<&Fill>,
+    ) {
         if let Some(stroke_color) = stroke_color {
-            self.set_stroke_color(stroke_color.r, stroke_color.g, stroke_color.b);
+            self.set_stroke_color(stroke_color);
         }
-        let fill_color = path.get_fill_color();
-        if let Some(fill_color) = fill_color {
-            self.set_fill_color(fill_color.r, fill_color.g, fill_color.b);
+        if let Some(Fill::Solid(fill_color)) = fill {
+            self.set_fill_color(fill_color);
         }
-        let stroke_width = path.get_stroke_width();
+        let is_stroked = stroke_color.is_some() && stroke_width > 0.0;
         self.set_line_width(stroke_width);
-        let mut init_point_drawn = false;
-        for point in path.get_points() {
-            if !init_point_drawn {
-                writeln!(self.output, "n {} {} m", point.x, point.y).unwrap();
-                init_point_drawn = true;
-            } else {
-                writeln!(self.output, "{} {} l", point.x, point.y).unwrap();
+        match fill {
+            Some(Fill::Solid(_)) => {
+                self.output.write_all(path_ops).unwrap();
+                writeln!(self.output, "{}", if is_stroked { "B" } else { "f" }).unwrap();
             }
+            Some(Fill::Gradient(gradient)) => {
+                // clip to path, paint gradient over the bounding box and stroke afterwards
+                self.save_state();
+                self.output.write_all(path_ops).unwrap();
+                writeln!(self.output, "W n").unwrap();
+                self.paint_gradient(gradient, bounds);
+                self.restore_state();
+                if is_stroked {
+                    self.output.write_all(path_ops).unwrap();
+                    writeln!(self.output, "S").unwrap();
+                }
+            }
+            None if is_stroked => {
+                self.output.write_all(path_ops).unwrap();
+                writeln!(self.output, "S").unwrap();
+            }
+            None => (),
         }
-        writeln!(self.output, "h").unwrap(); // close path
-        if fill_color.is_some() && stroke_color.is_some() && stroke_width > 0.0 {
-            writeln!(self.output, "B").unwrap();
-        } else if fill_color.is_some() {
-            writeln!(self.output, "f").unwrap();
-        } else if stroke_color.is_some() && stroke_width > 0.0 {
-            writeln!(self.output, "S").unwrap();
-        }
-        self.restore_state();
-        self.set_cursor(self.cursor.0, self.cursor.1 - path.get_height());
-        Ok(())
     }
     fn draw_lines(&mut self, lines: Vec<Line>, table: &Table) {
         let style = table.get_style();
         self.save_state();
-        self.set_stroke_color(style.grid_color.r, style.grid_color.g, style.grid_color.b);
+        self.set_stroke_color(&style.grid_color);
         self.set_line_width(style.grid_width);
         for line in lines {
             self.draw_line(line);
@@ -235,8 +795,8 @@ impl Canvas {
         new_page: bool,
     ) -> Result<(), &'static str> {
         let table_style = table.get_style();
-        let (_, fy, fwidth, fheight) = self.template.get_frame().get_rect();
-        let frame_bottom = fy - fheight;
+        let (_, _, fwidth, _) = self.template.get_frame().get_rect();
+        let frame_bottom = self.get_frame_bottom();
         let horizontal_padding = table_style.padding_left + table_style.padding_right;
         let vertical_padding = table_style.padding_bottom + table_style.padding_top;
         let available_height = self.cursor.1 - frame_bottom - vertical_padding;
@@ -321,6 +881,7 @@ impl Canvas {
             let right_line = Line::new(r.x + r.w, r.y, r.x + r.w, r.y - r.h);
             grid_lines.push(right_line);
         }
+        self.place_destinations(row_cursor.1);
         // Draw grid lines if so configured
         if table_style.grid_visible {
             self.draw_lines(grid_lines, table);
@@ -329,10 +890,16 @@ impl Canvas {
         let mut cell_cursor = (row_cursor.0, row_cursor.1);
         for (index, cell) in row.get_cells().iter().enumerate() {
             let cell_width = cell.get_span() * span_width;
+            // Clip background and contents to the cell shape
+            let clip = &cell.get_style().clip;
+            if let Some(clip) = clip {
+                self.save_state();
+                self.clip(clip, cell_rects[index]);
+            }
             // Background color fill
-            if let Some(bg_color) = cell.get_style().background_color {
+            if let Some(background) = &cell.get_style().background_color {
                 let rect = cell_rects[index];
-                self.fill_rect(rect, bg_color);
+                self.fill_rect(rect, background);
             }
             // Check for vertical alignment
             let cell_content_height = cell_content_heights[index];
@@ -353,6 +920,9 @@ impl Canvas {
             for content in cell.get_contents() {
                 content.draw(self, cell_width - horizontal_padding)?
             }
+            if clip.is_some() {
+                self.restore_state();
+            }
             cell_cursor.0 += cell_width;
             self.set_cursor(cell_cursor.0, cell_cursor.1);
         }
@@ -380,50 +950,403 @@ impl Canvas {
     ) -> Result<(), &'static str> {
         // add image to canvas images first, then add transform to output
         // check first if image fits to this page..
-        let (_, fy, _, fheight) = self.template.get_frame().get_rect();
-        let frame_bottom = fy - fheight;
-        let (img_width, img_height) = image.get_dimensions();
-        let width = if image.fits_width() {
-            available_width
-        } else {
-            img_width
-        };
-        let height = if image.fits_width() {
-            available_width / img_width * img_height
-        } else {
-            img_height
-        };
-        let pos_x = if !image.fits_width() {
-            match image.get_style().horizontal_align {
-                HorizontalAlign::Left => self.cursor.0,
-                HorizontalAlign::Center => self.cursor.0 + (available_width - width) / 2.0,
-                _ => self.cursor.0 + available_width - width,
-            }
-        } else {
-            self.cursor.0
+        let frame_bottom = self.get_frame_bottom();
+        let (width, height) = image.get_size(available_width);
+        let pos_x = match image.get_style().horizontal_align {
+            HorizontalAlign::Left => self.cursor.0,
+            HorizontalAlign::Center => self.cursor.0 + (available_width - width) / 2.0,
+            _ => self.cursor.0 + available_width - width,
         };
         if self.cursor.1 - height < frame_bottom {
             if new_page {
-                return Err("Image is too large to fit on page.");
+                return Err(
+                    "Image is too large to fit on page, use shrink_to_page to scale it down.",
+                );
             }
             self.save_page();
             return self.draw_image(image, true, available_width);
         }
 
-        let image_id = self.doc.get_image_id();
-        let pdf_image = PDFImage::new(image_id, img_width, img_height, &image.get_data());
-        let image_name = pdf_image.get_uid();
-        self.images.push(pdf_image);
+        self.place_destinations(self.cursor.1);
         self.set_cursor(self.cursor.0, self.cursor.1 - height);
         self.save_state();
         self.translate(pos_x, self.cursor.1);
-        let mut stream = Vec::new();
-        writeln!(stream, "{} 0 0 {} 0 0 cm", width, height).unwrap();
-        writeln!(stream, "/{} Do", image_name).unwrap();
-        self.output.write_all(&stream).unwrap();
+        self.paint_image(image, width, height);
         self.restore_state();
         Ok(())
     }
+    /// Paints the image into a box of the given size at the origin of the current coordinate system.
+    fn paint_image(&mut self, image: &Image, width: f32, height: f32) {
+        let image_id = self.doc.add_image(image.get_data());
+        if !self.images.contains(&image_id) {
+            self.images.push(image_id);
+        }
+        let box_rect = Rect::new(0.0, height, width, height);
+        if let Some(clip) = &image.get_style().clip {
+            self.clip(clip, box_rect);
+        }
+        let (natural_width, natural_height) = image.get_data().get_natural_size();
+        let (x, y, image_width, image_height) =
+            image
+                .get_style()
+                .fit
+                .get_image_rect(natural_width / natural_height, width, height);
+        if image_width > width + 0.01 || image_height > height + 0.01 {
+            // covering image is cropped to its box
+            self.clip(&Clip::Rect, box_rect);
+        }
+        self.set_transparency(&image.get_style().transparency);
+        let mut matrix = image.get_data().get_matrix(image_width, image_height);
+        matrix[4] += x;
+        matrix[5] += y;
+        self.concat_matrix(matrix);
+        writeln!(self.output, "/Im{} Do", image_id).unwrap();
+    }
+    /// Draws a form field and adds its widgets to the page.
+    /// Check boxes and radio buttons are followed by their label or option text.
+    pub fn draw_form_field(
+        &mut self,
+        field: &FormField,
+        new_page: bool,
+        available_width: f32,
+    ) -> Result<(), &'static str> {
+        let (width, height) = field.get_size(available_width);
+        if self.cursor.1 - height < self.get_frame_bottom() {
+            if new_page {
+                return Err("Form field is too large to fit on page.");
+            }
+            self.save_page();
+            return self.draw_form_field(field, true, available_width);
+        }
+        self.place_destinations(self.cursor.1);
+        let font = field.get_font();
+        let font_size = field.get_font_size();
+        self.doc.add_font(font);
+        let (x, top) = self.cursor;
+        let labels: Vec<&str> = match field.get_kind() {
+            FieldKind::CheckBox { .. } => vec![field.get_label()],
+            FieldKind::Radio { options, .. } => options.iter().map(|o| o.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        let rects = if labels.is_empty() {
+            vec![[x, top - height, x + width, top]]
+        } else {
+            let row_height = field.get_row_height();
+            let mut rects = Vec::new();
+            for (index, label) in labels.iter().enumerate() {
+                // button centred in its row, label on the same baseline as the button bottom
+                let bottom = top - row_height * (index as f32 + 0.5) - font_size / 2.0;
+                rects.push([x, bottom, x + font_size, bottom + font_size]);
+                let mut stream = Vec::new();
+                write!(
+                    stream,
+                    "BT /{} {} Tf 0 g 1 0 0 1 {} {} Tm ",
+                    font.get_ref(),
+                    font_size,
+                    x + font_size * 1.5,
+                    bottom + font_size * 0.15
+                )
+                .unwrap();
+                stream.write_all(&TextSpan::encode_text(label)).unwrap();
+                writeln!(stream, "ET").unwrap();
+                self.output.write_all(&stream).unwrap();
+            }
+            rects
+        };
+        self.form_fields.push(PDFFormField::new(
+            field.get_name(),
+            field.get_kind(),
+            field.is_required(),
+            field.is_read_only(),
+            font,
+            font_size,
+            rects,
+        ));
+        self.set_cursor(x, top - height);
+        Ok(())
+    }
+    /// Draws content with a transformation. The content is laid out at the top of
+    /// the frame and mapped with `cm` so that its transformed bounding box sits at the cursor.
+    pub fn draw_transformed(
+        &mut self,
+        transformed: &Transformed,
+        new_page: bool,
+        available_width: f32,
+    ) -> Result<(), &'static str> {
+        let (_, fy, _, fheight) = self.template.get_frame().get_rect();
+        let frame_bottom = self.get_frame_bottom();
+        let content = transformed.get_content();
+        let transform = transformed.get_transform();
+        let (width, height) = content.wrap((available_width, fheight));
+        let (bounds_width, bounds_height) = transform.get_bounds(width, height);
+        if height > fheight || bounds_height > fheight {
+            return Err("Transformed content is too large to fit on page.");
+        }
+        // the content is laid out from the top of the frame and must end above
+        // the footnotes of the page, a page break would split the transformation
+        if self.cursor.1 - bounds_height < frame_bottom || fy - height < frame_bottom {
+            if new_page {
+                return Err("Transformed content is too large to fit on page.");
+            }
+            self.save_page();
+            return self.draw_transformed(transformed, true, available_width);
+        }
+        self.place_destinations(self.cursor.1);
+        let cursor = self.cursor;
+        let pos_x = match transformed.get_align() {
+            HorizontalAlign::Left => cursor.0,
+            HorizontalAlign::Center => cursor.0 + (available_width - bounds_width) / 2.0,
+            HorizontalAlign::Right => cursor.0 + available_width - bounds_width,
+        };
+        // centre of the bounding box and of the untransformed content
+        let (center_x, center_y) = (pos_x + bounds_width / 2.0, cursor.1 - bounds_height / 2.0);
+        let (content_x, content_y) = (center_x - width / 2.0, fy);
+        let (origin_x, origin_y) = (center_x, content_y - height / 2.0);
+        let [a, b, c, d] = transform.get_matrix();
+        let matrix = [
+            a,
+            b,
+            c,
+            d,
+            center_x - a * origin_x - c * origin_y,
+            center_y - b * origin_x - d * origin_y,
+        ];
+        self.save_state();
+        self.concat_matrix(matrix);
+        self.set_cursor(content_x, content_y);
+        let first_annotation = self.annotations.len();
+        self.transformed = true;
+        // small tolerance keeps rounding from wrapping the last word of a line
+        let drawn = content.draw(self, width + 0.01);
+        self.transformed = false;
+        drawn?;
+        self.restore_state();
+        // annotation rectangles are in page coordinates, not affected by cm
+        for annotation in &mut self.annotations[first_annotation..] {
+            annotation.transform(matrix);
+        }
+        self.set_cursor(cursor.0, cursor.1 - bounds_height);
+        Ok(())
+    }
+    /// Draws content and adds its outline entry and anchor. They point to the top of
+    /// the content on the page where drawing starts, content types place them when
+    /// they know whether they fit on the current page.
+    pub fn draw_destination(
+        &mut self,
+        destination: &Destination,
+        available_width: f32,
+    ) -> Result<(), &'static str> {
+        if let Some((level, title)) = destination.get_outline() {
+            self.pending_outline.push((*level, title.to_owned()));
+        }
+        if let Some(anchor) = destination.get_anchor() {
+            self.pending_anchors.push(String::from(anchor));
+        }
+        destination.get_content().draw(self, available_width)?;
+        // e.g. spacers don't place them
+        self.place_destinations(self.cursor.1);
+        Ok(())
+    }
+    /// Add pending outline entries and anchors pointing to the given y position of the current page.
+    fn place_destinations(&mut self, top: f32) {
+        for (level, title) in self.pending_outline.drain(..) {
+            if self.page_info.toc.is_some() {
+                let index = self.doc.get_outline_len();
+                self.doc.add_anchor(&get_toc_anchor(index), top);
+            }
+            self.doc.add_outline(level, &title, top);
+        }
+        for name in self.pending_anchors.drain(..) {
+            self.doc.add_anchor(&name, top);
+        }
+    }
+    /// Draws table of contents entries of the previous pass with dot leaders and
+    /// page numbers, each entry links to its heading.
+    pub fn draw_table_of_contents(
+        &mut self,
+        toc: &TableOfContents,
+        available_width: f32,
+    ) -> Result<(), &'static str> {
+        let font = toc.get_font();
+        let font_size = toc.get_font_size();
+        self.doc.add_font(font);
+        let style = toc.get_style();
+        let leading = style.leading;
+        let frame_bottom = self.get_frame_bottom();
+        let entries = self.page_info.toc.clone().unwrap_or_default();
+        self.set_cursor(self.cursor.0, self.cursor.1 - style.padding.0);
+        for (index, entry) in entries.iter().enumerate() {
+            if !toc.shows_level(entry.level) {
+                continue;
+            }
+            if self.cursor.1 - leading < frame_bottom {
+                self.save_page();
+            }
+            self.set_cursor(self.cursor.0, self.cursor.1 - leading);
+            let left = self.cursor.0 + style.padding.1 + toc.get_indent(entry.level);
+            let right = self.cursor.0 + available_width - style.padding.3;
+            let number = self
+                .template
+                .get_page_numbering()
+                .get_number(entry.page)
+                .map_or_else(String::new, |number| number.to_string());
+            let number_width = font.get_width(font_size, &number);
+            let leader = toc.get_leader();
+            let leader_width = font.get_width(font_size, leader).max(0.1);
+            // keep room for a few leader characters
+            let title = toc.fit_title(
+                &entry.title,
+                right - left - number_width - 3.0 * leader_width,
+            );
+            let title_width = font.get_width(font_size, &title);
+            let gap = right - number_width - left - title_width - leader_width;
+            let leaders = leader.repeat((gap / leader_width).max(0.0) as usize);
+            let leaders_width = font.get_width(font_size, &leaders);
+            self.save_state();
+            self.set_transparency(&style.transparency);
+            self.set_fill_color(&style.color);
+            // same baseline as paragraph text
+            let baseline = self.cursor.1 + 2.0;
+            for (x, text) in &[
+                (left, title.as_str()),
+                (
+                    right - number_width - leader_width / 2.0 - leaders_width,
+                    leaders.as_str(),
+                ),
+                (right - number_width, number.as_str()),
+            ] {
+                let mut stream = Vec::new();
+                write!(
+                    stream,
+                    "BT /{} {} Tf 1 0 0 1 {} {} Tm ",
+                    font.get_ref(),
+                    font_size,
+                    x,
+                    baseline
+                )
+                .unwrap();
+                stream.write_all(&TextSpan::encode_text(text)).unwrap();
+                writeln!(stream, "ET").unwrap();
+                self.output.write_all(&stream).unwrap();
+            }
+            self.restore_state();
+            let annot = Annotation::link(
+                &format!("#{}", get_toc_anchor(index)),
+                left,
+                self.cursor.1,
+                right,
+                self.cursor.1 + leading,
+            );
+            self.annotations.push(annot);
+        }
+        self.set_cursor(self.cursor.0, self.cursor.1 - style.padding.2);
+        Ok(())
+    }
+    /// Outline entries drawn so far, listed in the table of contents of the next pass.
+    pub fn get_toc_entries(&self) -> Vec<TocEntry> {
+        self.doc
+            .get_outline()
+            .into_iter()
+            .map(|(level, title, page)| TocEntry { level, title, page })
+            .collect()
+    }
+    /// Bottom of the frame above the footnotes of the page.
+    fn get_frame_bottom(&self) -> f32 {
+        let (_, fy, _, fheight) = self.template.get_frame().get_rect();
+        fy - fheight + self.notes_height
+    }
+    /// Note bodies referenced on the line, numbered after the notes drawn so far.
+    fn get_line_notes(
+        &self,
+        line: &[TextSpan],
+        paragraph: &Paragraph,
+        frame_width: f32,
+    ) -> Vec<(Paragraph, f32)> {
+        let font_size = paragraph.get_font_size() * NOTE_SCALE;
+        let mut number = self.note_number;
+        let mut notes = Vec::new();
+        for span in line {
+            if let Tag::Footnote { text } = &span.tag {
+                number += 1;
+                let note = Paragraph::note(number, text, paragraph.get_font(), font_size);
+                let (_, height) = note.wrapped_size(&note.wrap_to_width(frame_width));
+                notes.push((note, height));
+            }
+        }
+        notes
+    }
+    /// Copy of the line with the note references numbered after the notes drawn so far.
+    fn number_notes(&mut self, line: &[TextSpan]) -> Vec<TextSpan> {
+        let mut numbered = line.to_vec();
+        for span in numbered.iter_mut() {
+            if let Tag::Footnote { .. } = span.tag {
+                self.note_number += 1;
+                span.text = self.note_number.to_string();
+            }
+        }
+        numbered
+    }
+    /// Space needed at the bottom of the page for the notes, including the separator.
+    fn get_notes_height(&self, notes: &[(Paragraph, f32)]) -> f32 {
+        if notes.is_empty() || self.template.get_notes() == NotesMode::End {
+            return 0.0;
+        }
+        let separator = if self.page_notes.is_empty() {
+            NOTE_SEPARATOR
+        } else {
+            0.0
+        };
+        separator + notes.iter().map(|(_, height)| height).sum::<f32>()
+    }
+    fn add_notes(&mut self, notes: Vec<(Paragraph, f32)>, height: f32) {
+        let notes = notes.into_iter().map(|(note, _)| note);
+        match self.template.get_notes() {
+            NotesMode::Foot => {
+                self.page_notes.extend(notes);
+                self.notes_height += height;
+            }
+            NotesMode::End => self.endnotes.extend(notes),
+        }
+    }
+    /// Draws the footnotes of the page at the bottom of the frame, below a separator rule.
+    fn write_footnotes(&mut self) {
+        if self.page_notes.is_empty() {
+            return;
+        }
+        let notes = std::mem::take(&mut self.page_notes);
+        // outline entries and anchors belong to the content being drawn
+        let pending_outline = std::mem::take(&mut self.pending_outline);
+        let pending_anchors = std::mem::take(&mut self.pending_anchors);
+        let cursor = self.cursor;
+        let (fx, fy, fwidth, fheight) = self.template.get_frame().get_rect();
+        let notes_top = fy - fheight + self.notes_height - NOTE_SEPARATOR;
+        self.notes_height = 0.0;
+        self.save_state();
+        let operator = self.get_color_operator(&Color::new(0.0, 0.0, 0.0), true);
+        writeln!(self.output, "{} 0.5 w", operator).unwrap();
+        let rule_y = notes_top + NOTE_SEPARATOR / 2.0;
+        self.draw_line(Line::new(fx, rule_y, fx + fwidth / 3.0, rule_y));
+        self.restore_state();
+        // small tolerance keeps rounding from moving the last line to the next page
+        self.set_cursor(fx, notes_top + 0.01);
+        for note in &notes {
+            let wrapped = note.wrap_to_width(fwidth);
+            // note bodies have no references, they always fit to the reserved space
+            let _ = self.draw_text(note, &wrapped, fwidth);
+        }
+        self.set_cursor(cursor.0, cursor.1);
+        self.pending_outline = pending_outline;
+        self.pending_anchors = pending_anchors;
+    }
+    /// Draws the endnotes referenced so far in the flow.
+    pub fn draw_endnotes(&mut self, available_width: f32) -> Result<(), &'static str> {
+        let notes = std::mem::take(&mut self.endnotes);
+        for note in &notes {
+            let wrapped = note.wrap_to_width(available_width);
+            self.draw_text(note, &wrapped, available_width)?;
+        }
+        Ok(())
+    }
     /// Draws Paragraph text or wrapped TextSpans
     pub fn draw_text(
         &mut self,
@@ -443,9 +1366,10 @@ impl Canvas {
         self.save_state();
         self.translate(self.cursor.0 + padding_left, self.cursor.1);
         self.save_state();
-        let color = style.color;
-        let link_color = style.link_color;
-        self.set_fill_color(color.r, color.g, color.b);
+        let color = &style.color;
+        let link_color = &style.link_color;
+        self.set_transparency(&style.transparency);
+        self.set_fill_color(color);
         if let Some(bullet) = &style.bullet {
             let mut stream = Vec::new();
             write!(
@@ -463,17 +1387,37 @@ impl Canvas {
         }
         let mut out_text: Vec<u8> = Vec::new();
         let mut next_page_lines: Vec<Vec<TextSpan>> = Vec::new();
-        let (_, fy, _, fheight) = self.template.get_frame().get_rect();
-        let frame_bottom = fy - fheight;
+        let (_, _, frame_width, frame_height) = self.template.get_frame().get_rect();
         let mut break_page = false;
+        let mut lines_drawn = false;
+        // text areas of review comments on this page
+        let mut markups: Vec<(Markup, Vec<[f32; 4]>)> = Vec::new();
         for line in wrapped {
             let mut line_width: f32 = 0.0;
             let mut width_offset: f32 = 0.0;
+            // footnotes are placed on the same page as their references
+            let line_notes = self.get_line_notes(line, paragraph, frame_width);
+            let line_notes_height = self.get_notes_height(&line_notes);
+            if line_notes_height > 0.0 && self.transformed {
+                return Err("Footnotes are not supported in transformed content, use endnotes.");
+            }
+            if line_notes_height > frame_height - leading - padding_top {
+                return Err("Footnote is too large to fit on page.");
+            }
             // check first if we have to write to next page
-            if self.cursor.1 < frame_bottom {
+            if break_page || self.cursor.1 < self.get_frame_bottom() + line_notes_height {
                 break_page = true;
                 next_page_lines.push(line.clone());
             } else {
+                self.add_notes(line_notes, line_notes_height);
+                let line = &self.number_notes(line);
+                if !lines_drawn {
+                    self.place_destinations(self.cursor.1 + leading + padding_top);
+                    // marks are set on every page the paragraph is on
+                    if let Some((name, text)) = paragraph.get_mark() {
+                        self.set_mark(name, text);
+                    }
+                }
                 match style.align {
                     HorizontalAlign::Center => {
                         for span in line {
@@ -491,7 +1435,8 @@ impl Canvas {
                     }
                     _ => (),
                 }
-                let mut _x: f32 = self.cursor.0;
+                let line_start = self.cursor.0 + padding_left + width_offset;
+                let mut _x: f32 = line_start;
                 let mut _y: f32 = self.cursor.1;
 
                 let mut text_color_changed = false;
@@ -501,12 +1446,10 @@ impl Canvas {
                     match &span.tag {
                         Tag::Link { url } => {
                             let annot =
-                                LinkAnnotation::new(&url, _x, _y, _x + span_width, _y + leading);
-                            self.link_annotations.push(annot);
-                            out_text.extend(
-                                format!(" {} {} {} rg ", link_color.r, link_color.g, link_color.b)
-                                    .as_bytes(),
-                            );
+                                Annotation::link(url, _x, _y, _x + span_width, _y + leading);
+                            self.annotations.push(annot);
+                            let operator = self.get_color_operator(link_color, false);
+                            write!(out_text, " {} ", operator).unwrap();
                             text_color_changed = true;
                         }
                         Tag::Bold => {
@@ -522,12 +1465,18 @@ impl Canvas {
                             );
                             font_weight_is_bold = true;
                         }
-                        _ => {
+                        tag => {
+                            if let Tag::Anchor { name } = tag {
+                                self.doc.add_anchor(name, _y + leading);
+                            }
+                            if let Tag::Annotation { markup } = tag {
+                                let area = [_x, _y, _x + span_width, _y + leading];
+                                add_markup_area(&mut markups, markup, area);
+                            }
                             // Change back normal text color.
                             if text_color_changed {
-                                out_text.extend(
-                                    format!(" {} {} {} rg ", color.r, color.g, color.b).as_bytes(),
-                                );
+                                let operator = self.get_color_operator(color, false);
+                                write!(out_text, " {} ", operator).unwrap();
                                 text_color_changed = false;
                             }
                             // Change back to normal font.
@@ -537,17 +1486,37 @@ impl Canvas {
                                 );
                                 font_weight_is_bold = false;
                             }
+                            if matches!(tag, Tag::Footnote { .. } | Tag::Superscript) {
+                                write!(
+                                    out_text,
+                                    " /{} {} Tf {} Ts ",
+                                    font.get_ref(),
+                                    font_size * SUPERSCRIPT_SCALE,
+                                    font_size * SUPERSCRIPT_RISE
+                                )
+                                .unwrap();
+                                out_text.extend(span.encoded_text());
+                                write!(out_text, " 0 Ts /{} {} Tf ", font.get_ref(), font_size)
+                                    .unwrap();
+                                _x += span_width;
+                                continue;
+                            }
                         }
                     }
                     out_text.extend(span.encoded_text());
                     _x += span_width;
                 }
+                if let Some(markup) = paragraph.get_annotation() {
+                    let area = [line_start, _y, _x, _y + leading];
+                    add_markup_area(&mut markups, markup, area);
+                }
                 write!(out_text, " T* ").unwrap();
                 // Reset any offsets after printing a line
                 if width_offset != 0.0 {
                     write!(out_text, " {} 0 Td ", -width_offset).unwrap();
                 }
                 self.set_cursor(self.cursor.0, self.cursor.1 - leading);
+                lines_drawn = true;
             }
         }
         // move up one leading to count for one row of text
@@ -566,6 +1535,15 @@ impl Canvas {
         self.output.write_all(&stream).unwrap();
         self.restore_state();
         self.restore_state();
+        let (fx, _, fwidth, _) = self.template.get_frame().get_rect();
+        let margin = (fx + fwidth, self.template.get_size().0);
+        for (markup, areas) in markups {
+            if markup.kind == AnnotationKind::FreeText {
+                self.doc.add_font(get_free_text_font());
+            }
+            self.annotations
+                .push(Annotation::markup(&markup, areas, margin));
+        }
         // save and start drawing to a new page
         if break_page {
             self.save_page();
@@ -573,23 +1551,377 @@ impl Canvas {
         }
         Ok(())
     }
+    /// Displayed number of the current page, None when the page is not numbered.
+    fn get_page_number(&self) -> Option<i32> {
+        self.template
+            .get_page_numbering()
+            .get_number(self.doc.page_number())
+    }
+    /// Number of the page being drawn, same as the page count when all contents are drawn.
+    pub fn page_number(&self) -> u16 {
+        self.doc.page_number()
+    }
     /// Build and return PDF bytes
-    pub fn build(&mut self) -> Result<Vec<u8>, &'static str> {
+    pub fn build(
+        &mut self,
+        info: &DocumentInfo,
+        page_mode: PageMode,
+        encryption: Option<&Encryption>,
+        signer: Option<&Signer>,
+        attachments: &[EmbeddedFile],
+    ) -> Result<Vec<u8>, &'static str> {
         self.save_page();
-        self.doc.save_document(&self.template)
+        self.doc.save_document(
+            &self.template,
+            info,
+            page_mode,
+            encryption,
+            signer,
+            attachments,
+        )
+    }
+}
+
+/// Add a text area to the review comment, lines of the same comment share one annotation.
+fn add_markup_area(markups: &mut Vec<(Markup, Vec<[f32; 4]>)>, markup: &Markup, area: [f32; 4]) {
+    match markups.iter_mut().rev().find(|(other, _)| other == markup) {
+        Some((_, areas)) => areas.push(area),
+        None => markups.push((markup.clone(), vec![area])),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::json::{JsContent, JsParamValue};
+    use super::super::models::Content;
+    use super::super::styles::ParagraphStyle;
+    use super::super::template::PageFilter;
     use super::super::units::A4;
     use super::*;
 
     #[test]
     fn test_initial_canvas() {
         let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
-        let canvas = Canvas::new(&tpl);
+        let canvas = Canvas::new(&tpl, PageInfo::default());
         let output = "1 0 0 1 0 0 cm  BT /F1 12 Tf 14.4 TL ET\n".as_bytes();
         assert_eq!(canvas._get_test_output(), output);
     }
+
+    #[test]
+    fn test_graphics_states_are_shared_per_page() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let mut transparency = Transparency::new();
+        canvas.set_transparency(&transparency);
+        assert!(canvas.graphics_states.is_empty());
+        transparency.fill_opacity = 0.5;
+        canvas.save_state();
+        canvas.set_transparency(&transparency);
+        canvas.set_transparency(&transparency);
+        canvas.restore_state();
+        canvas.save_state();
+        canvas.set_transparency(&transparency);
+        canvas.restore_state();
+        assert_eq!(canvas.graphics_states.len(), 1);
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.ends_with("q\n/GS1 gs\nQ\nq\n/GS1 gs\nQ\n"));
+        canvas.save_page();
+        assert!(canvas.graphics_states.is_empty());
+    }
+
+    #[test]
+    fn test_color_alpha_sets_opacity() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let translucent = Color::Rgb {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 0.5,
+        };
+        canvas.save_state();
+        canvas.set_fill_color(&translucent);
+        canvas.set_fill_color(&Color::new(0.0, 0.0, 0.0));
+        canvas.restore_state();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.ends_with("q\n/GS1 gs 1 0 0 rg\n/GS2 gs 0 0 0 rg\nQ\n"));
+    }
+
+    #[test]
+    fn test_spot_colors_are_shared_by_name_and_fallback() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let spot = |tint: f32, cmyk: [f32; 4]| Color::Spot {
+            name: String::from("Brand"),
+            tint,
+            cmyk,
+        };
+        canvas.set_fill_color(&spot(1.0, [0.0, 0.5, 1.0, 0.0]));
+        canvas.set_fill_color(&spot(0.5, [0.0, 0.5, 1.0, 0.0]));
+        canvas.set_fill_color(&spot(1.0, [1.0, 0.0, 0.0, 0.0]));
+        assert_eq!(canvas.color_spaces.len(), 2);
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.ends_with(
+            "/CS1 cs 1 scn
+/CS1 cs 0.5 scn
+/CS2 cs 1 scn
+"
+        ));
+    }
+
+    #[test]
+    fn test_stationary_image_is_shared_by_pages() {
+        use super::super::decoders::ImageData;
+        use super::super::styles::ImageStyle;
+        use std::rc::Rc;
+        let data = ImageData {
+            width: 1,
+            height: 1,
+            color_space: String::from("/DeviceGray"),
+            bits_per_component: 8,
+            filter: "/FlateDecode",
+            decode_parms: None,
+            decode: None,
+            data: vec![0],
+            smask: None,
+            orientation: 1,
+            dpi: None,
+        };
+        let image = Image::new(Rc::new(data), 20.0, 10.0, false, false, ImageStyle::new());
+        let mut tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        tpl.add_stationary(
+            Stationary::Image {
+                image: Rc::new(image),
+                x: 10.0,
+                y: 20.0,
+                transform: Transform::new(),
+            },
+            PageFilter::All,
+        );
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        canvas.save_page();
+        assert_eq!(canvas.images, vec![1]);
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("q\n1 0 0 1 10 20 cm\n20 0 0 10 0 0 cm\n/Im1 Do\nQ\n"));
+    }
+
+    #[test]
+    fn test_watermark_behind_and_above_contents() {
+        let mut tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let watermark = |text: &str, above: bool| Stationary::Watermark {
+            text: String::from(text),
+            image: None,
+            font_size: 10.0,
+            font: get_font("helvetica"),
+            color: Color::new(0.5, 0.5, 0.5),
+            transparency: Transparency::new(),
+            transform: Transform::new(),
+            center: None,
+            above,
+        };
+        tpl.add_stationary(watermark("BEHIND", false), PageFilter::All);
+        tpl.add_stationary(watermark("ABOVE", true), PageFilter::All);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(BEHIND) Tj"));
+        assert!(!output.contains("(ABOVE) Tj"));
+        canvas.write_overlay();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.ends_with("(ABOVE) Tj  ET\nQ\nQ\nQ\n"));
+    }
+
+    #[test]
+    fn test_stationary_text_placeholders() {
+        let mut tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        tpl.add_stationary(
+            Stationary::Text {
+                text: String::from("{title}, {date}: {page} / {pages}"),
+                font_size: 10.0,
+                font: get_font("helvetica"),
+                x: 100.0,
+                y: 20.0,
+                color: Color::new(0.0, 0.0, 0.0),
+                align: HorizontalAlign::Right,
+                transparency: Transparency::new(),
+                transform: Transform::new(),
+            },
+            PageFilter::All,
+        );
+        let page_info = PageInfo {
+            title: String::from("Report"),
+            date: String::from("2024-01-31"),
+            total_pages: Some(3),
+            toc: None,
+        };
+        let mut canvas = Canvas::new(&tpl, page_info);
+        canvas.save_page();
+        let text = "Report, 2024-01-31: 2 / 3";
+        let width = get_font("helvetica").get_width(10.0, text);
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains(&format!("BT 1 0 0 1 {} 2 Tm", -width)));
+        assert!(output.contains(&format!("({}) Tj", text)));
+    }
+
+    #[test]
+    fn test_marks_in_stationary_text() {
+        let mut tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let header = Stationary::Text {
+            text: String::from("{mark:chapter:first}|{mark:chapter:last}"),
+            font_size: 10.0,
+            font: get_font("helvetica"),
+            x: 50.0,
+            y: 800.0,
+            color: Color::new(0.0, 0.0, 0.0),
+            align: HorizontalAlign::Left,
+            transparency: Transparency::new(),
+            transform: Transform::new(),
+        };
+        tpl.add_stationary(header, PageFilter::All);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        // header is drawn at the end of the page, when marks are known
+        assert!(!String::from_utf8(canvas._get_test_output())
+            .unwrap()
+            .contains("Tj"));
+        canvas.set_mark("chapter", "One");
+        canvas.set_mark("chapter", "Two");
+        canvas.write_overlay();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(One|Two) Tj"));
+        canvas.save_page();
+        canvas.set_mark("chapter", "Three");
+        canvas.write_overlay();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(Three|Three) Tj"));
+        canvas.save_page();
+        canvas.write_overlay();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(Three|Three) Tj"));
+    }
+
+    #[test]
+    fn test_marks_of_paragraph_across_page_break() {
+        let mut tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let header = Stationary::Text {
+            text: String::from("{mark:chapter:first}|{mark:chapter:last}"),
+            font_size: 10.0,
+            font: get_font("helvetica"),
+            x: 50.0,
+            y: 800.0,
+            color: Color::new(0.0, 0.0, 0.0),
+            align: HorizontalAlign::Left,
+            transparency: Transparency::new(),
+            transform: Transform::new(),
+        };
+        tpl.add_stationary(header, PageFilter::All);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let text = "Chapter text. ".repeat(300);
+        let content: JsContent = serde_json::from_str(&format!(
+            r#"{{"obj_type": "paragraph", "params": {{"text": "{}", "mark": "chapter", "mark_text": "One"}}}}"#,
+            text
+        ))
+        .unwrap();
+        let paragraph = Paragraph::from_content(&content).unwrap();
+        canvas.set_cursor(50.0, 80.0);
+        canvas.set_mark("chapter", "Intro");
+        paragraph.draw(&mut canvas, 495.28).unwrap();
+        assert_eq!(canvas.page_number(), 2);
+        // the first page ends with the paragraph
+        assert_eq!(canvas.marks.get("chapter").map(String::as_str), Some("One"));
+        canvas.write_overlay();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(One|One) Tj"));
+    }
+
+    #[test]
+    fn test_table_of_contents() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let page_info = PageInfo {
+            toc: Some(vec![
+                TocEntry {
+                    level: 1,
+                    title: String::from("Introduction"),
+                    page: 1,
+                },
+                TocEntry {
+                    level: 2,
+                    title: String::from("Details"),
+                    page: 12,
+                },
+            ]),
+            ..PageInfo::default()
+        };
+        let mut params = HashMap::new();
+        params.insert(String::from("indent"), JsParamValue::Number(20.0));
+        let content = JsContent {
+            obj_type: String::from("TableOfContents"),
+            params,
+        };
+        let toc = TableOfContents::from_content(&content).unwrap();
+        let mut canvas = Canvas::new(&tpl, page_info.clone());
+        canvas.draw_table_of_contents(&toc, 495.28).unwrap();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("1 0 0 1 50 773.8898 Tm (Introduction) Tj"));
+        assert!(output.contains("1 0 0 1 70 759.8898 Tm (Details) Tj"));
+        assert!(output.contains("(12) Tj"));
+        assert!(output.contains("(....."));
+        assert_eq!(canvas.annotations.len(), 2);
+        // deeper levels can be left out
+        let mut content = content;
+        content
+            .params
+            .insert(String::from("levels"), JsParamValue::Number(1.0));
+        let toc = TableOfContents::from_content(&content).unwrap();
+        let mut canvas = Canvas::new(&tpl, page_info);
+        canvas.draw_table_of_contents(&toc, 495.28).unwrap();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(!output.contains("Details"));
+        assert_eq!(canvas.annotations.len(), 1);
+    }
+
+    #[test]
+    fn test_footnotes_at_page_bottom() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        let style = ParagraphStyle::new(14.0, HorizontalAlign::Left, (0.0, 0.0, 0.0, 0.0));
+        let paragraph = Paragraph::new(
+            "Text<footnote>First note</footnote> and<footnote>Second note</footnote>.",
+            "helvetica",
+            12.0,
+            style,
+        );
+        paragraph.draw(&mut canvas, 495.28).unwrap();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("(Text) Tj  /F1 7.2000003 Tf 4.2 Ts (1) Tj  0 Ts /F1 12 Tf"));
+        assert!(output.contains("(2) Tj"));
+        // two note lines and the separator are reserved from the frame
+        assert_eq!(canvas.notes_height, 2.0 * 11.6 + NOTE_SEPARATOR);
+        assert_eq!(canvas.get_frame_bottom(), 50.0 + canvas.notes_height);
+        canvas.write_footnotes();
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains("n 50 77.2 m 215.09187 77.2 l S"));
+        assert!(output.contains("(First note) Tj"));
+        assert!(output.contains("(Second note) Tj"));
+        assert_eq!(canvas.notes_height, 0.0);
+    }
+
+    #[test]
+    fn test_footnote_references_are_measured_with_their_number() {
+        let tpl = PageTemplate::new(A4, 50.0, 50.0, 50.0, 50.0);
+        let mut canvas = Canvas::new(&tpl, PageInfo::default());
+        canvas.note_number = 9;
+        let style = ParagraphStyle::new(14.0, HorizontalAlign::Right, (0.0, 0.0, 0.0, 0.0));
+        let paragraph = Paragraph::new(
+            "Text<footnote>Tenth note</footnote>",
+            "helvetica",
+            12.0,
+            style,
+        );
+        paragraph.draw(&mut canvas, 495.28).unwrap();
+        let font = get_font("helvetica");
+        let width = font.get_width(12.0, "Text") + font.get_width(12.0 * SUPERSCRIPT_SCALE, "10");
+        let output = String::from_utf8(canvas._get_test_output()).unwrap();
+        assert!(output.contains(&format!(" {} 0 Td (Text) Tj", 495.28 - width)));
+        assert!(output.contains("(10) Tj"));
+        assert_eq!(canvas.note_number, 10);
+    }
 }
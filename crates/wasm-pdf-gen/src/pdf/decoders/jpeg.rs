@@ -0,0 +1,256 @@
+use super::ImageData;
+
+/// JPEG header information needed for embedding the image as is.
+#[derive(Debug, PartialEq)]
+struct Header {
+    width: u32,
+    height: u32,
+    components: u8,
+    /// Adobe APP14 marker is present, CMYK data is stored inverted
+    adobe: bool,
+    /// EXIF orientation, 1 when not given
+    orientation: u8,
+    /// JFIF pixel density in dots per inch
+    dpi: Option<(f32, f32)>,
+}
+
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8])
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from(data[pos]) << 8 | u16::from(data[pos + 1])
+}
+
+/// Walk through the marker segments up to the frame header (any SOFn marker).
+fn read_header(data: &[u8]) -> Result<Header, String> {
+    if !is_jpeg(data) {
+        return Err("Invalid JPEG format.".to_string());
+    }
+    let mut adobe = false;
+    let mut orientation = 1;
+    let mut dpi = None;
+    let mut pos = 2;
+    loop {
+        // markers may be preceded by any number of fill bytes
+        while pos < data.len() && data[pos] == 0xFF {
+            pos += 1;
+        }
+        if pos >= data.len() || data[pos - 1] != 0xFF {
+            return Err("Invalid JPEG: no frame header found.".to_string());
+        }
+        let marker = data[pos];
+        pos += 1;
+        // standalone markers without a length
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+        if marker == 0xD9 || marker == 0xDA || pos + 2 > data.len() {
+            return Err("Invalid JPEG: no frame header found.".to_string());
+        }
+        let length = read_u16(data, pos) as usize;
+        if length < 2 || pos + length > data.len() {
+            return Err("Invalid JPEG: truncated segment.".to_string());
+        }
+        let segment = &data[pos + 2..pos + length];
+        match marker {
+            // SOF0-SOF15 except DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                if segment.len() < 6 {
+                    return Err("Invalid JPEG: truncated frame header.".to_string());
+                }
+                if segment[0] != 8 {
+                    return Err(format!("{}-bit JPEG images are not supported.", segment[0]));
+                }
+                let components = segment[5];
+                if components != 1 && components != 3 && components != 4 {
+                    return Err(format!(
+                        "JPEG images with {} components are not supported.",
+                        components
+                    ));
+                }
+                let (width, height) = (read_u16(segment, 3), read_u16(segment, 1));
+                // height 0 is defined later by a DNL marker, which isn't supported
+                if width == 0 || height == 0 {
+                    return Err("Invalid JPEG: image size is zero.".to_string());
+                }
+                return Ok(Header {
+                    width: u32::from(width),
+                    height: u32::from(height),
+                    components,
+                    adobe,
+                    orientation,
+                    dpi,
+                });
+            }
+            0xE0 if segment.starts_with(b"JFIF\0") && segment.len() >= 12 => {
+                dpi = read_jfif_density(&segment[7..12]);
+            }
+            0xE1 if segment.starts_with(b"Exif\0\0") => {
+                orientation = read_exif_orientation(&segment[6..]).unwrap_or(1);
+            }
+            0xEE if segment.starts_with(b"Adobe") => adobe = true,
+            _ => (),
+        }
+        pos += length;
+    }
+}
+
+/// Density units (1 dots per inch, 2 dots per cm) followed by x and y density.
+fn read_jfif_density(density: &[u8]) -> Option<(f32, f32)> {
+    let (x, y) = (
+        f32::from(read_u16(density, 1)),
+        f32::from(read_u16(density, 3)),
+    );
+    if x == 0.0 || y == 0.0 {
+        return None;
+    }
+    match density[0] {
+        1 => Some((x, y)),
+        2 => Some((x * 2.54, y * 2.54)),
+        _ => None,
+    }
+}
+
+/// Orientation tag (0x0112) of the first IFD in EXIF (TIFF) data.
+fn read_exif_orientation(tiff: &[u8]) -> Option<u8> {
+    let big_endian = match tiff.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = tiff.get(pos..pos.checked_add(2)?)?;
+        Some(if big_endian {
+            u16::from(bytes[0]) << 8 | u16::from(bytes[1])
+        } else {
+            u16::from(bytes[1]) << 8 | u16::from(bytes[0])
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let second = read_u16(pos.checked_add(2)?)?;
+        let (first, second) = (u32::from(read_u16(pos)?), u32::from(second));
+        Some(if big_endian {
+            first << 16 | second
+        } else {
+            second << 16 | first
+        })
+    };
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    for index in 0..entries {
+        // offsets out of range leave the orientation out
+        let entry = index.checked_mul(12)?.checked_add(ifd)?.checked_add(2)?;
+        if read_u16(entry)? == 0x0112 {
+            let orientation = read_u16(entry.checked_add(8)?)?;
+            return if (1..=8).contains(&orientation) {
+                Some(orientation as u8)
+            } else {
+                None
+            };
+        }
+    }
+    None
+}
+
+/// Width and height of the image as displayed, EXIF orientation applied.
+pub fn read_size(data: &[u8]) -> Result<(u32, u32), String> {
+    let header = read_header(data)?;
+    if header.orientation >= 5 {
+        Ok((header.height, header.width))
+    } else {
+        Ok((header.width, header.height))
+    }
+}
+
+/// JPEG data is embedded as is with DCTDecode, the header gives size and colour space.
+pub fn decode(data: &[u8]) -> Result<ImageData, String> {
+    let header = read_header(data)?;
+    let color_space = match header.components {
+        1 => "/DeviceGray",
+        4 => "/DeviceCMYK",
+        _ => "/DeviceRGB",
+    };
+    // Photoshop writes CMYK JPEGs inverted
+    let decode = if header.components == 4 && header.adobe {
+        Some(String::from("[1 0 1 0 1 0 1 0]"))
+    } else {
+        None
+    };
+    Ok(ImageData {
+        width: header.width,
+        height: header.height,
+        color_space: String::from(color_space),
+        bits_per_component: 8,
+        filter: "/DCTDecode",
+        decode_parms: None,
+        decode,
+        data: data.to_vec(),
+        smask: None,
+        orientation: header.orientation,
+        dpi: header.dpi,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, marker];
+        data.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn frame(marker: u8, components: u8) -> Vec<u8> {
+        // precision 8, height 20, width 30
+        segment(marker, &[8, 0, 20, 0, 30, components])
+    }
+
+    #[test]
+    fn test_progressive_cmyk_with_exif() {
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(segment(0xE1, &exif));
+        data.extend(segment(0xEE, b"Adobe\0\x64\0\0\0\0\x02"));
+        data.extend(frame(0xC2, 4));
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (30, 20));
+        assert_eq!(image.color_space, "/DeviceCMYK");
+        assert_eq!(image.decode.as_deref(), Some("[1 0 1 0 1 0 1 0]"));
+        assert_eq!(image.orientation, 6);
+        assert_eq!(read_size(&data), Ok((20, 30)));
+    }
+
+    #[test]
+    fn test_grayscale_and_invalid_jpeg() {
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(segment(0xE0, b"JFIF\0\x01\x02\x01\x01\x2c\x01\x2c\0\0"));
+        data.extend(segment(0xC4, &[0, 0, 0]));
+        data.extend(frame(0xC1, 1));
+        let image = decode(&data).unwrap();
+        assert_eq!(image.color_space, "/DeviceGray");
+        assert_eq!(image.dpi, Some((300.0, 300.0)));
+        assert!(decode(&data[..24]).is_err());
+        assert!(decode(b"GIF89a").is_err());
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(segment(0xC0, &[8, 0, 20, 0, 0, 3]));
+        assert_eq!(
+            decode(&data),
+            Err("Invalid JPEG: image size is zero.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exif_offsets_out_of_range() {
+        // first IFD far past the data
+        assert_eq!(read_exif_orientation(b"MM\0\x2a\xff\xff\xff\xff"), None);
+        // more entries than the data holds
+        assert_eq!(read_exif_orientation(b"II\x2a\0\x08\0\0\0\xff\xff"), None);
+        let mut tiff = b"II\x2a\0\x08\0\0\0\x01\0".to_vec();
+        tiff.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        assert_eq!(read_exif_orientation(&tiff), Some(6));
+    }
+}
@@ -0,0 +1,83 @@
+pub mod jpeg;
+pub mod png;
+
+/// Image data in the form written to an image XObject.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    /// Colour space name or array, e.g. "/DeviceRGB" or "[/Indexed /DeviceRGB 1 <000000ffffff>]"
+    pub color_space: String,
+    pub bits_per_component: u8,
+    /// Stream filter, "/DCTDecode" or "/FlateDecode"
+    pub filter: &'static str,
+    /// Filter parameters, e.g. PNG predictor settings
+    pub decode_parms: Option<String>,
+    /// Decode array mapping samples to colour components, e.g. for inverted CMYK
+    pub decode: Option<String>,
+    pub data: Vec<u8>,
+    /// Soft mask (alpha channel) as a grayscale image
+    pub smask: Option<Box<ImageData>>,
+    /// EXIF orientation (1-8), 1 is upright
+    pub orientation: u8,
+    /// Horizontal and vertical resolution in dots per inch, if given in the file
+    pub dpi: Option<(f32, f32)>,
+}
+
+impl ImageData {
+    /// Width and height as displayed, sides are swapped for rotated orientations.
+    pub fn get_display_size(&self) -> (u32, u32) {
+        if self.orientation >= 5 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+    /// Width and height in points as displayed, 72 dpi is assumed when resolution is not given.
+    pub fn get_natural_size(&self) -> (f32, f32) {
+        let (width, height) = self.get_display_size();
+        let (dpi_x, dpi_y) = self.get_display_dpi();
+        (width as f32 * 72.0 / dpi_x, height as f32 * 72.0 / dpi_y)
+    }
+    /// Resolution with sides swapped for rotated orientations, like get_display_size.
+    pub fn get_display_dpi(&self) -> (f32, f32) {
+        match self.dpi {
+            Some((dpi_x, dpi_y)) if self.orientation >= 5 => (dpi_y, dpi_x),
+            Some(dpi) => dpi,
+            None => (72.0, 72.0),
+        }
+    }
+    /// Image matrix for drawing the image upright into a width x height box.
+    pub fn get_matrix(&self, width: f32, height: f32) -> [f32; 6] {
+        match self.orientation {
+            2 => [-width, 0.0, 0.0, height, width, 0.0],
+            3 => [-width, 0.0, 0.0, -height, width, height],
+            4 => [width, 0.0, 0.0, -height, 0.0, height],
+            5 => [0.0, -height, -width, 0.0, width, height],
+            6 => [0.0, -height, width, 0.0, 0.0, height],
+            7 => [0.0, height, width, 0.0, 0.0, 0.0],
+            8 => [0.0, height, -width, 0.0, width, 0.0],
+            _ => [width, 0.0, 0.0, height, 0.0, 0.0],
+        }
+    }
+}
+
+/// Decode image bytes, format is recognised from the file signature.
+pub fn decode(data: &[u8]) -> Result<ImageData, String> {
+    if png::is_png(data) {
+        png::decode(data)
+    } else if jpeg::is_jpeg(data) {
+        jpeg::decode(data)
+    } else {
+        Err("Unsupported image format, only JPEG and PNG images are supported.".to_string())
+    }
+}
+
+/// Width and height of image as displayed.
+pub fn read_size(data: &[u8]) -> Result<(u32, u32), String> {
+    if png::is_png(data) {
+        png::read_size(data)
+    } else {
+        jpeg::read_size(data)
+    }
+}
@@ -0,0 +1,582 @@
+use super::super::encoders;
+use super::ImageData;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Adam7 passes as (x offset, y offset, x step, y step)
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+pub fn is_png(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header, String> {
+        if data.len() != 13 {
+            return Err("Invalid PNG header.".to_string());
+        }
+        let header = Header {
+            width: read_u32(&data[0..4]),
+            height: read_u32(&data[4..8]),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: data[12] == 1,
+        };
+        let valid_depths: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(format!("Invalid PNG colour type {}.", header.color_type)),
+        };
+        if !valid_depths.contains(&header.bit_depth) {
+            return Err(format!(
+                "Invalid PNG bit depth {} for colour type {}.",
+                header.bit_depth, header.color_type
+            ));
+        }
+        if header.width == 0 || header.height == 0 || data[10] != 0 || data[11] != 0 || data[12] > 1
+        {
+            return Err("Invalid PNG header.".to_string());
+        }
+        Ok(header)
+    }
+    /// Samples per pixel
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+    /// Bytes per complete pixel, used by the filters (at least 1)
+    fn filter_bpp(&self) -> usize {
+        (self.channels() * self.bit_depth as usize)
+            .div_ceil(8)
+            .max(1)
+    }
+    fn row_bytes(&self, width: usize) -> Option<usize> {
+        let bits = width
+            .checked_mul(self.channels())?
+            .checked_mul(self.bit_depth as usize)?;
+        Some(bits.div_ceil(8))
+    }
+    /// Passes of the image data as (x offset, y offset, x step, y step)
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlaced {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        }
+    }
+    /// Length of the inflated image data, filter type bytes included,
+    /// None if it doesn't fit in memory.
+    fn data_size(&self) -> Option<usize> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut size: usize = 0;
+        for (x0, y0, dx, dy) in self.passes() {
+            if *x0 >= width || *y0 >= height {
+                continue;
+            }
+            let pass_width = (width - x0).div_ceil(*dx);
+            let pass_height = (height - y0).div_ceil(*dy);
+            let row_bytes = self.row_bytes(pass_width)?.checked_add(1)?;
+            size = size.checked_add(pass_height.checked_mul(row_bytes)?)?;
+        }
+        Some(size)
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from(bytes[0]) << 24
+        | u32::from(bytes[1]) << 16
+        | u32::from(bytes[2]) << 8
+        | u32::from(bytes[3])
+}
+
+/// Width and height of a PNG image from the IHDR chunk.
+pub fn read_size(data: &[u8]) -> Result<(u32, u32), String> {
+    if !is_png(data) || data.len() < 24 || &data[12..16] != b"IHDR" {
+        return Err("Invalid PNG format.".to_string());
+    }
+    Ok((read_u32(&data[16..20]), read_u32(&data[20..24])))
+}
+
+/// Decode PNG into image XObject data. Non-interlaced images without transparency
+/// are embedded as is, since PNG image data is valid FlateDecode with PNG predictors.
+/// Other images are decoded to 8 bits per component and transparency is moved to a soft mask.
+pub fn decode(data: &[u8]) -> Result<ImageData, String> {
+    if !is_png(data) {
+        return Err("Invalid PNG format.".to_string());
+    }
+    let mut header: Option<Header> = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: Option<&[u8]> = None;
+    let mut image_data: Vec<u8> = Vec::new();
+    let mut dpi: Option<(f32, f32)> = None;
+    let mut pos = SIGNATURE.len();
+    while pos + 8 <= data.len() {
+        let length = read_u32(&data[pos..pos + 4]) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let start = pos + 8;
+        // chunk data and crc
+        let end = match start.checked_add(length) {
+            Some(end) if end < data.len() && data.len() - end >= 4 => end,
+            _ => return Err("Invalid PNG: truncated chunk.".to_string()),
+        };
+        let chunk = &data[start..end];
+        match chunk_type {
+            b"IHDR" => header = Some(Header::parse(chunk)?),
+            b"PLTE" => palette = chunk,
+            b"tRNS" => transparency = Some(chunk),
+            b"IDAT" => image_data.extend_from_slice(chunk),
+            // pixels per metre, unit 0 only gives the aspect ratio
+            b"pHYs" if chunk.len() == 9 && chunk[8] == 1 => {
+                let (x, y) = (read_u32(&chunk[0..4]), read_u32(&chunk[4..8]));
+                if x > 0 && y > 0 {
+                    dpi = Some((x as f32 * 0.0254, y as f32 * 0.0254));
+                }
+            }
+            b"IEND" => break,
+            _ => (),
+        }
+        pos = end + 4; // skip crc
+    }
+    let header = header.ok_or("Invalid PNG: missing IHDR chunk.")?;
+    if image_data.is_empty() {
+        return Err("Invalid PNG: missing image data.".to_string());
+    }
+    let color_space = match header.color_type {
+        0 | 4 => String::from("/DeviceGray"),
+        2 | 6 => String::from("/DeviceRGB"),
+        _ => {
+            // palette of 1 to 256 RGB entries
+            let entries = palette.len() / 3;
+            if entries == 0 || entries > 256 || entries * 3 != palette.len() {
+                return Err("Invalid PNG: missing palette.".to_string());
+            }
+            let hex: String = palette.iter().map(|b| format!("{:02x}", b)).collect();
+            format!("[/Indexed /DeviceRGB {} <{}>]", entries - 1, hex)
+        }
+    };
+    let has_alpha = header.color_type == 4 || header.color_type == 6 || transparency.is_some();
+    if !header.interlaced && !has_alpha && header.bit_depth <= 8 {
+        return Ok(ImageData {
+            width: header.width,
+            height: header.height,
+            color_space,
+            bits_per_component: header.bit_depth,
+            filter: "/FlateDecode",
+            decode_parms: Some(predictor_parms(
+                header.channels(),
+                header.bit_depth,
+                header.width,
+            )),
+            data: image_data,
+            decode: None,
+            smask: None,
+            orientation: 1,
+            dpi,
+        });
+    }
+    let inflated = inflate::inflate_bytes_zlib(&image_data)
+        .map_err(|err| format!("Invalid PNG image data: {}", err))?;
+    let samples = read_samples(&header, &inflated)?;
+    let (color, alpha) = split_alpha(&header, &samples, transparency);
+    let colors = if header.color_type == 2 || header.color_type == 6 {
+        3
+    } else {
+        1
+    };
+    let width = header.width as usize;
+    let smask = alpha.map(|alpha| {
+        Box::new(ImageData {
+            width: header.width,
+            height: header.height,
+            color_space: String::from("/DeviceGray"),
+            bits_per_component: 8,
+            filter: "/FlateDecode",
+            decode_parms: Some(predictor_parms(1, 8, header.width)),
+            data: encode_rows(&alpha, width, 1),
+            decode: None,
+            smask: None,
+            orientation: 1,
+            dpi: None,
+        })
+    });
+    Ok(ImageData {
+        width: header.width,
+        height: header.height,
+        color_space,
+        bits_per_component: 8,
+        filter: "/FlateDecode",
+        decode_parms: Some(predictor_parms(colors, 8, header.width)),
+        data: encode_rows(&color, width * colors, colors),
+        decode: None,
+        smask,
+        orientation: 1,
+        dpi,
+    })
+}
+
+fn predictor_parms(colors: usize, bits_per_component: u8, columns: u32) -> String {
+    format!(
+        "/Predictor 15 /Colors {} /BitsPerComponent {} /Columns {}",
+        colors, bits_per_component, columns
+    )
+}
+
+/// Unfilter (and deinterlace) image data to samples, one value per channel.
+/// The header size is checked against the data before samples are allocated.
+fn read_samples(header: &Header, data: &[u8]) -> Result<Vec<u16>, String> {
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let channels = header.channels();
+    let too_large = || "Invalid PNG: image is too large.".to_string();
+    let data_size = header.data_size().ok_or_else(too_large)?;
+    if data.len() < data_size {
+        return Err("Invalid PNG: image data is too short.".to_string());
+    }
+    let sample_count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(too_large)?;
+    let mut samples = vec![0u16; sample_count];
+    let mut offset = 0;
+    for (x0, y0, dx, dy) in header.passes() {
+        if *x0 >= width || *y0 >= height {
+            continue;
+        }
+        let pass_width = (width - x0).div_ceil(*dx);
+        let pass_height = (height - y0).div_ceil(*dy);
+        // within data_size
+        let row_bytes = header.row_bytes(pass_width).ok_or_else(too_large)?;
+        let end = offset + pass_height * (row_bytes + 1);
+        let rows = unfilter(&data[offset..end], row_bytes, header.filter_bpp())?;
+        offset = end;
+        for (pass_y, row) in rows.chunks(row_bytes).enumerate() {
+            let y = y0 + pass_y * dy;
+            for pass_x in 0..pass_width {
+                let x = x0 + pass_x * dx;
+                for channel in 0..channels {
+                    samples[(y * width + x) * channels + channel] =
+                        read_sample(row, pass_x * channels + channel, header.bit_depth);
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from(row[index * 2]) << 8 | u16::from(row[index * 2 + 1]),
+        8 => u16::from(row[index]),
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            u16::from(row[bit / 8] >> shift) & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+/// Reverse PNG row filters, input rows start with the filter type byte.
+fn unfilter(data: &[u8], row_bytes: usize, bpp: usize) -> Result<Vec<u8>, String> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len());
+    let mut previous = vec![0u8; row_bytes];
+    for line in data.chunks(row_bytes + 1) {
+        let filter = line[0];
+        let mut row = line[1..].to_vec();
+        for i in 0..row_bytes {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(format!("Invalid PNG filter type {}.", filter)),
+            };
+            row[i] = row[i].wrapping_add(predicted);
+        }
+        output.extend_from_slice(&row);
+        previous = row;
+    }
+    Ok(output)
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let p = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let pa = (p - i16::from(left)).abs();
+    let pb = (p - i16::from(up)).abs();
+    let pc = (p - i16::from(up_left)).abs();
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Convert samples to 8 bit colour components and alpha. Alpha comes from the
+/// alpha channel or the tRNS chunk and is left out when the image is fully opaque.
+fn split_alpha(
+    header: &Header,
+    samples: &[u16],
+    transparency: Option<&[u8]>,
+) -> (Vec<u8>, Option<Vec<u8>>) {
+    let channels = header.channels();
+    let to_byte = |value: u16| match header.bit_depth {
+        16 => (value >> 8) as u8,
+        8 => value as u8,
+        depth => (u32::from(value) * 255 / ((1 << depth) - 1)) as u8,
+    };
+    let color_channels = if header.color_type == 4 || header.color_type == 6 {
+        channels - 1
+    } else {
+        channels
+    };
+    // tRNS holds alpha per palette index or a single transparent colour key
+    let key: Vec<u16> = match (header.color_type, transparency) {
+        (0, Some(trns)) | (2, Some(trns)) if trns.len() >= color_channels * 2 => trns
+            .chunks(2)
+            .take(color_channels)
+            .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1]))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut color: Vec<u8> = Vec::with_capacity(samples.len());
+    let mut alpha: Vec<u8> = Vec::with_capacity(samples.len() / channels);
+    for pixel in samples.chunks(channels) {
+        let components = &pixel[..color_channels];
+        if header.color_type == 3 {
+            // palette indices are kept as they are
+            color.push(components[0] as u8);
+        } else {
+            color.extend(components.iter().map(|value| to_byte(*value)));
+        }
+        let pixel_alpha = match header.color_type {
+            4 | 6 => to_byte(pixel[channels - 1]),
+            3 => transparency
+                .and_then(|trns| trns.get(components[0] as usize))
+                .copied()
+                .unwrap_or(255),
+            _ if !key.is_empty() && components == key.as_slice() => 0,
+            _ => 255,
+        };
+        alpha.push(pixel_alpha);
+    }
+    if alpha.iter().all(|value| *value == 255) {
+        (color, None)
+    } else {
+        (color, Some(alpha))
+    }
+}
+
+/// Filter rows for the PNG predictor (choosing the filter with the smallest
+/// sum of absolute differences for each row) and compress them.
+fn encode_rows(data: &[u8], row_bytes: usize, bpp: usize) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(data.len() + data.len() / row_bytes);
+    let empty = vec![0u8; row_bytes];
+    let mut previous: &[u8] = &empty;
+    for row in data.chunks(row_bytes) {
+        let mut best: (u64, u8, Vec<u8>) = (u64::MAX, 0, Vec::new());
+        for filter in 0..5u8 {
+            let filtered: Vec<u8> = (0..row_bytes)
+                .map(|i| {
+                    let left = if i >= bpp { row[i - bpp] } else { 0 };
+                    let up = previous[i];
+                    let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+                    let predicted = match filter {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                        _ => paeth(left, up, up_left),
+                    };
+                    row[i].wrapping_sub(predicted)
+                })
+                .collect();
+            let cost: u64 = filtered
+                .iter()
+                .map(|value| u64::from((*value as i8).unsigned_abs()))
+                .sum();
+            if cost < best.0 {
+                best = (cost, filter, filtered);
+            }
+        }
+        output.push(best.1);
+        output.extend_from_slice(&best.2);
+        previous = row;
+    }
+    encoders::zlib::encode(&output).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a PNG file from raw chunks
+    fn build_png(chunks: &[(&[u8], Vec<u8>)]) -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+        for (chunk_type, chunk) in chunks {
+            data.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            data.extend_from_slice(chunk_type);
+            data.extend_from_slice(chunk);
+            data.extend_from_slice(&[0, 0, 0, 0]); // crc is not checked
+        }
+        data
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut data = width.to_be_bytes().to_vec();
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        data
+    }
+
+    #[test]
+    fn test_png_without_alpha_is_passed_through() {
+        // 2x1 grayscale, filter type 0
+        let idat = encoders::zlib::encode(&[0, 10, 200]).unwrap();
+        let png = build_png(&[
+            (b"IHDR", ihdr(2, 1, 8, 0, 0)),
+            // 11811 pixels per metre is 300 dpi
+            (b"pHYs", vec![0, 0, 0x2E, 0x23, 0, 0, 0x2E, 0x23, 1]),
+            (b"IDAT", idat.clone()),
+            (b"IEND", Vec::new()),
+        ]);
+        assert_eq!(read_size(&png), Ok((2, 1)));
+        let image = decode(&png).unwrap();
+        assert_eq!(image.color_space, "/DeviceGray");
+        assert_eq!(image.data, idat);
+        assert!(image.smask.is_none());
+        let (dpi_x, dpi_y) = image.dpi.unwrap();
+        assert!((dpi_x - 300.0).abs() < 0.01 && (dpi_y - 300.0).abs() < 0.01);
+        assert_eq!(
+            image.decode_parms.unwrap(),
+            "/Predictor 15 /Colors 1 /BitsPerComponent 8 /Columns 2"
+        );
+    }
+
+    #[test]
+    fn test_png_alpha_to_smask() {
+        // 2x2 RGBA, Sub filter on the second row
+        let raw = [
+            0, 255, 0, 0, 255, 0, 255, 0, 128, //
+            1, 0, 0, 255, 0, 0, 0, 0, 255,
+        ];
+        let png = build_png(&[
+            (b"IHDR", ihdr(2, 2, 8, 6, 0)),
+            (b"IDAT", encoders::zlib::encode(&raw).unwrap()),
+            (b"IEND", Vec::new()),
+        ]);
+        let image = decode(&png).unwrap();
+        assert_eq!(image.color_space, "/DeviceRGB");
+        let smask = image.smask.unwrap();
+        let alpha = inflate::inflate_bytes_zlib(&smask.data).unwrap();
+        let alpha = unfilter(&alpha, 2, 1).unwrap();
+        assert_eq!(alpha, vec![255, 128, 0, 255]);
+        let color = inflate::inflate_bytes_zlib(&image.data).unwrap();
+        let color = unfilter(&color, 6, 3).unwrap();
+        assert_eq!(color, vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_png_interlaced_palette() {
+        // 3x3 palette image with 2 bit indices, index = x, first entry transparent
+        let mut raw: Vec<u8> = Vec::new();
+        for (x0, y0, dx, dy) in ADAM7.iter() {
+            if *x0 >= 3 || *y0 >= 3 {
+                continue;
+            }
+            let pass_width = (3 - x0).div_ceil(*dx);
+            let pass_height = (3 - y0).div_ceil(*dy);
+            for _ in 0..pass_height {
+                let mut byte = 0u8;
+                for i in 0..pass_width {
+                    byte |= ((x0 + i * dx) as u8) << (6 - 2 * i);
+                }
+                raw.extend_from_slice(&[0, byte]);
+            }
+        }
+        let png = build_png(&[
+            (b"IHDR", ihdr(3, 3, 2, 3, 1)),
+            (b"PLTE", vec![0, 0, 0, 255, 0, 0, 0, 255, 0]),
+            (b"tRNS", vec![0]),
+            (b"IDAT", encoders::zlib::encode(&raw).unwrap()),
+            (b"IEND", Vec::new()),
+        ]);
+        let image = decode(&png).unwrap();
+        assert_eq!(
+            image.color_space,
+            "[/Indexed /DeviceRGB 2 <000000ff000000ff00>]"
+        );
+        let indices = inflate::inflate_bytes_zlib(&image.data).unwrap();
+        assert_eq!(
+            unfilter(&indices, 3, 1).unwrap(),
+            vec![0, 1, 2, 0, 1, 2, 0, 1, 2]
+        );
+        let alpha = inflate::inflate_bytes_zlib(&image.smask.unwrap().data).unwrap();
+        assert_eq!(
+            unfilter(&alpha, 3, 1).unwrap(),
+            vec![0, 255, 255, 0, 255, 255, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_invalid_png() {
+        let png = build_png(&[(b"IHDR", ihdr(1, 1, 3, 2, 0))]);
+        assert_eq!(
+            decode(&png),
+            Err("Invalid PNG bit depth 3 for colour type 2.".to_string())
+        );
+        assert!(decode(b"not a png").is_err());
+        // header size doesn't match the image data
+        let png = build_png(&[
+            (b"IHDR", ihdr(0x7FFF_FFFF, 0x7FFF_FFFF, 16, 6, 1)),
+            (b"IDAT", encoders::zlib::encode(&[0, 0, 0]).unwrap()),
+            (b"IEND", Vec::new()),
+        ]);
+        assert!(decode(&png).is_err());
+        let mut png = build_png(&[(b"IHDR", ihdr(1, 1, 8, 0, 0))]);
+        png.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xF8]);
+        png.extend_from_slice(b"IDAT");
+        assert_eq!(
+            decode(&png),
+            Err("Invalid PNG: truncated chunk.".to_string())
+        );
+        // palettes have whole RGB entries
+        for palette in &[vec![0; 4], vec![0; 257 * 3]] {
+            let png = build_png(&[
+                (b"IHDR", ihdr(1, 1, 8, 3, 0)),
+                (b"PLTE", palette.clone()),
+                (b"IDAT", encoders::zlib::encode(&[0, 0]).unwrap()),
+                (b"IEND", Vec::new()),
+            ]);
+            assert_eq!(
+                decode(&png).err(),
+                Some("Invalid PNG: missing palette.".to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use aes::cipher::{Block, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes256};
+use md5::{Digest, Md5};
+use sha2::{Sha256, Sha384, Sha512};
+
+use super::encoders::winansi;
+
+/// Padding string of the standard security handler (revisions 2-4)
+const PADDING: [u8; 32] = [
+    0x28, 0xBF, 0x4E, 0x5E, 0x4E, 0x75, 0x8A, 0x41, 0x64, 0x00, 0x4E, 0x56, 0xFF, 0xFA, 0x01, 0x08,
+    0x2E, 0x2E, 0x00, 0xB6, 0xD0, 0x68, 0x3E, 0x80, 0x2F, 0x0C, 0xA9, 0xFE, 0x64, 0x53, 0x69, 0x7A,
+];
+
+/// Permission bits of /P
+const PRINT: u32 = 1 << 2;
+const MODIFY: u32 = 1 << 3;
+const COPY: u32 = 1 << 4;
+const ANNOTATE: u32 = 1 << 5;
+const FILL_FORMS: u32 = 1 << 8;
+const EXTRACT_FOR_ACCESSIBILITY: u32 = 1 << 9;
+const ASSEMBLE: u32 = 1 << 10;
+const PRINT_HIGH_QUALITY: u32 = 1 << 11;
+/// Reserved bits that must be set
+const RESERVED: u32 = 0xFFFF_F0C0;
+
+/// Encryption algorithm of the standard security handler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncryptionMethod {
+    /// RC4 with a 128-bit key (revision 3), readable by old viewers
+    Rc4,
+    /// AES-256 (revision 6)
+    Aes256,
+}
+
+impl EncryptionMethod {
+    pub fn from_name(name: &str) -> Result<EncryptionMethod, String> {
+        match name.to_lowercase().as_str() {
+            "" | "aes" | "aes256" | "aes-256" => Ok(EncryptionMethod::Aes256),
+            "rc4" | "rc4-128" => Ok(EncryptionMethod::Rc4),
+            _ => Err(format!(
+                "Invalid encryption method '{}', expected \"aes256\" or \"rc4\".",
+                name
+            )),
+        }
+    }
+}
+
+/// Operations allowed when the document is opened with the user password.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Permissions {
+    pub print: bool,
+    pub copy: bool,
+    pub modify: bool,
+}
+
+impl Permissions {
+    /// Value of /P, modifying includes annotating, filling forms and assembling pages.
+    fn get_flags(&self) -> i32 {
+        let mut flags = RESERVED | EXTRACT_FOR_ACCESSIBILITY;
+        if self.print {
+            flags |= PRINT | PRINT_HIGH_QUALITY;
+        }
+        if self.copy {
+            flags |= COPY;
+        }
+        if self.modify {
+            flags |= MODIFY | ANNOTATE | FILL_FORMS | ASSEMBLE;
+        }
+        flags as i32
+    }
+}
+
+/// Passwords and permissions of a protected document.
+/// Without an owner password a random one is used, so that permissions can't be changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encryption {
+    pub user_password: String,
+    pub owner_password: String,
+    pub method: EncryptionMethod,
+    pub permissions: Permissions,
+}
+
+/// Standard security handler, holds the file key and the encryption dictionary.
+pub struct SecurityHandler {
+    method: EncryptionMethod,
+    key: Vec<u8>,
+    dictionary: String,
+    /// Random seed and counter of AES initialization vectors
+    iv_seed: Vec<u8>,
+    iv_counter: u32,
+}
+
+impl SecurityHandler {
+    /// Compute keys and password entries, `id` is the first file identifier.
+    pub fn new(encryption: &Encryption, id: &[u8]) -> Result<SecurityHandler, &'static str> {
+        let permissions = encryption.permissions.get_flags();
+        let owner_password = if encryption.owner_password.is_empty() {
+            to_hex(&random_bytes(16)?)
+        } else {
+            encryption.owner_password.clone()
+        };
+        match encryption.method {
+            EncryptionMethod::Rc4 => {
+                let user = winansi::encode(&encryption.user_password);
+                let owner = winansi::encode(&owner_password);
+                let owner_entry = get_rc4_owner_entry(&user, &owner);
+                let key = get_rc4_key(&user, &owner_entry, permissions, id);
+                let user_entry = get_rc4_user_entry(&key, id);
+                Ok(SecurityHandler {
+                    method: EncryptionMethod::Rc4,
+                    key,
+                    iv_seed: Vec::new(),
+                    iv_counter: 0,
+                    dictionary: format!(
+                        "/Filter /Standard /V 2 /R 3 /Length 128 /O <{}> /U <{}> /P {}",
+                        to_hex(&owner_entry),
+                        to_hex(&user_entry),
+                        permissions
+                    ),
+                })
+            }
+            EncryptionMethod::Aes256 => {
+                let key = random_bytes(32)?;
+                let salts = random_bytes(32)?;
+                let user = truncate_password(&encryption.user_password);
+                let owner = truncate_password(&owner_password);
+                let (user_entry, user_key) =
+                    get_aes_password_entries(user, &salts[..16], &[], &key);
+                let (owner_entry, owner_key) =
+                    get_aes_password_entries(owner, &salts[16..], &user_entry, &key);
+                let mut perms = [0xFF; 16];
+                perms[..4].copy_from_slice(&permissions.to_le_bytes());
+                perms[8..12].copy_from_slice(b"Tadb");
+                perms[12..].copy_from_slice(&random_bytes(4)?);
+                let perms = aes256_encrypt_block(&key, &perms);
+                Ok(SecurityHandler {
+                    method: EncryptionMethod::Aes256,
+                    key,
+                    iv_seed: random_bytes(32)?,
+                    iv_counter: 0,
+                    dictionary: format!(
+                        "/Filter /Standard /V 5 /R 6 /Length 256 \
+                         /CF << /StdCF << /AuthEvent /DocOpen /CFM /AESV3 /Length 32 >> >> \
+                         /StmF /StdCF /StrF /StdCF /O <{}> /U <{}> /OE <{}> /UE <{}> /P {} \
+                         /Perms <{}> /EncryptMetadata true",
+                        to_hex(&owner_entry),
+                        to_hex(&user_entry),
+                        to_hex(&owner_key),
+                        to_hex(&user_key),
+                        permissions,
+                        to_hex(&perms)
+                    ),
+                })
+            }
+        }
+    }
+    /// Entries of the encryption dictionary
+    pub fn get_dictionary(&self) -> &str {
+        &self.dictionary
+    }
+    /// Catalog entries declaring the PDF version the encryption needs.
+    pub fn get_catalog_entries(&self) -> &'static str {
+        match self.method {
+            EncryptionMethod::Rc4 => "",
+            EncryptionMethod::Aes256 => {
+                " /Version /1.7 /Extensions << /ADBE << /BaseVersion /1.7 /ExtensionLevel 8 >> >>"
+            }
+        }
+    }
+    /// Encrypt a string or stream of the given object.
+    pub fn encrypt(&mut self, object_id: u16, data: &[u8]) -> Vec<u8> {
+        match self.method {
+            EncryptionMethod::Rc4 => {
+                let mut key = self.key.clone();
+                key.extend_from_slice(&u32::from(object_id).to_le_bytes()[..3]);
+                key.extend_from_slice(&[0, 0]);
+                rc4(&md5(&key), data)
+            }
+            EncryptionMethod::Aes256 => {
+                // unpredictable initialization vectors from the random seed
+                self.iv_counter += 1;
+                let iv = Sha256::new()
+                    .chain_update(&self.iv_seed)
+                    .chain_update(self.iv_counter.to_le_bytes())
+                    .finalize();
+                let padding = 16 - data.len() % 16;
+                let mut padded = data.to_vec();
+                padded.resize(data.len() + padding, padding as u8);
+                let cipher = Aes256::new_from_slice(&self.key).unwrap();
+                let mut output = iv[..16].to_vec();
+                output.extend(aes_cbc_encrypt(&cipher, &iv[..16], &padded));
+                output
+            }
+        }
+    }
+    /// Encrypt strings and the stream of an object written as "N 0 obj ... endobj".
+    /// Literal strings are written as hex strings and stream /Length is updated.
+    pub fn encrypt_object(&mut self, object: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let header_end = find(object, b" 0 obj").ok_or("Object without header.")?;
+        let object_id: u16 = std::str::from_utf8(&object[..header_end])
+            .ok()
+            .and_then(|id| id.trim().parse().ok())
+            .ok_or("Invalid object id.")?;
+        let (body, stream) = match find(object, b">>\nstream\n") {
+            Some(index) => object.split_at(index + 2),
+            None => (object, &[][..]),
+        };
+        let mut output = self.encrypt_strings(object_id, body)?;
+        if stream.is_empty() {
+            return Ok(output);
+        }
+        // stream data starts after "\nstream\n" and has /Length bytes
+        let length = get_stream_length(body).ok_or("Stream without length.")?;
+        let data = stream
+            .get(8..8 + length)
+            .ok_or("Stream shorter than its length.")?;
+        let encrypted = self.encrypt(object_id, data);
+        let length_entry = format!("/Length {}", length);
+        let index = find(&output, length_entry.as_bytes()).ok_or("Stream without length.")?;
+        let mut dictionary = output.split_off(index);
+        dictionary.drain(..length_entry.len());
+        output.extend_from_slice(format!("/Length {}", encrypted.len()).as_bytes());
+        output.extend(dictionary);
+        output.extend_from_slice(&stream[..8]);
+        output.extend(encrypted);
+        output.extend_from_slice(&stream[8 + length..]);
+        Ok(output)
+    }
+    /// Replace literal and hex strings with encrypted hex strings.
+    fn encrypt_strings(&mut self, object_id: u16, body: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let mut output = Vec::new();
+        let mut index = 0;
+        while index < body.len() {
+            match body[index] {
+                b'(' => {
+                    let (text, end) = read_literal_string(body, index);
+                    output.push(b'<');
+                    output.extend(to_hex(&self.encrypt(object_id, &text)).bytes());
+                    output.push(b'>');
+                    index = end;
+                }
+                b'<' if body.get(index + 1) == Some(&b'<') => {
+                    output.extend_from_slice(b"<<");
+                    index += 2;
+                }
+                // signature value is not encrypted
+                b'<' if output.ends_with(b"/Contents ") && find(body, b"/Type /Sig ").is_some() => {
+                    let end =
+                        index + find(&body[index..], b">").ok_or("Unterminated hex string.")?;
+                    output.extend_from_slice(&body[index..=end]);
+                    index = end + 1;
+                }
+                b'<' => {
+                    let end =
+                        index + find(&body[index..], b">").ok_or("Unterminated hex string.")?;
+                    let text = from_hex(&body[index + 1..end]);
+                    output.push(b'<');
+                    output.extend(to_hex(&self.encrypt(object_id, &text)).bytes());
+                    output.push(b'>');
+                    index = end + 1;
+                }
+                byte => {
+                    output.push(byte);
+                    index += 1;
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Bytes of the literal string starting at `start` and the index after it.
+fn read_literal_string(body: &[u8], start: usize) -> (Vec<u8>, usize) {
+    let mut text = Vec::new();
+    let mut depth = 0;
+    let mut index = start + 1;
+    while index < body.len() {
+        let byte = body[index];
+        index += 1;
+        match byte {
+            b'\\' => {
+                let escaped = body.get(index).copied().unwrap_or(b'\\');
+                index += 1;
+                match escaped {
+                    b'n' => text.push(b'\n'),
+                    b'r' => text.push(b'\r'),
+                    b't' => text.push(b'\t'),
+                    b'b' => text.push(8),
+                    b'f' => text.push(12),
+                    b'0'..=b'7' => {
+                        let mut value = u32::from(escaped - b'0');
+                        for _ in 0..2 {
+                            match body.get(index) {
+                                Some(digit @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(digit - b'0');
+                                    index += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        text.push(value as u8);
+                    }
+                    // line continuation
+                    b'\n' => {}
+                    other => text.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                text.push(byte);
+            }
+            b')' if depth == 0 => break,
+            b')' => {
+                depth -= 1;
+                text.push(byte);
+            }
+            _ => text.push(byte),
+        }
+    }
+    (text, index)
+}
+
+fn get_stream_length(dictionary: &[u8]) -> Option<usize> {
+    let index = find(dictionary, b"/Length ")? + 8;
+    let digits: String = dictionary[index..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .map(|&byte| byte as char)
+        .collect();
+    digits.parse().ok()
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+/// Owner password entry /O (algorithm 3)
+fn get_rc4_owner_entry(user: &[u8], owner: &[u8]) -> Vec<u8> {
+    let mut hash = md5(&pad_password(owner));
+    for _ in 0..50 {
+        hash = md5(&hash);
+    }
+    let mut entry = pad_password(user).to_vec();
+    for round in 0..20 {
+        let key: Vec<u8> = hash.iter().map(|byte| byte ^ round).collect();
+        entry = rc4(&key, &entry);
+    }
+    entry
+}
+
+/// File key from the user password (algorithm 2)
+fn get_rc4_key(user: &[u8], owner_entry: &[u8], permissions: i32, id: &[u8]) -> Vec<u8> {
+    let mut input = pad_password(user).to_vec();
+    input.extend_from_slice(owner_entry);
+    input.extend_from_slice(&permissions.to_le_bytes());
+    input.extend_from_slice(id);
+    let mut key = md5(&input);
+    for _ in 0..50 {
+        key = md5(&key);
+    }
+    key
+}
+
+/// User password entry /U (algorithm 5), padded to 32 bytes
+fn get_rc4_user_entry(key: &[u8], id: &[u8]) -> Vec<u8> {
+    let mut input = PADDING.to_vec();
+    input.extend_from_slice(id);
+    let mut entry = md5(&input);
+    for round in 0..20 {
+        let round_key: Vec<u8> = key.iter().map(|byte| byte ^ round).collect();
+        entry = rc4(&round_key, &entry);
+    }
+    entry.extend_from_slice(&[0; 16]);
+    entry
+}
+
+fn pad_password(password: &[u8]) -> [u8; 32] {
+    let mut padded = PADDING;
+    let length = password.len().min(32);
+    padded[..length].copy_from_slice(&password[..length]);
+    padded[length..].copy_from_slice(&PADDING[..32 - length]);
+    padded
+}
+
+/// Password entry (/U or /O) and the encrypted file key (/UE or /OE) of revision 6,
+/// `salts` holds the validation and key salts.
+fn get_aes_password_entries(
+    password: &[u8],
+    salts: &[u8],
+    user_entry: &[u8],
+    key: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let mut entry = get_aes_hash(password, &salts[..8], user_entry);
+    entry.extend_from_slice(salts);
+    let intermediate = get_aes_hash(password, &salts[8..], user_entry);
+    let cipher = Aes256::new_from_slice(&intermediate).unwrap();
+    (entry, aes_cbc_encrypt(&cipher, &[0; 16], key))
+}
+
+/// Password hash of revision 6 (algorithm 2.B)
+fn get_aes_hash(password: &[u8], salt: &[u8], user_entry: &[u8]) -> Vec<u8> {
+    let mut hash = Sha256::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(user_entry)
+        .finalize()
+        .to_vec();
+    let mut round = 0;
+    loop {
+        let mut block = password.to_vec();
+        block.extend_from_slice(&hash);
+        block.extend_from_slice(user_entry);
+        let data = block.repeat(64);
+        let cipher = Aes128::new_from_slice(&hash[..16]).unwrap();
+        let encrypted = aes_cbc_encrypt(&cipher, &hash[16..32], &data);
+        let sum: u32 = encrypted[..16].iter().map(|&byte| u32::from(byte)).sum();
+        hash = match sum % 3 {
+            0 => Sha256::digest(&encrypted).to_vec(),
+            1 => Sha384::digest(&encrypted).to_vec(),
+            _ => Sha512::digest(&encrypted).to_vec(),
+        };
+        round += 1;
+        if round >= 64 && u32::from(*encrypted.last().unwrap()) <= round - 32 {
+            break;
+        }
+    }
+    hash.truncate(32);
+    hash
+}
+
+/// Passwords of revision 6 are UTF-8, up to 127 bytes.
+fn truncate_password(password: &str) -> &[u8] {
+    let mut length = password.len().min(127);
+    while !password.is_char_boundary(length) {
+        length -= 1;
+    }
+    &password.as_bytes()[..length]
+}
+
+/// CBC mode without padding, data is a multiple of the block size.
+fn aes_cbc_encrypt<C: BlockEncrypt>(cipher: &C, iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut previous = [0; 16];
+    previous.copy_from_slice(iv);
+    let mut output = Vec::with_capacity(data.len());
+    for chunk in data.chunks(16) {
+        let mut block = Block::<C>::default();
+        for (index, byte) in block.iter_mut().enumerate() {
+            *byte = chunk[index] ^ previous[index];
+        }
+        cipher.encrypt_block(&mut block);
+        previous.copy_from_slice(&block);
+        output.extend_from_slice(&block);
+    }
+    output
+}
+
+fn aes256_encrypt_block(key: &[u8], data: &[u8; 16]) -> Vec<u8> {
+    aes_cbc_encrypt(&Aes256::new_from_slice(key).unwrap(), &[0; 16], data)
+}
+
+/// RC4 stream cipher, encryption and decryption are the same operation.
+pub fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            let index = state[i as usize].wrapping_add(state[j as usize]);
+            byte ^ state[index as usize]
+        })
+        .collect()
+}
+
+pub fn md5(data: &[u8]) -> Vec<u8> {
+    Md5::digest(data).to_vec()
+}
+
+pub fn random_bytes(length: usize) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = vec![0; length];
+    getrandom::getrandom(&mut bytes).map_err(|_| "Random numbers are not available.")?;
+    Ok(bytes)
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn from_hex(hex: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .iter()
+        .filter_map(|&byte| (byte as char).to_digit(16).map(|digit| digit as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rc4() {
+        assert_eq!(to_hex(&rc4(b"Key", b"Plaintext")), "BBF316E8D940AF0AD3");
+        assert_eq!(rc4(b"Key", &rc4(b"Key", b"Plaintext")), b"Plaintext");
+    }
+
+    #[test]
+    fn test_rc4_security_handler() {
+        let encryption = Encryption {
+            user_password: String::from("user"),
+            owner_password: String::from("owner"),
+            method: EncryptionMethod::Rc4,
+            permissions: Permissions {
+                print: true,
+                copy: false,
+                modify: false,
+            },
+        };
+        let id = [7; 16];
+        let mut handler = SecurityHandler::new(&encryption, &id).unwrap();
+        assert!(handler.get_dictionary().contains("/V 2 /R 3 /Length 128"));
+        assert!(handler.get_dictionary().ends_with("/P -1340"));
+        let object =
+            b"5 0 obj\n<<\n/T (a\\(b\\)) /V <0102> /Length 4\n>>\nstream\ntextendstream\nendobj\n";
+        let encrypted = handler.encrypt_object(object).unwrap();
+        let object_key = {
+            let mut key = handler.key.clone();
+            key.extend_from_slice(&[5, 0, 0, 0, 0]);
+            md5(&key)
+        };
+        let expected = format!(
+            "5 0 obj\n<<\n/T <{}> /V <{}> /Length 4\n>>\nstream\n",
+            to_hex(&rc4(&object_key, b"a(b)")),
+            to_hex(&rc4(&object_key, &[1, 2]))
+        );
+        assert!(encrypted.starts_with(expected.as_bytes()));
+        assert!(encrypted.ends_with(b"endstream\nendobj\n"));
+        assert_eq!(
+            &encrypted[expected.len()..expected.len() + 4],
+            &rc4(&object_key, b"text")[..]
+        );
+    }
+
+    #[test]
+    fn test_aes_security_handler() {
+        let encryption = Encryption {
+            user_password: String::from("pässword"),
+            owner_password: String::new(),
+            method: EncryptionMethod::Aes256,
+            permissions: Permissions {
+                print: false,
+                copy: false,
+                modify: false,
+            },
+        };
+        let mut handler = SecurityHandler::new(&encryption, &[0; 16]).unwrap();
+        assert_eq!(handler.key.len(), 32);
+        assert!(handler.get_catalog_entries().contains("/ExtensionLevel 8"));
+        let object = b"9 0 obj\n<<\n/Length 5\n>>\nstream\nhelloendstream\nendobj\n";
+        let encrypted = handler.encrypt_object(object).unwrap();
+        // 16 bytes of IV and one padded block
+        assert!(encrypted.starts_with(b"9 0 obj\n<<\n/Length 32\n>>\nstream\n"));
+        assert_eq!(encrypted.len(), object.len() - 5 + 32 + 1);
+        assert_eq!(to_hex(&from_hex(b"0a1B")), "0A1B");
+        // malformed objects are reported instead of panicking
+        assert!(handler.encrypt_object(b"<< /T (a) >>").is_err());
+        assert!(handler
+            .encrypt_object(b"9 0 obj\n<<\n>>\nstream\nhello")
+            .is_err());
+        assert!(handler
+            .encrypt_object(b"9 0 obj\n<<\n/Length 9\n>>\nstream\nhello")
+            .is_err());
+        assert!(handler.encrypt_object(b"9 0 obj\n<< /V <0102").is_err());
+    }
+}
@@ -0,0 +1,501 @@
+use std::io::Write;
+
+use super::font::Font;
+use super::metadata::encode_text_string;
+use super::objects::{encode_name, PDFFile, PDFObject};
+use super::styles::Clip;
+use super::text::TextSpan;
+use super::units::Rect;
+
+/// Field flags (/Ff)
+const READ_ONLY: u32 = 1;
+const REQUIRED: u32 = 1 << 1;
+const MULTILINE: u32 = 1 << 12;
+const PASSWORD: u32 = 1 << 13;
+const NO_TOGGLE_TO_OFF: u32 = 1 << 14;
+const RADIO: u32 = 1 << 15;
+const COMBO: u32 = 1 << 17;
+const EDIT: u32 = 1 << 18;
+
+/// Export value of a checked check box
+const CHECKED: &str = "Yes";
+
+/// Field type and its value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Text {
+        value: String,
+        multiline: bool,
+        password: bool,
+        max_length: Option<u16>,
+    },
+    CheckBox {
+        checked: bool,
+    },
+    /// Radio buttons of a group, one of the options is selected
+    Radio {
+        options: Vec<String>,
+        value: Option<String>,
+    },
+    Combo {
+        options: Vec<String>,
+        value: String,
+        editable: bool,
+    },
+    Signature,
+}
+
+/// Form field placed on a page. Radio groups have a widget for each option,
+/// other fields are merged with their only widget annotation.
+#[derive(Debug, Clone)]
+pub struct PDFFormField {
+    name: String,
+    kind: FieldKind,
+    required: bool,
+    read_only: bool,
+    font: &'static Font,
+    font_size: f32,
+    /// Widget rectangles (x1, y1, x2, y2)
+    rects: Vec<[f32; 4]>,
+    field_id: u16,
+    widget_ids: Vec<u16>,
+    /// Signature dictionary of a signed signature field
+    signature_id: Option<u16>,
+}
+
+impl PDFFormField {
+    pub fn new(
+        name: &str,
+        kind: &FieldKind,
+        required: bool,
+        read_only: bool,
+        font: &'static Font,
+        font_size: f32,
+        rects: Vec<[f32; 4]>,
+    ) -> PDFFormField {
+        PDFFormField {
+            name: String::from(name),
+            kind: kind.clone(),
+            required,
+            read_only,
+            font,
+            font_size,
+            rects,
+            field_id: 0,
+            widget_ids: Vec::new(),
+            signature_id: None,
+        }
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_field_id(&self) -> u16 {
+        self.field_id
+    }
+    pub fn is_signature(&self) -> bool {
+        self.kind == FieldKind::Signature
+    }
+    /// Sign the field with the signature dictionary of the given id.
+    pub fn set_signature(&mut self, signature_id: u16) {
+        self.signature_id = Some(signature_id);
+    }
+    /// Annotation ids listed in the page /Annots
+    pub fn get_widget_ids(&self) -> &[u16] {
+        &self.widget_ids
+    }
+    fn get_flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.read_only {
+            flags |= READ_ONLY;
+        }
+        if self.required {
+            flags |= REQUIRED;
+        }
+        match &self.kind {
+            FieldKind::Text {
+                multiline,
+                password,
+                ..
+            } => {
+                if *multiline {
+                    flags |= MULTILINE;
+                }
+                if *password {
+                    flags |= PASSWORD;
+                }
+            }
+            FieldKind::Radio { .. } => flags |= RADIO | NO_TOGGLE_TO_OFF,
+            FieldKind::Combo { editable, .. } => {
+                flags |= COMBO;
+                if *editable {
+                    flags |= EDIT;
+                }
+            }
+            FieldKind::CheckBox { .. } | FieldKind::Signature => {}
+        }
+        flags
+    }
+    /// Field type, value and type specific entries of the field dictionary.
+    fn get_field_entries(&self) -> String {
+        let flags = self.get_flags();
+        let mut entries = format!("/T {} /Ff {}", encode_text_string(&self.name), flags);
+        let default_appearance =
+            format!(" /DA (/{} {} Tf 0 g)", self.font.get_ref(), self.font_size);
+        match &self.kind {
+            FieldKind::Text {
+                value,
+                max_length,
+                password,
+                ..
+            } => {
+                entries = format!("/FT /Tx {}{}", entries, default_appearance);
+                // the value of a password field is not stored in the file
+                if !value.is_empty() && !*password {
+                    let value = encode_text_string(value);
+                    entries += &format!(" /V {0} /DV {0}", value);
+                }
+                if let Some(max_length) = max_length {
+                    entries += &format!(" /MaxLen {}", max_length);
+                }
+            }
+            FieldKind::CheckBox { checked } => {
+                let state = if *checked { CHECKED } else { "Off" };
+                entries = format!("/FT /Btn {} /V /{1} /DV /{1}", entries, state);
+            }
+            FieldKind::Radio { value, .. } => {
+                let state = value.as_deref().map_or(String::from("Off"), encode_name);
+                entries = format!("/FT /Btn {} /V /{1} /DV /{1}", entries, state);
+            }
+            FieldKind::Combo { options, value, .. } => {
+                let options: Vec<String> = options.iter().map(|o| encode_text_string(o)).collect();
+                entries = format!(
+                    "/FT /Ch {}{} /Opt [ {} ]",
+                    entries,
+                    default_appearance,
+                    options.join(" ")
+                );
+                if !value.is_empty() {
+                    let value = encode_text_string(value);
+                    entries += &format!(" /V {0} /DV {0}", value);
+                }
+            }
+            FieldKind::Signature => {
+                entries = format!("/FT /Sig {}", entries);
+                if let Some(signature_id) = self.signature_id {
+                    entries += &format!(" /V {} 0 R", signature_id);
+                }
+            }
+        }
+        entries
+    }
+    /// Appearance states of the widget and the current one, on and off states for buttons.
+    fn get_states(&self, index: usize) -> (Vec<String>, Option<String>) {
+        match &self.kind {
+            FieldKind::CheckBox { checked } => {
+                let current = if *checked { CHECKED } else { "Off" };
+                (
+                    vec![String::from(CHECKED), String::from("Off")],
+                    Some(String::from(current)),
+                )
+            }
+            FieldKind::Radio { options, value } => {
+                let option = &options[index];
+                let current = if value.as_ref() == Some(option) {
+                    encode_name(option)
+                } else {
+                    String::from("Off")
+                };
+                (
+                    vec![encode_name(option), String::from("Off")],
+                    Some(current),
+                )
+            }
+            _ => (vec![String::new()], None),
+        }
+    }
+    /// Widget annotation entries with appearance streams of the given ids.
+    fn get_widget_entries(&self, index: usize, appearance_ids: &[u16]) -> String {
+        let [x1, y1, x2, y2] = self.rects[index];
+        let (states, current) = self.get_states(index);
+        let (appearance, state) = match current {
+            Some(current) => {
+                let streams: Vec<String> = states
+                    .iter()
+                    .zip(appearance_ids)
+                    .map(|(state, id)| format!("/{} {} 0 R", state, id))
+                    .collect();
+                (
+                    format!("<< {} >>", streams.join(" ")),
+                    format!(" /AS /{}", current),
+                )
+            }
+            None => (format!("{} 0 R", appearance_ids[0]), String::new()),
+        };
+        format!(
+            "/Type /Annot /Subtype /Widget /F 4 /Rect [ {} {} {} {} ] \
+             /MK << /BC [0.5] /BG [1] >> /AP << /N {} >>{}",
+            x1, y1, x2, y2, appearance, state
+        )
+    }
+    /// Write the field, its widgets and their appearance streams.
+    /// Ids are given first, objects are written in the order of their ids.
+    pub fn write(&mut self, pdf: &mut PDFFile, font_id: u16) -> Result<(), &'static str> {
+        self.field_id = pdf.get_new_object_id();
+        let is_radio = matches!(self.kind, FieldKind::Radio { .. });
+        self.widget_ids = if is_radio {
+            self.rects.iter().map(|_| pdf.get_new_object_id()).collect()
+        } else {
+            vec![self.field_id]
+        };
+        let appearance_ids: Vec<Vec<u16>> = (0..self.rects.len())
+            .map(|index| {
+                let (states, _) = self.get_states(index);
+                states.iter().map(|_| pdf.get_new_object_id()).collect()
+            })
+            .collect();
+        let mut entries = self.get_field_entries();
+        if is_radio {
+            let kids: Vec<String> = self
+                .widget_ids
+                .iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect();
+            entries += &format!(" /Kids [ {} ]", kids.join(" "));
+        } else {
+            entries = format!(
+                "{} {}",
+                entries,
+                self.get_widget_entries(0, &appearance_ids[0])
+            );
+        }
+        pdf.add_object(&PDFObject::new(&entries, self.field_id))?;
+        if is_radio {
+            for (index, widget_id) in self.widget_ids.iter().enumerate() {
+                let entries = format!(
+                    "/Parent {} 0 R {}",
+                    self.field_id,
+                    self.get_widget_entries(index, &appearance_ids[index])
+                );
+                pdf.add_object(&PDFObject::new(&entries, *widget_id))?;
+            }
+        }
+        for (index, ids) in appearance_ids.iter().enumerate() {
+            let [x1, y1, x2, y2] = self.rects[index];
+            let (width, height) = (x2 - x1, y2 - y1);
+            for (state, id) in ids.iter().enumerate() {
+                let contents = self.get_appearance(width, height, state == 0);
+                pdf.add_bytes(&write_form_xobject(
+                    *id,
+                    width,
+                    height,
+                    &format!("/Font {} 0 R", font_id),
+                    &contents,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+    /// Appearance stream contents of a widget of the given size,
+    /// `on` selects the checked appearance of buttons.
+    fn get_appearance(&self, width: f32, height: f32, on: bool) -> Vec<u8> {
+        let mut output = Vec::new();
+        // invisible signature
+        if width <= 0.0 || height <= 0.0 {
+            return output;
+        }
+        let is_radio = matches!(self.kind, FieldKind::Radio { .. });
+        // background and border
+        let border = Rect::new(0.5, height - 0.5, width - 1.0, height - 1.0);
+        let shape = if is_radio { Clip::Circle } else { Clip::Rect };
+        writeln!(output, "1 g 0.5 G 1 w\n{}\nB", shape.get_path_ops(border)).unwrap();
+        match &self.kind {
+            FieldKind::Text {
+                value,
+                multiline,
+                password,
+                ..
+            } => {
+                let value = if *password {
+                    "*".repeat(value.chars().count())
+                } else {
+                    value.clone()
+                };
+                let lines: Vec<&str> = if *multiline {
+                    value.split('\n').collect()
+                } else {
+                    vec![value.as_str()]
+                };
+                self.write_text(&mut output, &lines, width, height, *multiline);
+            }
+            FieldKind::Combo { value, .. } => {
+                // drop-down arrow
+                let size = height.min(width) / 3.0;
+                let (x, y) = (width - height / 2.0, height / 2.0);
+                writeln!(
+                    output,
+                    "0.5 g {} {} m {} {} l {} {} l f",
+                    x - size / 2.0,
+                    y + size / 4.0,
+                    x + size / 2.0,
+                    y + size / 4.0,
+                    x,
+                    y - size / 4.0
+                )
+                .unwrap();
+                self.write_text(
+                    &mut output,
+                    &[value.as_str()],
+                    width - height,
+                    height,
+                    false,
+                );
+            }
+            FieldKind::CheckBox { .. } if on => {
+                writeln!(
+                    output,
+                    "0 G {} w 1 J 1 j {} {} m {} {} l {} {} l S",
+                    (width / 8.0).max(1.0),
+                    width * 0.22,
+                    height * 0.5,
+                    width * 0.42,
+                    height * 0.28,
+                    width * 0.78,
+                    height * 0.74
+                )
+                .unwrap();
+            }
+            FieldKind::Radio { .. } if on => {
+                let dot = Rect::new(width * 0.3, height * 0.7, width * 0.4, height * 0.4);
+                writeln!(output, "0 g\n{}\nf", Clip::Circle.get_path_ops(dot)).unwrap();
+            }
+            _ => {}
+        }
+        output
+    }
+    /// Text lines of a variable text field, vertically centred when single line.
+    fn write_text(
+        &self,
+        output: &mut Vec<u8>,
+        lines: &[&str],
+        width: f32,
+        height: f32,
+        multiline: bool,
+    ) {
+        writeln!(
+            output,
+            "/Tx BMC\nq 1 1 {} {} re W n",
+            width - 2.0,
+            height - 2.0
+        )
+        .unwrap();
+        let leading = self.font_size * 1.15;
+        let baseline = if multiline {
+            height - 2.0 - self.font_size
+        } else {
+            (height - self.font_size) / 2.0 + self.font_size * 0.22
+        };
+        write!(
+            output,
+            "BT /{} {} Tf 0 g {} TL 2 {} Td ",
+            self.font.get_ref(),
+            self.font_size,
+            leading,
+            baseline
+        )
+        .unwrap();
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                write!(output, "T* ").unwrap();
+            }
+            output.write_all(&TextSpan::encode_text(line)).unwrap();
+        }
+        writeln!(output, "ET\nQ\nEMC").unwrap();
+    }
+}
+
+/// Form XObject used as a widget or annotation appearance, with the given resources.
+pub fn write_form_xobject(
+    id: u16,
+    width: f32,
+    height: f32,
+    resources: &str,
+    contents: &[u8],
+) -> Vec<u8> {
+    let mut output = Vec::new();
+    writeln!(
+        output,
+        "{} 0 obj\n<<\n/Type /XObject /Subtype /Form /BBox [0 0 {} {}] \
+         /Resources << {} >> /Length {}\n>>",
+        id,
+        width,
+        height,
+        resources,
+        contents.len()
+    )
+    .unwrap();
+    writeln!(output, "stream").unwrap();
+    output.write_all(contents).unwrap();
+    writeln!(output, "endstream").unwrap();
+    writeln!(output, "endobj").unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pdf::font::get_font;
+
+    #[test]
+    fn test_field_entries() {
+        let font = get_font("helvetica");
+        let text = PDFFormField::new(
+            "email",
+            &FieldKind::Text {
+                value: String::from("a@b.c"),
+                multiline: false,
+                password: false,
+                max_length: Some(40),
+            },
+            true,
+            true,
+            font,
+            10.0,
+            vec![[0.0, 0.0, 100.0, 20.0]],
+        );
+        assert_eq!(
+            text.get_field_entries(),
+            format!(
+                "/FT /Tx /T (email) /Ff 3 /DA (/{} 10 Tf 0 g) /V (a@b.c) /DV (a@b.c) /MaxLen 40",
+                font.get_ref()
+            )
+        );
+        let radio = PDFFormField::new(
+            "size",
+            &FieldKind::Radio {
+                options: vec![String::from("Small"), String::from("Extra large")],
+                value: Some(String::from("Extra large")),
+            },
+            false,
+            false,
+            font,
+            10.0,
+            vec![[0.0, 0.0, 10.0, 10.0], [0.0, 20.0, 10.0, 30.0]],
+        );
+        assert_eq!(
+            radio.get_field_entries(),
+            "/FT /Btn /T (size) /Ff 49152 /V /Extra#20large /DV /Extra#20large"
+        );
+        assert_eq!(
+            radio.get_states(0),
+            (
+                vec![String::from("Small"), String::from("Off")],
+                Some(String::from("Off"))
+            )
+        );
+        let mut pdf = PDFFile::new();
+        let mut radio = radio;
+        radio.write(&mut pdf, 1).unwrap();
+        assert_eq!(radio.get_field_id(), 1);
+        assert_eq!(radio.get_widget_ids(), &[2, 3]);
+    }
+}
@@ -2,7 +2,7 @@ use super::units::A4;
 use std::collections::HashMap;
 
 /// Parameter values from JSON
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum JsParamValue {
     Null,
@@ -14,7 +14,7 @@ pub enum JsParamValue {
     Array(Vec<JsParamValue>),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JsContent {
     #[serde(default = "default_obj_type")]
     pub obj_type: String,
@@ -29,6 +29,29 @@ pub struct JsTemplate {
     pub left: f32,
     pub right: f32,
     pub bottom: f32,
+    /// Watermark parameters, text or image drawn on every page
+    #[serde(default)]
+    pub watermark: Option<HashMap<String, JsParamValue>>,
+    #[serde(default)]
+    pub page_numbering: JsPageNumbering,
+}
+
+/// First displayed page number and number of unnumbered pages (e.g. cover) before it.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct JsPageNumbering {
+    #[serde(default = "default_page_number_start")]
+    pub start: i32,
+    #[serde(default)]
+    pub skip: u16,
+}
+
+impl Default for JsPageNumbering {
+    fn default() -> JsPageNumbering {
+        JsPageNumbering {
+            start: default_page_number_start(),
+            skip: 0,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,6 +70,131 @@ pub struct JsDocument {
     pub image_widths: HashMap<String, f32>,
     #[serde(default = "default_image_sizes")]
     pub image_heights: HashMap<String, f32>,
+    /// Date for {date} placeholders, current date (YYYY-MM-DD) when empty
+    #[serde(default)]
+    pub date: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub keywords: String,
+    #[serde(default)]
+    pub creator: String,
+    #[serde(default = "default_producer")]
+    pub producer: String,
+    /// Creation date (ISO 8601), the document date when empty
+    #[serde(default)]
+    pub creation_date: String,
+    /// Modification date (ISO 8601), the creation date when empty
+    #[serde(default)]
+    pub mod_date: String,
+    /// Panel shown when the document is opened, "outline" shows the bookmarks
+    /// and "attachments" the embedded files
+    #[serde(default)]
+    pub page_mode: String,
+    /// Note placement, "foot" (default) or "end"
+    #[serde(default)]
+    pub notes: String,
+    /// Password protection, the document is not encrypted when missing
+    #[serde(default)]
+    pub encryption: Option<JsEncryption>,
+    /// Digital signature, the document is not signed when missing
+    #[serde(default)]
+    pub signature: Option<JsSignature>,
+    /// Files embedded in the document, e.g. the XML of an e-invoice
+    #[serde(default)]
+    pub attachments: Vec<JsAttachment>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JsEncryption {
+    #[serde(default)]
+    pub user_password: String,
+    #[serde(default)]
+    pub owner_password: String,
+    /// "aes256" (default) or "rc4"
+    #[serde(default)]
+    pub method: String,
+    #[serde(default)]
+    pub permissions: JsPermissions,
+}
+
+/// Signing key and certificates, as base64 encoded PKCS#12 or as PEM texts.
+#[derive(Serialize, Deserialize, Default)]
+pub struct JsSignature {
+    /// Name of a signature field, an invisible field is added when empty
+    #[serde(default)]
+    pub field: String,
+    #[serde(default)]
+    pub pkcs12: String,
+    /// Password of the PKCS#12 data
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub private_key: String,
+    #[serde(default)]
+    pub certificates: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub contact_info: String,
+    /// Signing time, defaults to the modification date
+    #[serde(default)]
+    pub date: String,
+}
+
+/// Embedded file, given as base64 data or, in the CLI, as a file path in `src`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct JsAttachment {
+    /// File name shown by viewers, the file name of `src` when empty
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub data: String,
+    #[serde(default)]
+    pub src: String,
+    /// MIME type, e.g. "text/xml"
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub description: String,
+    /// "source", "data", "alternative", "supplement" or "unspecified" (default)
+    #[serde(default)]
+    pub relationship: String,
+}
+
+/// Operations allowed for users opening the document with the user password.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct JsPermissions {
+    #[serde(default = "default_permission")]
+    pub print: bool,
+    #[serde(default = "default_permission")]
+    pub copy: bool,
+    #[serde(default = "default_permission")]
+    pub modify: bool,
+}
+
+impl Default for JsPermissions {
+    fn default() -> JsPermissions {
+        JsPermissions {
+            print: true,
+            copy: true,
+            modify: true,
+        }
+    }
+}
+
+fn default_permission() -> bool {
+    true
+}
+
+fn default_producer() -> String {
+    "WASM-PDF library".to_string()
 }
 
 fn default_title() -> String {
@@ -64,9 +212,15 @@ fn default_template() -> JsTemplate {
         left: 50.0,
         bottom: 50.0,
         right: 50.0,
+        watermark: None,
+        page_numbering: JsPageNumbering::default(),
     }
 }
 
+fn default_page_number_start() -> i32 {
+    1
+}
+
 fn default_template_size() -> (f32, f32) {
     A4
 }
@@ -115,3 +269,27 @@ pub fn get_bool_from_js(value: Option<&JsParamValue>, default: bool) -> bool {
         default
     }
 }
+
+/// Render parameter value in JSON notation, used in error messages.
+pub fn to_js_string(value: &JsParamValue) -> String {
+    match value {
+        JsParamValue::Null => String::from("null"),
+        JsParamValue::Boolean(b) => b.to_string(),
+        JsParamValue::Text(t) => format!("{:?}", t),
+        JsParamValue::Number(n) => n.to_string(),
+        JsParamValue::Children(children) => format!("[{} elements]", children.len()),
+        JsParamValue::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort();
+            let values: Vec<String> = keys
+                .iter()
+                .map(|k| format!("{:?}: {}", k, to_js_string(&object[*k])))
+                .collect();
+            format!("{{{}}}", values.join(", "))
+        }
+        JsParamValue::Array(values) => {
+            let values: Vec<String> = values.iter().map(to_js_string).collect();
+            format!("[{}]", values.join(", "))
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use regex::Regex;
+
+/// Document metadata written to the Info dictionary and the XMP metadata stream.
+/// Dates are ISO 8601 dates, e.g. "2024-05-01" or "2024-05-01T12:30:00+02:00".
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocumentInfo {
+    pub title: String,
+    pub author: String,
+    pub subject: String,
+    pub keywords: String,
+    pub creator: String,
+    pub producer: String,
+    pub creation_date: String,
+    pub mod_date: String,
+}
+
+impl DocumentInfo {
+    /// Check that dates are valid, so that writing the document can't fail.
+    pub fn validate(&self) -> Result<(), String> {
+        for date in &[&self.creation_date, &self.mod_date] {
+            if !date.is_empty() && !is_date(date) {
+                return Err(format!(
+                    "Invalid date '{}', expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS with optional time zone.",
+                    date
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Entries of the Info dictionary, empty values are left out.
+    pub fn get_dictionary(&self) -> String {
+        let mut entries: Vec<String> = Vec::new();
+        let texts = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Subject", &self.subject),
+            ("Keywords", &self.keywords),
+            ("Creator", &self.creator),
+            ("Producer", &self.producer),
+        ];
+        for (key, value) in texts.iter() {
+            if !value.is_empty() {
+                entries.push(format!("/{} {}", key, encode_text_string(value)));
+            }
+        }
+        let dates = [
+            ("CreationDate", &self.creation_date),
+            ("ModDate", &self.mod_date),
+        ];
+        for (key, value) in dates.iter() {
+            if let Some(date) = get_pdf_date(value) {
+                entries.push(format!("/{} ({})", key, date));
+            }
+        }
+        entries.join(" ")
+    }
+    /// XMP metadata packet matching the Info dictionary.
+    pub fn get_xmp(&self) -> String {
+        let mut properties = String::from("<dc:format>application/pdf</dc:format>\n");
+        if !self.title.is_empty() {
+            properties += &format!(
+                "<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+                escape_xml(&self.title)
+            );
+        }
+        if !self.author.is_empty() {
+            properties += &format!(
+                "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+                escape_xml(&self.author)
+            );
+        }
+        if !self.subject.is_empty() {
+            properties += &format!(
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+                escape_xml(&self.subject)
+            );
+        }
+        let creation_date = get_xmp_date(&self.creation_date);
+        let mod_date = get_xmp_date(&self.mod_date);
+        let simple = [
+            ("pdf:Keywords", &self.keywords),
+            ("pdf:Producer", &self.producer),
+            ("xmp:CreatorTool", &self.creator),
+            ("xmp:CreateDate", &creation_date),
+            ("xmp:ModifyDate", &mod_date),
+            ("xmp:MetadataDate", &mod_date),
+        ];
+        for (name, value) in simple.iter() {
+            if !value.is_empty() {
+                properties += &format!("<{0}>{1}</{0}>\n", name, escape_xml(value));
+            }
+        }
+        format!(
+            "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\">
+{}</rdf:Description>
+</rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>",
+            properties
+        )
+    }
+}
+
+/// Date and time digits (YYYYMMDDHHmmSS) and time zone (Z, +HH:MM or -HH:MM) of an ISO 8601 date.
+fn parse_date(date: &str) -> Option<(String, Option<String>)> {
+    lazy_static! {
+        static ref DATE: Regex = Regex::new(
+            r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2}))?)?(Z|[+-]\d{2}:\d{2})?$"
+        )
+        .unwrap();
+    }
+    let captures = DATE.captures(date.trim())?;
+    let digits: String = (1..=6)
+        .map(|index| captures.get(index).map_or("00", |value| value.as_str()))
+        .collect();
+    let zone = captures.get(7).map(|zone| zone.as_str().to_owned());
+    Some((digits, zone))
+}
+
+/// Check if the text is an ISO 8601 date accepted in metadata.
+pub fn is_date(date: &str) -> bool {
+    parse_date(date).is_some()
+}
+
+/// Date in XMP format, ISO 8601 with the T separator, e.g. 2024-05-01T12:30+02:00
+fn get_xmp_date(date: &str) -> String {
+    date.trim().replacen(' ', "T", 1)
+}
+
+/// Date in PDF format, e.g. D:20240501123000+02'00'
+pub fn get_pdf_date(date: &str) -> Option<String> {
+    let (digits, zone) = parse_date(date)?;
+    let zone = match zone {
+        Some(zone) if zone == "Z" => zone,
+        Some(zone) => format!("{}'{}'", &zone[..3], &zone[4..]),
+        None => String::new(),
+    };
+    Some(format!("D:{}{}", digits, zone))
+}
+
+/// PDF text string, a literal string for ASCII text and UTF-16BE with byte order mark otherwise.
+pub fn encode_text_string(text: &str) -> String {
+    if text.is_ascii() {
+        let mut output = String::from("(");
+        for char in text.chars() {
+            match char {
+                '(' | ')' | '\\' => {
+                    output.push('\\');
+                    output.push(char);
+                }
+                '\n' => output += "\\n",
+                '\r' => output += "\\r",
+                _ => output.push(char),
+            }
+        }
+        output.push(')');
+        output
+    } else {
+        let hex: String = text
+            .encode_utf16()
+            .map(|unit| format!("{:04X}", unit))
+            .collect();
+        format!("<FEFF{}>", hex)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_info_dictionary() {
+        let info = DocumentInfo {
+            title: String::from("Report (draft)"),
+            author: String::from("Jyväskylä Oy"),
+            producer: String::from("WASM-PDF"),
+            creation_date: String::from("2024-05-01T12:30:00+02:00"),
+            mod_date: String::from("2024-05-02"),
+            ..DocumentInfo::default()
+        };
+        assert!(info.validate().is_ok());
+        assert_eq!(
+            info.get_dictionary(),
+            "/Title (Report \\(draft\\)) /Author <FEFF004A0079007600E40073006B0079006C00E40020004F0079> \
+             /Producer (WASM-PDF) /CreationDate (D:20240501123000+02'00') /ModDate (D:20240502000000)"
+        );
+        let xmp = info.get_xmp();
+        assert!(xmp.contains("<rdf:li>Jyväskylä Oy</rdf:li>"));
+        assert!(xmp.contains("<xmp:CreateDate>2024-05-01T12:30:00+02:00</xmp:CreateDate>"));
+        let info = DocumentInfo {
+            mod_date: String::from("2024-05-02 08:15Z"),
+            ..info
+        };
+        assert!(info.validate().is_ok());
+        assert!(info
+            .get_xmp()
+            .contains("<xmp:ModifyDate>2024-05-02T08:15Z</xmp:ModifyDate>"));
+        let invalid = DocumentInfo {
+            creation_date: String::from("1.5.2024"),
+            ..DocumentInfo::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
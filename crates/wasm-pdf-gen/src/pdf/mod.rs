@@ -1,20 +1,47 @@
+mod annotations;
+mod attachments;
 mod canvas;
+pub(crate) mod decoders;
 mod encoders;
+mod encryption;
 mod font;
+mod forms;
 pub mod json;
+mod metadata;
 mod models;
 mod objects;
+mod signature;
 mod styles;
+mod svg;
 mod template;
 mod text;
 mod units;
 
+use attachments::{EmbeddedFile, Relationship};
+use encryption::{Encryption, EncryptionMethod, Permissions};
 use json::JsDocument;
-use models::{Document, Image, Paragraph, Path, Spacer, Stationary, Table};
-use template::PageTemplate;
+use metadata::DocumentInfo;
+use models::{
+    Content, Destination, Document, Endnotes, FormField, Image, ImageCache, Paragraph, Path,
+    Spacer, Stationary, Table, TableOfContents, Transformed,
+};
+use objects::PageMode;
+use signature::{SignatureDetails, Signer};
+use std::rc::Rc;
+use template::{NotesMode, PageFilter, PageNumbering, PageTemplate};
+
+pub use signature::TimestampClient;
 
 /// Create PDF file from JSON input
-pub fn create(js_doc: &JsDocument) -> Result<Vec<u8>, &'static str> {
+pub fn create(js_doc: &JsDocument) -> Result<Vec<u8>, String> {
+    create_with_timestamp(js_doc, None)
+}
+
+/// Create PDF file from JSON input, signatures get a time stamp from the client if given.
+pub fn create_with_timestamp(
+    js_doc: &JsDocument,
+    timestamp_client: Option<Rc<dyn TimestampClient>>,
+) -> Result<Vec<u8>, String> {
     // add document content to template and build
     let mut template = PageTemplate::new(
         js_doc.template.size,
@@ -24,54 +51,205 @@ pub fn create(js_doc: &JsDocument) -> Result<Vec<u8>, &'static str> {
         js_doc.template.bottom,
     );
     let mut doc = Document::new(&js_doc.title);
+    if js_doc.date.is_empty() {
+        doc.set_date(&today());
+    } else {
+        doc.set_date(&js_doc.date);
+    }
+    // document date may be free text for placeholders
+    let creation_date = if !js_doc.creation_date.is_empty() {
+        js_doc.creation_date.clone()
+    } else if metadata::is_date(doc.get_date()) {
+        String::from(doc.get_date())
+    } else {
+        today()
+    };
+    let mod_date = if js_doc.mod_date.is_empty() {
+        creation_date.clone()
+    } else {
+        js_doc.mod_date.clone()
+    };
+    doc.set_info(DocumentInfo {
+        title: js_doc.title.clone(),
+        author: js_doc.author.clone(),
+        subject: js_doc.subject.clone(),
+        keywords: js_doc.keywords.clone(),
+        creator: js_doc.creator.clone(),
+        producer: js_doc.producer.clone(),
+        creation_date,
+        mod_date,
+    })?;
+    doc.set_page_mode(PageMode::from_name(&js_doc.page_mode)?);
+    if let Some(encryption) = &js_doc.encryption {
+        doc.set_encryption(Encryption {
+            user_password: encryption.user_password.clone(),
+            owner_password: encryption.owner_password.clone(),
+            method: EncryptionMethod::from_name(&encryption.method)?,
+            permissions: Permissions {
+                print: encryption.permissions.print,
+                copy: encryption.permissions.copy,
+                modify: encryption.permissions.modify,
+            },
+        });
+    }
+    if let Some(signature) = &js_doc.signature {
+        let mut signer = if signature.pkcs12.is_empty() {
+            Signer::from_pem(&signature.private_key, &signature.certificates)?
+        } else {
+            let data = base64::decode(&signature.pkcs12)
+                .map_err(|_| String::from("Invalid PKCS#12 data, expected base64."))?;
+            Signer::from_pkcs12(&data, &signature.password)?
+        };
+        // signing time defaults to the modification date
+        let date = if signature.date.is_empty() {
+            doc.get_info().mod_date.clone()
+        } else {
+            signature.date.clone()
+        };
+        signer.set_details(SignatureDetails {
+            field: signature.field.clone(),
+            name: signature.name.clone(),
+            reason: signature.reason.clone(),
+            location: signature.location.clone(),
+            contact_info: signature.contact_info.clone(),
+            date,
+        });
+        if let Some(client) = timestamp_client {
+            signer.set_timestamp_client(client);
+        }
+        doc.set_signer(signer);
+    }
+    for attachment in &js_doc.attachments {
+        let name = if attachment.name.is_empty() {
+            attachment.src.rsplit(['/', '\\']).next().unwrap_or("")
+        } else {
+            attachment.name.as_str()
+        };
+        if name.is_empty() {
+            return Err(String::from("Attachment name is missing."));
+        }
+        if attachment.data.is_empty() {
+            return Err(format!("Attachment '{}' has no data.", name));
+        }
+        let data = base64::decode(&attachment.data)
+            .map_err(|_| format!("Invalid data of attachment '{}', expected base64.", name))?;
+        doc.add_attachment(EmbeddedFile::new(
+            name,
+            data,
+            &attachment.mime_type,
+            &attachment.description,
+            Relationship::from_name(&attachment.relationship)?,
+        ));
+    }
+    template.set_notes(NotesMode::from_name(&js_doc.notes)?);
     // parse stationary elements
+    template.set_page_numbering(PageNumbering {
+        start: js_doc.template.page_numbering.start,
+        skip: js_doc.template.page_numbering.skip,
+    });
+    // images placed many times are decoded once
+    let mut images = ImageCache::new();
     for element in &js_doc.stationary {
-        if let "pagenumber" = element.obj_type.to_lowercase().as_str() {
-            let page_number = Stationary::page_number(&element);
-            template.add_stationary(page_number);
-        } else if let "text" = element.obj_type.to_lowercase().as_str() {
-            let text = Stationary::text(&element);
-            template.add_stationary(text);
+        let stationary = match element.obj_type.to_lowercase().as_str() {
+            "pagenumber" => Some(Stationary::page_number(element)?),
+            "text" => Some(Stationary::text(element)?),
+            "image" => Stationary::image(element, js_doc, &mut images)?,
+            "line" => Some(Stationary::line(element)?),
+            "rect" => Some(Stationary::rect(element)?),
+            "path" => Stationary::path(element)?,
+            _ => None,
+        };
+        if let Some(stationary) = stationary {
+            let pages = PageFilter::from_param(element.params.get("pages"))?;
+            template.add_stationary(stationary, pages);
         }
     }
+    if let Some(watermark) = &js_doc.template.watermark {
+        let pages = PageFilter::from_param(watermark.get("pages"))?;
+        template.add_stationary(
+            Stationary::watermark(watermark, js_doc, &mut images)?,
+            pages,
+        );
+    }
     // parse contents of JSON Document
     for content in &js_doc.contents {
-        match content.obj_type.to_lowercase().as_str() {
-            "table" => {
-                if let Some(table) = Table::from_content(&content, &js_doc) {
-                    doc.add(Box::new(table));
-                }
-            }
-            "image" => {
-                if let Some(image) = Image::from_content(&content, &js_doc) {
-                    doc.add(Box::new(image));
-                }
-            }
-            "paragraph" => {
-                let paragraph = Paragraph::from_content(&content);
-                doc.add(Box::new(paragraph));
-            }
-            "spacer" => {
-                let spacer = Spacer::from_content(&content);
-                doc.add(Box::new(spacer));
-            }
-            "path" => {
-                if let Some(path) = Path::from_content(&content) {
-                    doc.add(Box::new(path));
+        // paragraph text is the default outline title
+        let mut title = String::new();
+        let object: Box<dyn Content> =
+            match content.obj_type.to_lowercase().as_str() {
+                "table" => Box::new(Table::from_content(content, js_doc, &mut images)?),
+                "image" => match Image::from_content(content, js_doc, &mut images)? {
+                    Some(image) => {
+                        let align = image.get_style().horizontal_align;
+                        Transformed::from_content(content, Box::new(image), align)
+                    }
+                    None => continue,
+                },
+                "paragraph" => {
+                    let paragraph = Paragraph::from_content(content)?;
+                    title = paragraph.get_plain_text();
+                    let align = paragraph.get_style().align;
+                    Transformed::from_content(content, Box::new(paragraph), align)
                 }
-            }
-            _ => (),
-        }
+                "spacer" => Box::new(Spacer::from_content(&content)),
+                "tableofcontents" => Box::new(TableOfContents::from_content(content)?),
+                "endnotes" => Box::new(Endnotes {}),
+                obj_type @ ("textfield" | "checkbox" | "radiogroup" | "combobox"
+                | "signaturefield") => Box::new(FormField::from_content(content, obj_type)?),
+                "path" => match Path::from_content(content)? {
+                    Some(path) => {
+                        let align = path.get_style().horizontal_align;
+                        Transformed::from_content(content, Box::new(path), align)
+                    }
+                    None => continue,
+                },
+                _ => continue,
+            };
+        doc.add(Destination::from_content(content, object, &title)?);
     }
     // build document -> return bytes
     let bytes = template.build(&doc)?;
     Ok(bytes)
 }
 
+/// Current date as YYYY-MM-DD (UTC). System time is not available in the browser,
+/// where the date is given in JsDocument.
+#[cfg(not(all(target_arch = "wasm32", not(target_os = "wasi"))))]
+fn today() -> String {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    format_date(seconds / 86400)
+}
+
+#[cfg(all(target_arch = "wasm32", not(target_os = "wasi")))]
+fn today() -> String {
+    String::new()
+}
+
+/// Format days since 1970-01-01 as YYYY-MM-DD.
+fn format_date(days: u64) -> String {
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::create;
-    use super::json::JsDocument;
+    use super::font::get_font;
+    use super::json::{JsDocument, JsParamValue};
+    use super::{create, format_date};
     use serde_json;
 
     #[test]
@@ -98,4 +276,386 @@ mod tests {
         };
         assert!(bytes.starts_with(b"%PDF-1.4\n%\x93\x8C\x8B\x9E WASM-PDF library\n"));
     }
+
+    #[test]
+    fn test_internal_links() {
+        let data = r#"
+        {
+            "contents": [
+                {"params": {"text": "See <a href='#results'>results</a> and <a href='#end'>the end</a>."}},
+                {"obj_type": "Spacer", "params": {"height": 900}},
+                {"params": {"text": "Results", "anchor": "results"}},
+                {"params": {"text": "Last words.<a name='end'></a>"}}
+            ]
+        }"#;
+        let js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        assert!(output.contains("/A << /S /GoTo /D /results >>"));
+        assert!(output.contains("/A << /S /GoTo /D /end >>"));
+        assert!(output.contains("/Dests "));
+        // both anchors are on the second page
+        let page_id = output
+            .split("/Kids [ ")
+            .nth(1)
+            .unwrap()
+            .split(' ')
+            .nth(3)
+            .unwrap();
+        assert!(output.contains(&format!("/results [{} 0 R /XYZ 0 791.8898 null]", page_id)));
+        assert!(output.contains(&format!("/end [{} 0 R /XYZ 0 ", page_id)));
+        // a link to a missing anchor is dropped, the document is still created
+        let mut js_doc = js_doc;
+        js_doc.contents.remove(2);
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        assert!(!output.contains("/A << /S /GoTo /D /results >>"));
+        assert!(output.contains("/A << /S /GoTo /D /end >>"));
+        assert_eq!(output.matches("/Subtype /Link").count(), 1);
+    }
+
+    /// Reference to the first object whose dictionary contains the entries.
+    fn object_ref(output: &str, entries: &str) -> String {
+        let end = output.find(entries).unwrap();
+        let start = output[..end].rfind(" 0 obj").unwrap();
+        let id = output[..start].rsplit('\n').next().unwrap();
+        format!("{} 0 R", id)
+    }
+
+    /// Dictionary of the referenced object.
+    fn object<'a>(output: &'a str, reference: &str) -> &'a str {
+        let header = format!("\n{} obj\n", reference.trim_end_matches(" R"));
+        let start = output.find(&header).unwrap() + header.len();
+        let end = start + output[start..].find("endobj").unwrap();
+        &output[start..end]
+    }
+
+    /// References in the array of the key in the dictionary.
+    fn ref_array(dictionary: &str, key: &str) -> Vec<String> {
+        let key = format!("{} [ ", key);
+        let start = dictionary.find(&key).unwrap() + key.len();
+        let end = start + dictionary[start..].find(']').unwrap();
+        dictionary[start..end]
+            .split(" R")
+            .map(str::trim)
+            .filter(|reference| !reference.is_empty())
+            .map(|reference| format!("{} R", reference))
+            .collect()
+    }
+
+    /// Decoded content streams of the pages, in page order.
+    fn page_contents(output: &str) -> Vec<String> {
+        let mut pages = Vec::new();
+        for part in output
+            .split("/Filter [/ASCII85Decode /FlateDecode]")
+            .skip(1)
+        {
+            let start = part.find("stream\n").unwrap() + 7;
+            let end = part.find("~>").unwrap();
+            let mut data = Vec::new();
+            let mut group: Vec<u32> = Vec::new();
+            for byte in part[start..end]
+                .bytes()
+                .filter(|byte| !byte.is_ascii_whitespace())
+            {
+                if byte == b'z' {
+                    data.extend_from_slice(&[0, 0, 0, 0]);
+                    continue;
+                }
+                group.push(u32::from(byte - 33));
+                if group.len() == 5 {
+                    let value = group.iter().fold(0u32, |value, digit| value * 85 + digit);
+                    data.extend_from_slice(&value.to_be_bytes());
+                    group.clear();
+                }
+            }
+            if !group.is_empty() {
+                let padding = 5 - group.len();
+                group.resize(5, 84);
+                let value = group.iter().fold(0u32, |value, digit| value * 85 + digit);
+                data.extend_from_slice(&value.to_be_bytes()[..4 - padding]);
+            }
+            let data = inflate::inflate_bytes_zlib(&data).unwrap();
+            pages.push(String::from_utf8_lossy(&data).into_owned());
+        }
+        pages
+    }
+
+    #[test]
+    fn test_table_of_contents_page_numbers() {
+        let mut contents = vec![serde_json::json!({"obj_type": "TableOfContents", "params": {}})];
+        for chapter in 1..=60 {
+            contents.push(serde_json::json!({
+                "params": {
+                    "text": format!("Chapter {}", chapter),
+                    "outline": {"title": format!("Entry {}", chapter)}
+                }
+            }));
+            contents.push(serde_json::json!({"obj_type": "Spacer", "params": {"height": 350}}));
+        }
+        let data = serde_json::json!({ "contents": contents }).to_string();
+        let js_doc: JsDocument = serde_json::from_str(&data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let pages = page_contents(&String::from_utf8_lossy(&bytes));
+        // the table of contents takes two pages and pushes the chapters forward
+        assert!(pages[1].contains("(Entry 60) Tj"));
+        assert!(pages[1].contains("(Chapter 1) Tj"));
+        let toc = format!("{}{}", pages[0], pages[1]);
+        for chapter in 1..=60 {
+            let page = pages
+                .iter()
+                .position(|page| page.contains(&format!("(Chapter {}) Tj", chapter)))
+                .unwrap()
+                + 1;
+            let entry = toc
+                .split(&format!("(Entry {}) Tj", chapter))
+                .nth(1)
+                .unwrap();
+            // title, dot leaders and page number
+            let number = entry.split(") Tj").nth(1).unwrap();
+            assert!(
+                number.ends_with(&format!("({}", page)),
+                "chapter {}",
+                chapter
+            );
+        }
+    }
+
+    #[test]
+    fn test_transformed_links() {
+        let data = r#"
+        {
+            "contents": [
+                {"params": {"text": "<a href='https://example.com'>Example site</a>", "rotate": 90}}
+            ]
+        }"#;
+        let js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        let rect: Vec<f32> = output
+            .split("/Rect [ ")
+            .nth(1)
+            .unwrap()
+            .split(' ')
+            .take(4)
+            .map(|value| value.parse().unwrap())
+            .collect();
+        // the link follows the text turned upright: one line wide, text width high
+        let (width, height) = (rect[2] - rect[0], rect[3] - rect[1]);
+        assert!((width - 14.0).abs() < 0.01);
+        assert!(height > 60.0);
+    }
+
+    #[test]
+    fn test_transformed_content_below_footnotes() {
+        let data = r#"
+        {
+            "template": {"size": [600, 800], "top": 50, "left": 50, "right": 50, "bottom": 50},
+            "contents": [
+                {"params": {"text": "Text<footnote>Note</footnote>"}},
+                {"params": {"text": "Alphabetical Alphabetical Alphabetical Alphabetical Alphabetical Alphabetical Alphabetical", "leading": 97, "padding": {"right": 400}, "rotate": 90}}
+            ]
+        }"#;
+        let mut js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let pages = page_contents(&String::from_utf8_lossy(&bytes));
+        // the rotated paragraph is laid out taller than the space above the
+        // footnote, it moves to the next page as a whole
+        assert_eq!(pages.len(), 2);
+        for page in &pages {
+            let count =
+                |operator: &str| page.split_whitespace().filter(|op| *op == operator).count();
+            assert_eq!(count("q"), count("Q"));
+        }
+        assert!(pages[1].contains(" cm"));
+        js_doc.contents[1].params.insert(
+            String::from("text"),
+            JsParamValue::Text(String::from("Rotated<footnote>Note</footnote>")),
+        );
+        assert_eq!(
+            create(&js_doc).err().unwrap(),
+            "Footnotes are not supported in transformed content, use endnotes."
+        );
+    }
+
+    #[test]
+    fn test_links_of_aligned_text() {
+        let data = r#"
+        {
+            "template": {"size": [600, 800], "top": 50, "left": 50, "right": 50, "bottom": 50},
+            "contents": [
+                {"params": {"text": "<a href='https://example.com'>Example</a>", "font_size": 10, "padding": {"left": 20, "right": 30}}},
+                {"params": {"text": "<a href='https://example.com'>Example</a>", "font_size": 10, "padding": {"left": 20, "right": 30}, "align": "center"}},
+                {"params": {"text": "<a href='https://example.com'>Example</a>", "font_size": 10, "padding": {"left": 20, "right": 30}, "align": "right"}}
+            ]
+        }"#;
+        let js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        let links: Vec<(f32, f32)> = output
+            .split("/Rect [ ")
+            .skip(1)
+            .filter(|part| part.contains("] /Subtype /Link"))
+            .map(|part| {
+                let rect: Vec<f32> = part
+                    .split(' ')
+                    .take(4)
+                    .map(|value| value.parse().unwrap())
+                    .collect();
+                (rect[0], rect[2])
+            })
+            .collect();
+        // the links start where the text is placed within the padding
+        let width = get_font("helvetica").get_width(10.0, "Example");
+        let expected = [70.0, 70.0 + (450.0 - width) / 2.0, 520.0 - width];
+        assert_eq!(links.len(), expected.len());
+        for ((x1, x2), x) in links.iter().zip(expected.iter()) {
+            assert!((x1 - x).abs() < 0.01);
+            assert!((x2 - x - width).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_form_fields() {
+        let data = r#"
+        {
+            "contents": [
+                {"obj_type": "TextField", "params": {"name": "name", "value": "Jane", "required": true, "max_length": 20}},
+                {"obj_type": "CheckBox", "params": {"name": "agree", "checked": true, "label": "I agree"}},
+                {"obj_type": "RadioGroup", "params": {"name": "size", "options": ["S", "M"], "value": "M"}},
+                {"obj_type": "ComboBox", "params": {"name": "country", "options": ["FI", "SE"], "read_only": true}},
+                {"obj_type": "SignatureField", "params": {"name": "signature"}},
+                {"obj_type": "TextField", "params": {"name": "pin", "value": "1234", "password": true}}
+            ]
+        }"#;
+        let js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        let fields: Vec<String> = ["name", "agree", "size", "country", "signature", "pin"]
+            .iter()
+            .map(|name| object_ref(&output, &format!("/T ({}) ", name)))
+            .collect();
+        let catalog = object(&output, &object_ref(&output, "/Type /Catalog"));
+        assert_eq!(ref_array(catalog, "/AcroForm << /Fields"), fields);
+        assert!(output.contains("/FT /Tx /T (name) /Ff 2 "));
+        assert!(output.contains("/V (Jane) /DV (Jane) /MaxLen 20"));
+        let check_box = object(&output, &fields[1]);
+        assert!(check_box.contains("/AP << /N << /Yes "));
+        assert!(check_box.contains(" >> >> /AS /Yes"));
+        // the radio buttons are the widgets of the group
+        let buttons = ref_array(object(&output, &fields[2]), "/Kids");
+        assert_eq!(buttons.len(), 2);
+        for button in &buttons {
+            assert!(object(&output, button).contains(&format!("/Parent {} ", fields[2])));
+        }
+        assert!(output.contains("/FT /Ch /T (country) /Ff 131073 "));
+        assert!(output.contains("/FT /Sig /T (signature)"));
+        // the password is only shown masked
+        assert!(output.contains("/FT /Tx /T (pin) /Ff 8192 "));
+        assert!(!output.contains("(1234)"));
+        assert!(output.contains("(****) Tj"));
+        let page = object(&output, &object_ref(&output, "/Type /Page\n"));
+        let mut widgets = fields.clone();
+        widgets.splice(2..3, buttons);
+        assert_eq!(ref_array(page, "/Annots"), widgets);
+        let mut duplicate: JsDocument = serde_json::from_str(data).unwrap();
+        duplicate.contents.push(duplicate.contents[0].clone());
+        assert!(create(&duplicate).is_err());
+        let invalid = r#"{"contents": [{"obj_type": "RadioGroup", "params": {"name": "size", "options": ["S"], "value": "XL"}}]}"#;
+        let invalid: JsDocument = serde_json::from_str(invalid).unwrap();
+        assert!(create(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_annotations() {
+        let data = r#"
+        {
+            "contents": [
+                {"params": {"text": "Total <annot type='highlight' author='Jane' contents='Check VAT' open='true'>100 EUR</annot>."}},
+                {"params": {"text": "Terms", "annotation": {"type": "note", "contents": "Rewrite", "color": [1, 0.5, 0]}}}
+            ]
+        }"#;
+        let js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        let highlight = object_ref(&output, "/Subtype /Highlight /F 4 ");
+        let note = object_ref(&output, "/Subtype /Text /F 4 ");
+        assert!(object(&output, &highlight)
+            .contains("/T (Jane) /Contents (Check VAT) /C [ 1 1 0 ] /QuadPoints"));
+        assert!(object(&output, &note).contains("/C [ 1 0.5 0 ] /Name /Comment /Open false"));
+        // each annotation has a pop-up that refers back to it
+        let mut annotations = Vec::new();
+        for parent in [highlight, note] {
+            let dictionary = object(&output, &parent);
+            let popup_start = dictionary.find("/Popup ").unwrap() + 7;
+            let popup_end = popup_start + dictionary[popup_start..].find(" R").unwrap() + 2;
+            let popup = String::from(&dictionary[popup_start..popup_end]);
+            assert!(
+                object(&output, &popup).contains(&format!("/Subtype /Popup /Parent {} ", parent))
+            );
+            annotations.push(parent);
+            annotations.push(popup);
+        }
+        let page = object(&output, &object_ref(&output, "/Type /Page\n"));
+        assert_eq!(ref_array(page, "/Annots"), annotations);
+        let invalid =
+            r#"{"contents": [{"params": {"text": "<annot type='circle'>Total</annot>"}}]}"#;
+        let invalid: JsDocument = serde_json::from_str(invalid).unwrap();
+        assert!(create(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_attachments() {
+        let data = r#"
+        {
+            "page_mode": "attachments",
+            "attachments": [
+                {"name": "notes.txt", "data": "aGVsbG8=", "mime_type": "text/plain"},
+                {"name": "factur-x.xml", "data": "PHhtbC8+", "mime_type": "text/xml", "relationship": "alternative"}
+            ],
+            "contents": [{"params": {"text": "See <annot type='file' file='factur-x.xml'>invoice</annot>."}}]
+        }"#;
+        let mut js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        assert!(output.contains("/Type /EmbeddedFile /Subtype /text#2Fxml /Params << /Size 6 "));
+        assert!(output.contains("/EF << /F 5 0 R /UF 5 0 R >> /AFRelationship /Alternative"));
+        assert!(output.contains(
+            "/Names << /EmbeddedFiles << /Names [ (factur-x.xml) 6 0 R (notes.txt) 4 0 R ] >> >> /AF [ 4 0 R 6 0 R ]"
+        ));
+        assert!(output.contains("/FS 6 0 R /Name /Paperclip"));
+        assert!(output.contains("/PageMode /UseAttachments"));
+        js_doc.attachments[0].name = String::from("factur-x.xml");
+        assert!(create(&js_doc).is_err());
+        js_doc.attachments[0].name = String::from("notes.txt");
+        js_doc.attachments[1].name = String::from("invoice.xml");
+        assert!(create(&js_doc).is_err());
+    }
+
+    #[test]
+    fn test_encryption() {
+        let data = r#"
+        {
+            "title": "Payslip",
+            "contents": [{"params": {"text": "Salary"}}],
+            "encryption": {"user_password": "secret", "method": "rc4", "permissions": {"copy": false}}
+        }"#;
+        let mut js_doc: JsDocument = serde_json::from_str(data).unwrap();
+        let bytes = create(&js_doc).unwrap();
+        let output = String::from_utf8_lossy(&bytes);
+        assert!(output.contains("/Filter /Standard /V 2 /R 3 /Length 128 "));
+        assert!(output.contains("/P -20"));
+        assert!(output.contains("/Encrypt 9 0 R /ID [<"));
+        assert!(!output.contains("(Payslip)"));
+        assert!(!output.contains("<?xpacket"));
+        js_doc.encryption.as_mut().unwrap().method = String::from("des");
+        assert!(create(&js_doc).is_err());
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11_016), "2000-02-29");
+        assert_eq!(format_date(19_782), "2024-02-29");
+    }
 }
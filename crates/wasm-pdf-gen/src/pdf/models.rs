@@ -1,12 +1,27 @@
 #![allow(dead_code)]
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::annotations::Markup;
+use super::attachments::EmbeddedFile;
 use super::canvas::Canvas;
+use super::decoders::{self, ImageData};
+use super::encryption::Encryption;
 use super::font::{get_font, Font};
-use super::styles::{CellStyle, Color, ImageStyle, ParagraphStyle, PathStyle, TableStyle};
-use super::text::TextSpan;
-use super::units::Point;
+use super::forms::FieldKind;
+use super::metadata::DocumentInfo;
+use super::objects::PageMode;
+use super::signature::Signer;
+use super::styles::{
+    CellStyle, Clip, Color, Fill, HorizontalAlign, ImageStyle, ParagraphStyle, PathStyle,
+    TableStyle, Transform, Transparency,
+};
+use super::text::{check_annotations, Tag, TextSpan};
+use super::units::{Line, Point, Rect};
 
 use super::json::{
-    get_bool_from_js, get_number_from_js, get_text_from_js, JsContent, JsDocument, JsParamValue,
+    get_bool_from_js, get_number_from_js, get_text_from_js, to_js_string, JsContent, JsDocument,
+    JsParamValue,
 };
 
 pub enum ContentType {
@@ -15,6 +30,9 @@ pub enum ContentType {
     Spacer,
     Table,
     Path,
+    TableOfContents,
+    Endnotes,
+    FormField,
 }
 
 // Content Trait is the center piece here.
@@ -37,6 +55,10 @@ pub enum Stationary {
         x: f32,
         y: f32,
         color: Color,
+        /// Text starts, ends or is centered at x
+        align: HorizontalAlign,
+        transparency: Transparency,
+        transform: Transform,
     },
     Text {
         text: String,
@@ -45,27 +67,77 @@ pub enum Stationary {
         x: f32,
         y: f32,
         color: Color,
+        /// Text starts, ends or is centered at x
+        align: HorizontalAlign,
+        transparency: Transparency,
+        transform: Transform,
+    },
+    /// Image box with its bottom left corner at x, y
+    Image {
+        image: Rc<Image>,
+        x: f32,
+        y: f32,
+        transform: Transform,
+    },
+    Line {
+        line: Line,
+        color: Color,
+        width: f32,
+        transparency: Transparency,
+    },
+    /// Rectangle with optional rounded corners, top left corner in rect x and y
+    Rect {
+        rect: Rect,
+        radius: f32,
+        stroke_color: Option<Color>,
+        stroke_width: f32,
+        fill: Option<Fill>,
+        transparency: Transparency,
+        transform: Transform,
+    },
+    /// Path with points in page coordinates
+    Path {
+        path: Rc<Path>,
+        transform: Transform,
+    },
+    /// Text or image rotated around its centre, at the page centre unless x and y are given.
+    /// Drawn behind the page contents or above them.
+    Watermark {
+        text: String,
+        image: Option<Rc<Image>>,
+        font_size: f32,
+        font: &'static Font,
+        color: Color,
+        transparency: Transparency,
+        transform: Transform,
+        center: Option<Point>,
+        above: bool,
     },
 }
 
 impl Stationary {
-    pub fn page_number(content: &JsContent) -> Stationary {
+    pub fn page_number(content: &JsContent) -> Result<Stationary, String> {
         let p_font_name = get_text_from_js(content.params.get("font_name"), "Helvetica");
         let font_size = get_number_from_js(content.params.get("font_size"), 12.0);
         let x = get_number_from_js(content.params.get("x"), 50.0);
         let y = get_number_from_js(content.params.get("y"), 50.0);
         let font = get_font(p_font_name.to_lowercase().as_str());
         let color =
-            Color::from_param_or_default(content.params.get("color"), Color::new(0.0, 0.0, 0.0));
-        Stationary::PageNumber {
+            Color::from_param_or_default(content.params.get("color"), Color::new(0.0, 0.0, 0.0))?;
+        let transparency = Transparency::from_content(content)?;
+        let transform = Transform::from_content(content);
+        Ok(Stationary::PageNumber {
             font,
             font_size,
             x,
             y,
             color,
-        }
+            align: HorizontalAlign::from_content(content),
+            transparency,
+            transform,
+        })
     }
-    pub fn text(content: &JsContent) -> Stationary {
+    pub fn text(content: &JsContent) -> Result<Stationary, String> {
         let text = get_text_from_js(content.params.get("text"), "");
         let p_font_name = get_text_from_js(content.params.get("font_name"), "Helvetica");
         let font_size = get_number_from_js(content.params.get("font_size"), 12.0);
@@ -73,20 +145,156 @@ impl Stationary {
         let y = get_number_from_js(content.params.get("y"), 50.0);
         let font = get_font(p_font_name.to_lowercase().as_str());
         let color =
-            Color::from_param_or_default(content.params.get("color"), Color::new(0.0, 0.0, 0.0));
-        Stationary::Text {
+            Color::from_param_or_default(content.params.get("color"), Color::new(0.0, 0.0, 0.0))?;
+        let transparency = Transparency::from_content(content)?;
+        let transform = Transform::from_content(content);
+        Ok(Stationary::Text {
             text,
             font,
             font_size,
             x,
             y,
             color,
+            align: HorizontalAlign::from_content(content),
+            transparency,
+            transform,
+        })
+    }
+    pub fn image(
+        content: &JsContent,
+        js_doc: &JsDocument,
+        images: &mut ImageCache,
+    ) -> Result<Option<Stationary>, String> {
+        let x = get_number_from_js(content.params.get("x"), 50.0);
+        let y = get_number_from_js(content.params.get("y"), 50.0);
+        let transform = Transform::from_content(content);
+        Ok(
+            Image::from_content(content, js_doc, images)?.map(|image| Stationary::Image {
+                image: Rc::new(image),
+                x,
+                y,
+                transform,
+            }),
+        )
+    }
+    pub fn line(content: &JsContent) -> Result<Stationary, String> {
+        let line = Line {
+            x: get_number_from_js(content.params.get("x"), 50.0),
+            y: get_number_from_js(content.params.get("y"), 50.0),
+            x2: get_number_from_js(content.params.get("x2"), 50.0),
+            y2: get_number_from_js(content.params.get("y2"), 50.0),
+        };
+        let color = Color::from_param_or_default(
+            content.params.get("stroke_color"),
+            Color::new(0.0, 0.0, 0.0),
+        )?;
+        let width = get_number_from_js(content.params.get("stroke_width"), 1.0);
+        let transparency = Transparency::from_content(content)?;
+        Ok(Stationary::Line {
+            line,
+            color,
+            width,
+            transparency,
+        })
+    }
+    pub fn rect(content: &JsContent) -> Result<Stationary, String> {
+        let x = get_number_from_js(content.params.get("x"), 50.0);
+        let y = get_number_from_js(content.params.get("y"), 50.0);
+        let width = get_number_from_js(content.params.get("width"), 0.0);
+        let height = get_number_from_js(content.params.get("height"), 0.0);
+        let stroke_color = if let Some(color) = content.params.get("stroke_color") {
+            Some(Color::from_param(color)?)
+        } else {
+            None
+        };
+        let fill = if let Some(color) = content.params.get("fill_color") {
+            Some(Fill::from_param(color)?)
+        } else {
+            None
+        };
+        Ok(Stationary::Rect {
+            rect: Rect::new(x, y + height, width, height),
+            radius: get_number_from_js(content.params.get("radius"), 0.0),
+            stroke_color,
+            stroke_width: get_number_from_js(content.params.get("stroke_width"), 0.0),
+            fill,
+            transparency: Transparency::from_content(content)?,
+            transform: Transform::from_content(content),
+        })
+    }
+    pub fn watermark(
+        params: &HashMap<String, JsParamValue>,
+        js_doc: &JsDocument,
+        images: &mut ImageCache,
+    ) -> Result<Stationary, String> {
+        let mut content = JsContent {
+            obj_type: String::from("Watermark"),
+            params: params.clone(),
+        };
+        // opacity applies to both text and image, watermarks are translucent by default
+        if !content.params.contains_key("fill_opacity") {
+            let opacity = get_number_from_js(content.params.get("opacity"), 0.3);
+            content
+                .params
+                .insert(String::from("fill_opacity"), JsParamValue::Number(opacity));
         }
+        let image = Image::from_content(&content, js_doc, images)?.map(Rc::new);
+        let text = get_text_from_js(content.params.get("text"), "");
+        if image.is_none() && text.is_empty() {
+            return Err("Watermark needs text or an image (src).".to_string());
+        }
+        let p_font_name = get_text_from_js(content.params.get("font_name"), "Helvetica-Bold");
+        let font_size = get_number_from_js(content.params.get("font_size"), 72.0);
+        let color =
+            Color::from_param_or_default(content.params.get("color"), Color::new(0.5, 0.5, 0.5))?;
+        let mut transform = Transform::new();
+        transform.rotate = get_number_from_js(content.params.get("angle"), 45.0);
+        let center = match (content.params.get("x"), content.params.get("y")) {
+            (Some(JsParamValue::Number(x)), Some(JsParamValue::Number(y))) => {
+                Some(Point { x: *x, y: *y })
+            }
+            _ => None,
+        };
+        let position = get_text_from_js(content.params.get("position"), "behind");
+        let above = match position.to_lowercase().as_str() {
+            "above" => true,
+            "behind" => false,
+            _ => {
+                return Err(format!(
+                    "Invalid watermark position '{}', expected behind or above.",
+                    position
+                ))
+            }
+        };
+        Ok(Stationary::Watermark {
+            text,
+            image,
+            font_size,
+            font: get_font(p_font_name.to_lowercase().as_str()),
+            color,
+            transparency: Transparency::from_content(&content)?,
+            transform,
+            center,
+            above,
+        })
+    }
+    pub fn path(content: &JsContent) -> Result<Option<Stationary>, String> {
+        let transform = Transform::from_content(content);
+        Ok(Path::from_content(content)?.map(|path| Stationary::Path {
+            path: Rc::new(path),
+            transform,
+        }))
     }
 }
 
 pub struct Document {
     title: String,
+    date: String,
+    info: DocumentInfo,
+    page_mode: PageMode,
+    encryption: Option<Encryption>,
+    signer: Option<Signer>,
+    attachments: Vec<EmbeddedFile>,
     content: Vec<Box<dyn Content>>,
 }
 
@@ -94,9 +302,56 @@ impl Document {
     pub fn new(title: &str) -> Document {
         Document {
             title: String::from(title),
+            date: String::new(),
+            info: DocumentInfo::default(),
+            page_mode: PageMode::None,
+            encryption: None,
+            signer: None,
+            attachments: Vec::new(),
             content: Vec::new(),
         }
     }
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+    pub fn set_date(&mut self, date: &str) {
+        self.date = String::from(date);
+    }
+    pub fn get_date(&self) -> &str {
+        &self.date
+    }
+    pub fn set_info(&mut self, info: DocumentInfo) -> Result<(), String> {
+        info.validate()?;
+        self.info = info;
+        Ok(())
+    }
+    pub fn get_info(&self) -> &DocumentInfo {
+        &self.info
+    }
+    pub fn set_page_mode(&mut self, page_mode: PageMode) {
+        self.page_mode = page_mode;
+    }
+    pub fn get_page_mode(&self) -> PageMode {
+        self.page_mode
+    }
+    pub fn set_encryption(&mut self, encryption: Encryption) {
+        self.encryption = Some(encryption);
+    }
+    pub fn get_encryption(&self) -> Option<&Encryption> {
+        self.encryption.as_ref()
+    }
+    pub fn set_signer(&mut self, signer: Signer) {
+        self.signer = Some(signer);
+    }
+    pub fn get_signer(&self) -> Option<&Signer> {
+        self.signer.as_ref()
+    }
+    pub fn add_attachment(&mut self, attachment: EmbeddedFile) {
+        self.attachments.push(attachment);
+    }
+    pub fn get_attachments(&self) -> &[EmbeddedFile] {
+        &self.attachments
+    }
     pub fn add(&mut self, object: Box<dyn Content>) {
         self.content.push(object);
     }
@@ -110,6 +365,10 @@ pub struct Paragraph {
     font: &'static Font,
     style: ParagraphStyle,
     spans: Vec<TextSpan>,
+    /// Named mark (e.g. chapter) set to the text when the paragraph is drawn
+    mark: Option<(String, String)>,
+    /// Review comment on the whole paragraph
+    annotation: Option<Markup>,
 }
 
 impl Paragraph {
@@ -120,8 +379,30 @@ impl Paragraph {
             font: get_font(font_name.to_lowercase().as_str()),
             style,
             spans: text_spans,
+            mark: None,
+            annotation: None,
         }
     }
+    /// Footnote or endnote body, starting with the note number in superscript.
+    pub fn note(number: u16, text: &str, font: &'static Font, font_size: f32) -> Paragraph {
+        let style =
+            ParagraphStyle::new(font_size + 2.0, HorizontalAlign::Left, (0.0, 0.0, 0.0, 0.0));
+        let mut paragraph = Paragraph::new(text, &font.get_name(), font_size, style);
+        paragraph
+            .spans
+            .insert(0, TextSpan::new(&number.to_string(), Tag::Superscript));
+        paragraph
+    }
+    pub fn get_mark(&self) -> Option<&(String, String)> {
+        self.mark.as_ref()
+    }
+    pub fn get_annotation(&self) -> Option<&Markup> {
+        self.annotation.as_ref()
+    }
+    /// Text without tags
+    pub fn get_plain_text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
     pub fn get_font_size(&self) -> f32 {
         self.font_size
     }
@@ -134,12 +415,26 @@ impl Paragraph {
     pub fn get_spans(&self) -> &Vec<TextSpan> {
         &self.spans
     }
-    pub fn from_content(content: &JsContent) -> Paragraph {
+    pub fn from_content(content: &JsContent) -> Result<Paragraph, String> {
         let p_font_name = get_text_from_js(content.params.get("font_name"), "Helvetica");
         let p_font_size = get_number_from_js(content.params.get("font_size"), 12.0);
-        let p_style = ParagraphStyle::from_content(&content, p_font_size);
+        let p_style = ParagraphStyle::from_content(content, p_font_size)?;
         let text_value = get_text_from_js(content.params.get("text"), "");
-        Paragraph::new(&text_value, &p_font_name, p_font_size, p_style)
+        check_annotations(&text_value)?;
+        let mut paragraph = Paragraph::new(&text_value, &p_font_name, p_font_size, p_style);
+        if let Some(JsParamValue::Text(name)) = content.params.get("mark") {
+            let text = get_text_from_js(content.params.get("mark_text"), "");
+            let text = if text.is_empty() {
+                paragraph.get_plain_text()
+            } else {
+                text
+            };
+            paragraph.mark = Some((name.to_owned(), text));
+        }
+        if let Some(annotation) = content.params.get("annotation") {
+            paragraph.annotation = Some(Markup::from_param(annotation)?);
+        }
+        Ok(paragraph)
     }
 
     /// Generate wrapped text spans, a line may contain multiple spans
@@ -179,6 +474,9 @@ impl Paragraph {
                     if !span_text.is_empty() {
                         let text_span = TextSpan::new(&span_text.trim_end(), span.tag.clone());
                         line_spans.push(text_span);
+                    }
+                    // the line may end with previous spans
+                    if !line_spans.is_empty() {
                         wrapped.push(line_spans);
                     }
                     line = String::new();
@@ -189,11 +487,19 @@ impl Paragraph {
                     span_text += " ";
                 }
             }
-            if !span_text.is_empty() || next_word != None {
+            // anchors and note references are kept without text
+            let is_marker = matches!(span.tag, Tag::Anchor { .. } | Tag::Footnote { .. });
+            if !span_text.is_empty() || next_word != None || is_marker {
                 if let Some(_next_word) = next_word {
                     span_text += &_next_word;
                 }
-                if i == num_spans - 1 {
+                // note references follow the text directly
+                let before_note = !span.text.ends_with(char::is_whitespace)
+                    && matches!(
+                        self.spans.get(i + 1).map(|next| &next.tag),
+                        Some(Tag::Footnote { .. })
+                    );
+                if i == num_spans - 1 || before_note {
                     // Remove trailing space from last span
                     span_text = span_text.trim_end().to_owned();
                 }
@@ -207,11 +513,10 @@ impl Paragraph {
         wrapped
     }
 
+    /// Size of the wrapped lines including padding and bullet indent.
     pub fn wrapped_size(&self, wrapped: &[Vec<TextSpan>]) -> (f32, f32) {
         let vertical_padding = self.style.padding.0 + self.style.padding.2;
-        if !self.style.wrap {
-            return (f32::MAX, self.style.leading + vertical_padding);
-        }
+        let horizontal_padding = self.style.padding.1 + self.style.padding.3;
         let mut width: f32 = 0.0;
         for line in wrapped {
             let mut max_line: f32 = 0.0;
@@ -222,8 +527,12 @@ impl Paragraph {
                 width = max_line;
             }
         }
-        let height = self.style.leading * wrapped.len() as f32 + vertical_padding;
-        (width, height)
+        let lines = if self.style.wrap { wrapped.len() } else { 1 };
+        let height = self.style.leading * lines as f32 + vertical_padding;
+        (
+            width + horizontal_padding + self.style.bullet_indent,
+            height,
+        )
     }
 }
 
@@ -238,8 +547,9 @@ impl Content for Paragraph {
         canvas.draw_text(&self, &wrapped, available_width)
     }
     fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
-        // Calculate width and height according to wrapped
-        let wrapped = self.wrap_to_width(area.0);
+        // Calculate width and height according to wrapped, same as in draw
+        let horizontal_padding = self.style.padding.1 + self.style.padding.3;
+        let wrapped = self.wrap_to_width(area.0 - horizontal_padding - self.style.bullet_indent);
         self.wrapped_size(&wrapped)
     }
     fn content_type(&self) -> ContentType {
@@ -278,20 +588,55 @@ impl Content for Spacer {
     }
 }
 
+/// Images decoded from `image_data`, each source is decoded once
+/// however many times it is placed.
+#[derive(Default)]
+pub struct ImageCache {
+    images: HashMap<String, Rc<ImageData>>,
+}
+
+impl ImageCache {
+    pub fn new() -> ImageCache {
+        ImageCache::default()
+    }
+    /// Decoded image of the source, the data must be in `image_data`.
+    pub fn get(&mut self, src: &str, js_doc: &JsDocument) -> Result<Rc<ImageData>, String> {
+        if let Some(image) = self.images.get(src) {
+            return Ok(Rc::clone(image));
+        }
+        let image_data_str = js_doc
+            .image_data
+            .get(src)
+            .ok_or_else(|| format!("Missing data of image '{}'.", src))?;
+        let image_bytes = base64::decode(image_data_str)
+            .map_err(|err| format!("Invalid image data for '{}': {}", src, err))?;
+        let image = Rc::new(
+            decoders::decode(&image_bytes)
+                .map_err(|err| format!("Error in image '{}': {}", src, err))?,
+        );
+        self.images.insert(String::from(src), Rc::clone(&image));
+        Ok(image)
+    }
+}
+
+#[derive(Debug)]
 pub struct Image {
-    data: Vec<u8>,
+    data: Rc<ImageData>,
+    /// Size of the image box in points
     width: f32,
     height: f32,
     fit_width: bool,
+    shrink_to_page: bool,
     style: ImageStyle,
 }
 
 impl Image {
     pub fn new(
-        data: Vec<u8>,
+        data: Rc<ImageData>,
         width: f32,
         height: f32,
         fit_width: bool,
+        shrink_to_page: bool,
         style: ImageStyle,
     ) -> Image {
         Image {
@@ -299,26 +644,37 @@ impl Image {
             width,
             height,
             fit_width,
+            shrink_to_page,
             style,
         }
     }
-    pub fn get_data(&self) -> &Vec<u8> {
+    pub fn get_data(&self) -> &Rc<ImageData> {
         &self.data
     }
     pub fn get_dimensions(&self) -> (f32, f32) {
         (self.width, self.height)
     }
-    pub fn fits_width(&self) -> bool {
-        self.fit_width
+    /// Size of the image box when drawn in the available width.
+    pub fn get_size(&self, available_width: f32) -> (f32, f32) {
+        if self.fit_width || (self.shrink_to_page && self.width > available_width) {
+            (available_width, available_width / self.width * self.height)
+        } else {
+            (self.width, self.height)
+        }
     }
     pub fn get_style(&self) -> &ImageStyle {
         &self.style
     }
-    pub fn from_content(content: &JsContent, js_doc: &JsDocument) -> Option<Image> {
+    pub fn from_content(
+        content: &JsContent,
+        js_doc: &JsDocument,
+        images: &mut ImageCache,
+    ) -> Result<Option<Image>, String> {
         let fit_width = get_bool_from_js(content.params.get("fit_width"), false);
+        let shrink_to_page = get_bool_from_js(content.params.get("shrink_to_page"), false);
         if let Some(src) = content.params.get("src") {
             if let JsParamValue::Text(s) = src {
-                if let Some(image_data_str) = js_doc.image_data.get(s) {
+                if js_doc.image_data.contains_key(s) {
                     let p_width = if let Some(width) = js_doc.image_widths.get(s) {
                         *width
                     } else {
@@ -329,14 +685,82 @@ impl Image {
                     } else {
                         0.0
                     };
-                    let image_data = base64::decode(&image_data_str).unwrap();
-                    let image_style = ImageStyle::from_content(&content);
-                    let image = Image::new(image_data, p_width, p_height, fit_width, image_style);
-                    return Some(image);
+                    let image_data = images.get(s, js_doc)?;
+                    // pixel size is read from the image when not given,
+                    // pixels are converted to points with the image resolution
+                    let (natural_width, natural_height) = image_data.get_natural_size();
+                    let (dpi_x, dpi_y) = image_data.get_display_dpi();
+                    let natural_width = if p_width > 0.0 {
+                        p_width * 72.0 / dpi_x
+                    } else {
+                        natural_width
+                    };
+                    let natural_height = if p_height > 0.0 {
+                        p_height * 72.0 / dpi_y
+                    } else {
+                        natural_height
+                    };
+                    let (width, height) = Image::get_box_size(
+                        content,
+                        js_doc,
+                        (natural_width, natural_height),
+                        shrink_to_page,
+                    );
+                    let image_style = ImageStyle::from_content(content)?;
+                    let image = Image::new(
+                        image_data,
+                        width,
+                        height,
+                        fit_width,
+                        shrink_to_page,
+                        image_style,
+                    );
+                    return Ok(Some(image));
                 }
             }
         }
-        None
+        Ok(None)
+    }
+    /// Image box size from `width`, `height`, `max_width` and `max_height` params.
+    /// A missing side is calculated from the aspect ratio of the image.
+    /// The box is scaled down to the template frame with shrink_to_page.
+    fn get_box_size(
+        content: &JsContent,
+        js_doc: &JsDocument,
+        natural_size: (f32, f32),
+        shrink_to_page: bool,
+    ) -> (f32, f32) {
+        let (natural_width, natural_height) = natural_size;
+        let p_width = get_number_from_js(content.params.get("width"), 0.0);
+        let p_height = get_number_from_js(content.params.get("height"), 0.0);
+        let (mut width, mut height) = match (p_width > 0.0, p_height > 0.0) {
+            (true, true) => (p_width, p_height),
+            (true, false) => (p_width, p_width / natural_width * natural_height),
+            (false, true) => (p_height / natural_height * natural_width, p_height),
+            (false, false) => (natural_width, natural_height),
+        };
+        let mut max_width = get_number_from_js(content.params.get("max_width"), 0.0);
+        let mut max_height = get_number_from_js(content.params.get("max_height"), 0.0);
+        if shrink_to_page {
+            let template = &js_doc.template;
+            let frame_width = template.size.0 - template.left - template.right;
+            let frame_height = template.size.1 - template.top - template.bottom;
+            if max_width <= 0.0 || max_width > frame_width {
+                max_width = frame_width;
+            }
+            if max_height <= 0.0 || max_height > frame_height {
+                max_height = frame_height;
+            }
+        }
+        if max_width > 0.0 && width > max_width {
+            height *= max_width / width;
+            width = max_width;
+        }
+        if max_height > 0.0 && height > max_height {
+            width *= max_height / height;
+            height = max_height;
+        }
+        (width, height)
     }
 }
 
@@ -345,17 +769,9 @@ impl Content for Image {
         canvas.draw_image(&self, false, available_width)
     }
     fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
-        let width = if self.fit_width || self.width > area.0 {
-            area.0
-        } else {
-            self.width
-        };
-        let height = if self.fit_width {
-            area.0 / self.width * self.height
-        } else {
-            self.height
-        };
-        (width, height)
+        // wider images don't fit when drawn, the width is still the available one
+        let (width, height) = self.get_size(area.0);
+        (width.min(area.0), height)
     }
     fn content_type(&self) -> ContentType {
         ContentType::Image
@@ -427,8 +843,12 @@ impl Table {
     pub fn get_style(&self) -> &TableStyle {
         &self.style
     }
-    pub fn from_content(content: &JsContent, js_doc: &JsDocument) -> Option<Table> {
-        let table_style = TableStyle::from_content(content);
+    pub fn from_content(
+        content: &JsContent,
+        js_doc: &JsDocument,
+        images: &mut ImageCache,
+    ) -> Result<Table, String> {
+        let table_style = TableStyle::from_content(content)?;
         let mut table = Table::new(table_style);
         if let Some(rows) = content.params.get("rows") {
             if let JsParamValue::Children(rows) = rows {
@@ -446,21 +866,40 @@ impl Table {
                                             match cell_content.obj_type.to_lowercase().as_str() {
                                                 "paragraph" => {
                                                     let paragraph =
-                                                        Paragraph::from_content(&cell_content);
-                                                    c.add(Box::new(paragraph));
+                                                        Paragraph::from_content(cell_content)?;
+                                                    let align = paragraph.get_style().align;
+                                                    c.add(Transformed::from_content(
+                                                        cell_content,
+                                                        Box::new(paragraph),
+                                                        align,
+                                                    ));
                                                 }
                                                 "image" => {
-                                                    if let Some(image) =
-                                                        Image::from_content(&cell_content, &js_doc)
-                                                    {
-                                                        c.add(Box::new(image));
+                                                    if let Some(image) = Image::from_content(
+                                                        cell_content,
+                                                        js_doc,
+                                                        images,
+                                                    )? {
+                                                        let align =
+                                                            image.get_style().horizontal_align;
+                                                        c.add(Transformed::from_content(
+                                                            cell_content,
+                                                            Box::new(image),
+                                                            align,
+                                                        ));
                                                     }
                                                 }
                                                 "path" => {
                                                     if let Some(path) =
-                                                        Path::from_content(&cell_content)
+                                                        Path::from_content(cell_content)?
                                                     {
-                                                        c.add(Box::new(path));
+                                                        let align =
+                                                            path.get_style().horizontal_align;
+                                                        c.add(Transformed::from_content(
+                                                            cell_content,
+                                                            Box::new(path),
+                                                            align,
+                                                        ));
                                                     }
                                                 }
                                                 _ => (),
@@ -468,10 +907,14 @@ impl Table {
                                         }
                                     }
                                 }
+                                if let Some(clip) = cell.params.get("clip") {
+                                    c.style.clip = Clip::from_param(clip)?;
+                                }
                                 if let Some(cell_style) = cell.params.get("style") {
                                     if let JsParamValue::Object(cell_style) = cell_style {
                                         if let Some(bg_color) = cell_style.get("background_color") {
-                                            c.style.background_color = Color::from_param(bg_color);
+                                            c.style.background_color =
+                                                Some(Fill::from_param(bg_color)?);
                                         }
                                     }
                                 }
@@ -483,7 +926,7 @@ impl Table {
                 }
             }
         }
-        Some(table)
+        Ok(table)
     }
 }
 
@@ -500,11 +943,12 @@ impl Content for Table {
     }
 }
 
+#[derive(Debug)]
 pub struct Path {
     points: Vec<Point>,
     stroke_color: Option<Color>,
     stroke_width: f32,
-    fill_color: Option<Color>,
+    fill_color: Option<Fill>,
     width: f32,
     height: f32,
     style: PathStyle,
@@ -515,7 +959,7 @@ impl Path {
         points: Vec<Point>,
         stroke_color: Option<Color>,
         stroke_width: f32,
-        fill_color: Option<Color>,
+        fill_color: Option<Fill>,
         style: PathStyle,
     ) -> Path {
         let min_x = points.iter().fold(std::f32::MAX, |acc, b| acc.min(b.x));
@@ -537,14 +981,20 @@ impl Path {
     pub fn get_points(&self) -> &Vec<Point> {
         &self.points
     }
-    pub fn get_stroke_color(&self) -> Option<Color> {
-        self.stroke_color
+    pub fn get_stroke_color(&self) -> Option<&Color> {
+        self.stroke_color.as_ref()
     }
     pub fn get_stroke_width(&self) -> f32 {
         self.stroke_width
     }
-    pub fn get_fill_color(&self) -> Option<Color> {
-        self.fill_color
+    pub fn get_fill_color(&self) -> Option<&Fill> {
+        self.fill_color.as_ref()
+    }
+    /// Bounding box of the points, top left corner in rect x and y.
+    pub fn get_bounds(&self) -> Rect {
+        let min_x = self.points.iter().fold(f32::MAX, |acc, b| acc.min(b.x));
+        let max_y = self.points.iter().fold(f32::MIN, |acc, b| acc.max(b.y));
+        Rect::new(min_x, max_y, self.width, self.height)
     }
     pub fn get_width(&self) -> f32 {
         self.width
@@ -555,14 +1005,14 @@ impl Path {
     pub fn get_style(&self) -> &PathStyle {
         &self.style
     }
-    pub fn from_content(content: &JsContent) -> Option<Path> {
+    pub fn from_content(content: &JsContent) -> Result<Option<Path>, String> {
         let stroke_color = if let Some(color) = content.params.get("stroke_color") {
-            Color::from_param(color)
+            Some(Color::from_param(color)?)
         } else {
             None
         };
         let fill_color = if let Some(color) = content.params.get("fill_color") {
-            Color::from_param(color)
+            Some(Fill::from_param(color)?)
         } else {
             None
         };
@@ -581,13 +1031,13 @@ impl Path {
                     }
                 }
                 if points.len() > 1 {
-                    let style = PathStyle::from_content(&content);
+                    let style = PathStyle::from_content(&content)?;
                     let path = Path::new(points, stroke_color, stroke_width, fill_color, style);
-                    return Some(path);
+                    return Ok(Some(path));
                 }
             }
         }
-        None
+        Ok(None)
     }
 }
 
@@ -602,3 +1052,531 @@ impl Content for Path {
         ContentType::Path
     }
 }
+
+/// Content drawn with rotation, scaling or skew around its centre.
+/// The transformed bounding box is placed in the flow like any other content.
+pub struct Transformed {
+    content: Box<dyn Content>,
+    transform: Transform,
+    align: HorizontalAlign,
+}
+
+impl Transformed {
+    pub fn new(
+        content: Box<dyn Content>,
+        transform: Transform,
+        align: HorizontalAlign,
+    ) -> Transformed {
+        Transformed {
+            content,
+            transform,
+            align,
+        }
+    }
+    /// Wraps content if `rotate`, `scale` or `skew` params are given.
+    pub fn from_content(
+        content: &JsContent,
+        object: Box<dyn Content>,
+        align: HorizontalAlign,
+    ) -> Box<dyn Content> {
+        let transform = Transform::from_content(content);
+        if transform.is_identity() {
+            object
+        } else {
+            Box::new(Transformed::new(object, transform, align))
+        }
+    }
+    pub fn get_content(&self) -> &dyn Content {
+        self.content.as_ref()
+    }
+    pub fn get_transform(&self) -> &Transform {
+        &self.transform
+    }
+    pub fn get_align(&self) -> HorizontalAlign {
+        self.align
+    }
+}
+
+impl Content for Transformed {
+    fn draw(&self, canvas: &mut Canvas, available_width: f32) -> Result<(), &'static str> {
+        canvas.draw_transformed(self, false, available_width)
+    }
+    fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
+        let (width, height) = self.content.wrap(area);
+        self.transform.get_bounds(width, height)
+    }
+    fn content_type(&self) -> ContentType {
+        self.content.content_type()
+    }
+}
+
+/// Content that is a link destination: it has an outline (bookmark) entry and/or
+/// a named anchor pointing to the place where it is drawn.
+pub struct Destination {
+    content: Box<dyn Content>,
+    outline: Option<(u8, String)>,
+    anchor: Option<String>,
+}
+
+impl Destination {
+    /// Wraps content if the `outline` param (e.g. `{"level": 2, "title": "Results"}`)
+    /// or the `anchor` param is given. The outline title defaults to the given text
+    /// (e.g. paragraph text).
+    pub fn from_content(
+        content: &JsContent,
+        object: Box<dyn Content>,
+        default_title: &str,
+    ) -> Result<Box<dyn Content>, String> {
+        let outline = Destination::outline_from_content(content, default_title)?;
+        let anchor = match content.params.get("anchor") {
+            None | Some(JsParamValue::Null) => None,
+            Some(JsParamValue::Text(name)) if !name.trim().is_empty() => Some(name.to_owned()),
+            Some(value) => {
+                return Err(format!(
+                    "Invalid anchor {}: expected a name.",
+                    to_js_string(value)
+                ))
+            }
+        };
+        if outline.is_none() && anchor.is_none() {
+            return Ok(object);
+        }
+        Ok(Box::new(Destination {
+            content: object,
+            outline,
+            anchor,
+        }))
+    }
+    fn outline_from_content(
+        content: &JsContent,
+        default_title: &str,
+    ) -> Result<Option<(u8, String)>, String> {
+        let (level, title) = match content.params.get("outline") {
+            None | Some(JsParamValue::Null) | Some(JsParamValue::Boolean(false)) => {
+                return Ok(None)
+            }
+            Some(JsParamValue::Boolean(true)) => (1.0, String::from(default_title)),
+            Some(JsParamValue::Text(title)) => (1.0, title.to_owned()),
+            Some(JsParamValue::Object(params)) => (
+                get_number_from_js(params.get("level"), 1.0),
+                get_text_from_js(params.get("title"), default_title),
+            ),
+            Some(value) => {
+                return Err(format!(
+                    "Invalid outline {}: expected {{\"level\": n, \"title\": \"...\"}}.",
+                    to_js_string(value)
+                ))
+            }
+        };
+        if !(1.0..=255.0).contains(&level) || level.fract() != 0.0 {
+            return Err(format!(
+                "Invalid outline level {}: expected a whole number from 1.",
+                level
+            ));
+        }
+        if title.trim().is_empty() {
+            return Err(String::from("Outline entry needs a title."));
+        }
+        Ok(Some((level as u8, title)))
+    }
+    pub fn get_content(&self) -> &dyn Content {
+        self.content.as_ref()
+    }
+    /// Outline level and title
+    pub fn get_outline(&self) -> Option<&(u8, String)> {
+        self.outline.as_ref()
+    }
+    pub fn get_anchor(&self) -> Option<&str> {
+        self.anchor.as_deref()
+    }
+}
+
+impl Content for Destination {
+    fn draw(&self, canvas: &mut Canvas, available_width: f32) -> Result<(), &'static str> {
+        canvas.draw_destination(self, available_width)
+    }
+    fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
+        self.content.wrap(area)
+    }
+    fn content_type(&self) -> ContentType {
+        self.content.content_type()
+    }
+}
+
+/// Table of contents listing the outline entries of the document with dot leaders
+/// and page numbers. Entries are known after the first layout pass.
+pub struct TableOfContents {
+    font_size: f32,
+    font: &'static Font,
+    style: ParagraphStyle,
+    /// Indentation per outline level
+    indent: f32,
+    /// Deepest outline level listed
+    levels: u8,
+    leader: String,
+}
+
+impl TableOfContents {
+    pub fn from_content(content: &JsContent) -> Result<TableOfContents, String> {
+        let font_name = get_text_from_js(content.params.get("font_name"), "Helvetica");
+        let font_size = get_number_from_js(content.params.get("font_size"), 12.0);
+        let style = ParagraphStyle::from_content(content, font_size)?;
+        let indent = get_number_from_js(content.params.get("indent"), font_size * 1.5);
+        let levels = get_number_from_js(content.params.get("levels"), 255.0);
+        if levels < 1.0 {
+            return Err(format!(
+                "Invalid table of contents levels {}: expected a number from 1.",
+                levels
+            ));
+        }
+        let leader = get_text_from_js(content.params.get("leader"), ".");
+        Ok(TableOfContents {
+            font_size,
+            font: get_font(font_name.to_lowercase().as_str()),
+            style,
+            indent,
+            levels: levels.min(255.0) as u8,
+            leader,
+        })
+    }
+    pub fn get_font_size(&self) -> f32 {
+        self.font_size
+    }
+    pub fn get_font(&self) -> &'static Font {
+        self.font
+    }
+    pub fn get_style(&self) -> &ParagraphStyle {
+        &self.style
+    }
+    pub fn get_leader(&self) -> &str {
+        &self.leader
+    }
+    pub fn shows_level(&self, level: u8) -> bool {
+        level <= self.levels
+    }
+    pub fn get_indent(&self, level: u8) -> f32 {
+        self.indent * f32::from(level.saturating_sub(1))
+    }
+    /// Shorten the title with an ellipsis to fit the width.
+    pub fn fit_title(&self, title: &str, width: f32) -> String {
+        if self.font.get_width(self.font_size, title) <= width {
+            return String::from(title);
+        }
+        let mut chars: Vec<char> = title.chars().collect();
+        while !chars.is_empty() {
+            chars.pop();
+            let text: String = chars.iter().collect::<String>().trim_end().to_owned() + "...";
+            if self.font.get_width(self.font_size, &text) <= width {
+                return text;
+            }
+        }
+        String::new()
+    }
+}
+
+impl Content for TableOfContents {
+    fn draw(&self, canvas: &mut Canvas, available_width: f32) -> Result<(), &'static str> {
+        canvas.draw_table_of_contents(self, available_width)
+    }
+    // entries are known only when drawing, table of contents is laid out in the main flow
+    fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
+        (area.0, self.style.padding.0 + self.style.padding.2)
+    }
+    fn content_type(&self) -> ContentType {
+        ContentType::TableOfContents
+    }
+}
+
+/// Section where the endnotes referenced so far are listed, when notes are
+/// collected to the end. Remaining notes are listed at the end of the document.
+pub struct Endnotes {}
+
+impl Content for Endnotes {
+    fn draw(&self, canvas: &mut Canvas, available_width: f32) -> Result<(), &'static str> {
+        canvas.draw_endnotes(available_width)
+    }
+    fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
+        (area.0, 0.0)
+    }
+    fn content_type(&self) -> ContentType {
+        ContentType::Endnotes
+    }
+}
+
+/// Interactive form field laid out as a block: a text field, check box,
+/// group of radio buttons, combo box or signature field.
+pub struct FormField {
+    name: String,
+    kind: FieldKind,
+    required: bool,
+    read_only: bool,
+    font: &'static Font,
+    font_size: f32,
+    /// Field box size, buttons are squares of the font size
+    width: Option<f32>,
+    height: f32,
+    /// Text right of a check box
+    label: String,
+}
+
+impl FormField {
+    /// Parse field of the given obj_type ("textfield", "checkbox", "radiogroup",
+    /// "combobox" or "signaturefield").
+    pub fn from_content(content: &JsContent, obj_type: &str) -> Result<FormField, String> {
+        let name = get_text_from_js(content.params.get("name"), "");
+        if name.is_empty() {
+            return Err(format!("Form field ({}) needs a name.", obj_type));
+        }
+        let font_name = get_text_from_js(content.params.get("font_name"), "Helvetica");
+        let font_size = get_number_from_js(content.params.get("font_size"), 12.0);
+        let value = get_text_from_js(content.params.get("value"), "");
+        let multiline = get_bool_from_js(content.params.get("multiline"), false);
+        let kind = match obj_type {
+            "textfield" => {
+                let max_length = match content.params.get("max_length") {
+                    Some(JsParamValue::Number(length)) if *length >= 1.0 => {
+                        Some(length.min(f32::from(u16::MAX)) as u16)
+                    }
+                    Some(value) => {
+                        return Err(format!(
+                            "Invalid max_length {} of field '{}': expected a number from 1.",
+                            to_js_string(value),
+                            name
+                        ))
+                    }
+                    None => None,
+                };
+                if let Some(max_length) = max_length {
+                    if value.chars().count() > max_length as usize {
+                        return Err(format!(
+                            "Value of field '{}' is longer than max_length {}.",
+                            name, max_length
+                        ));
+                    }
+                }
+                FieldKind::Text {
+                    value,
+                    multiline,
+                    password: get_bool_from_js(content.params.get("password"), false),
+                    max_length,
+                }
+            }
+            "checkbox" => FieldKind::CheckBox {
+                checked: get_bool_from_js(content.params.get("checked"), false),
+            },
+            "radiogroup" => {
+                let options = FormField::get_options(content, &name)?;
+                if !value.is_empty() && !options.contains(&value) {
+                    return Err(format!(
+                        "Value '{}' of field '{}' is not one of its options.",
+                        value, name
+                    ));
+                }
+                FieldKind::Radio {
+                    options,
+                    value: if value.is_empty() { None } else { Some(value) },
+                }
+            }
+            "combobox" => {
+                let options = FormField::get_options(content, &name)?;
+                let editable = get_bool_from_js(content.params.get("editable"), false);
+                if !editable && !value.is_empty() && !options.contains(&value) {
+                    return Err(format!(
+                        "Value '{}' of field '{}' is not one of its options.",
+                        value, name
+                    ));
+                }
+                FieldKind::Combo {
+                    options,
+                    value,
+                    editable,
+                }
+            }
+            _ => FieldKind::Signature,
+        };
+        let default_height = match kind {
+            FieldKind::Text {
+                multiline: true, ..
+            } => font_size * 4.0,
+            FieldKind::Signature => 50.0,
+            _ => font_size * 1.8,
+        };
+        let width = match content.params.get("width") {
+            Some(JsParamValue::Number(width)) => Some(*width),
+            _ if kind == FieldKind::Signature => Some(200.0),
+            _ => None,
+        };
+        Ok(FormField {
+            name,
+            kind,
+            required: get_bool_from_js(content.params.get("required"), false),
+            read_only: get_bool_from_js(content.params.get("read_only"), false),
+            font: get_font(font_name.to_lowercase().as_str()),
+            font_size,
+            width,
+            height: get_number_from_js(content.params.get("height"), default_height),
+            label: get_text_from_js(content.params.get("label"), ""),
+        })
+    }
+    /// Options of a radio group or a combo box, a list of texts.
+    fn get_options(content: &JsContent, name: &str) -> Result<Vec<String>, String> {
+        let error = || format!("Field '{}' needs options, a list of texts.", name);
+        match content.params.get("options") {
+            Some(JsParamValue::Array(values)) if !values.is_empty() => values
+                .iter()
+                .map(|value| match value {
+                    JsParamValue::Text(text) => Ok(text.to_owned()),
+                    _ => Err(error()),
+                })
+                .collect(),
+            _ => Err(error()),
+        }
+    }
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+    pub fn get_kind(&self) -> &FieldKind {
+        &self.kind
+    }
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+    pub fn get_font(&self) -> &'static Font {
+        self.font
+    }
+    pub fn get_font_size(&self) -> f32 {
+        self.font_size
+    }
+    pub fn get_label(&self) -> &str {
+        &self.label
+    }
+    /// Height of a check box or radio button row
+    pub fn get_row_height(&self) -> f32 {
+        self.font_size * 1.5
+    }
+    /// Size of the block, buttons are listed one per row.
+    pub fn get_size(&self, available_width: f32) -> (f32, f32) {
+        let width = self.width.unwrap_or(available_width).min(available_width);
+        match &self.kind {
+            FieldKind::CheckBox { .. } => (width, self.get_row_height()),
+            FieldKind::Radio { options, .. } => {
+                (width, self.get_row_height() * options.len() as f32)
+            }
+            _ => (width, self.height),
+        }
+    }
+}
+
+impl Content for FormField {
+    fn draw(&self, canvas: &mut Canvas, available_width: f32) -> Result<(), &'static str> {
+        canvas.draw_form_field(self, false, available_width)
+    }
+    fn wrap(&self, area: (f32, f32)) -> (f32, f32) {
+        self.get_size(area.0)
+    }
+    fn content_type(&self) -> ContentType {
+        ContentType::FormField
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1x1 grayscale PNG, the crc is not checked
+    fn test_png() -> Vec<u8> {
+        use super::super::encoders;
+        let mut png = vec![137, 80, 78, 71, 13, 10, 26, 10];
+        let idat = encoders::zlib::encode(&[0, 128]).unwrap();
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        for (chunk_type, chunk) in &[(b"IHDR", &ihdr[..]), (b"IDAT", &idat[..])] {
+            png.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            png.extend_from_slice(*chunk_type);
+            png.extend_from_slice(chunk);
+            png.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        png
+    }
+
+    #[test]
+    fn test_image_wrap_is_clamped_to_available_width() {
+        let data = Rc::new(decoders::decode(&test_png()).unwrap());
+        let image = Image::new(
+            Rc::clone(&data),
+            600.0,
+            300.0,
+            false,
+            false,
+            ImageStyle::new(),
+        );
+        assert_eq!(image.wrap((400.0, 800.0)), (400.0, 300.0));
+        let image = Image::new(data, 600.0, 300.0, false, true, ImageStyle::new());
+        assert_eq!(image.wrap((400.0, 800.0)), (400.0, 200.0));
+    }
+
+    #[test]
+    fn test_images_are_decoded_once() {
+        let png = test_png();
+        let mut js_doc: JsDocument = serde_json::from_str("{}").unwrap();
+        js_doc
+            .image_data
+            .insert(String::from("logo.png"), base64::encode(&png));
+        let content: JsContent =
+            serde_json::from_str(r#"{"obj_type": "image", "params": {"src": "logo.png"}}"#)
+                .unwrap();
+        let mut images = ImageCache::new();
+        let first = Image::from_content(&content, &js_doc, &mut images).unwrap();
+        let second = Image::from_content(&content, &js_doc, &mut images).unwrap();
+        assert!(Rc::ptr_eq(
+            first.unwrap().get_data(),
+            second.unwrap().get_data()
+        ));
+        assert!(images.get("missing.png", &js_doc).is_err());
+    }
+
+    #[test]
+    fn test_paragraph_wrap_includes_padding_and_bullet_indent() {
+        let mut style = ParagraphStyle::new(14.0, HorizontalAlign::Left, (2.0, 10.0, 3.0, 5.0));
+        style.bullet_indent = 15.0;
+        let paragraph = Paragraph::new("Lorem ipsum dolor sit amet", "helvetica", 12.0, style);
+        let font = get_font("helvetica");
+        let line_width = |text: &str| font.get_width(12.0, text);
+        // the lines are wrapped to the width left after padding and bullet indent,
+        // as they are when drawn
+        let available = line_width("Lorem ipsum dolor sit") + 20.0;
+        let (width, height) = paragraph.wrap((available, 500.0));
+        assert_eq!(width, line_width("Lorem ipsum dolor") + 30.0);
+        assert_eq!(height, 2.0 * 14.0 + 5.0);
+        let mut style = ParagraphStyle::new(14.0, HorizontalAlign::Left, (2.0, 10.0, 3.0, 5.0));
+        style.wrap = false;
+        let paragraph = Paragraph::new("Lorem ipsum dolor sit amet", "helvetica", 12.0, style);
+        let (width, height) = paragraph.wrap((50.0, 500.0));
+        assert_eq!(width, line_width("Lorem ipsum dolor sit amet") + 15.0);
+        assert_eq!(height, 14.0 + 5.0);
+    }
+
+    #[test]
+    fn test_paragraph_wrap_keeps_spans_before_a_wrapped_span() {
+        let style = ParagraphStyle::new(14.0, HorizontalAlign::Left, (0.0, 0.0, 0.0, 0.0));
+        let paragraph = Paragraph::new(
+            "Lorem ipsum <b>dolor sit</b> amet",
+            "helvetica",
+            12.0,
+            style,
+        );
+        // the first word of the bold span does not fit on the first line
+        let available = get_font("helvetica").get_width(12.0, "Lorem ipsum dol");
+        let wrapped = paragraph.wrap_to_width(available);
+        let lines: Vec<String> = wrapped
+            .iter()
+            .map(|line| {
+                let texts: Vec<&str> = line.iter().map(|span| span.text.trim()).collect();
+                texts.join(" ")
+            })
+            .collect();
+        assert_eq!(lines, vec!["Lorem ipsum", "dolor sit amet"]);
+    }
+}
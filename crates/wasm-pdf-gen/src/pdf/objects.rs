@@ -1,16 +1,37 @@
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::io::Write;
+use std::rc::Rc;
 use std::str;
 
+use super::annotations::Annotation;
+use super::attachments::EmbeddedFile;
+use super::decoders::ImageData;
 use super::encoders;
-use super::font::Font;
+use super::encryption::{md5, random_bytes, to_hex, Encryption, SecurityHandler};
+use super::font::{get_font, Font};
+use super::forms::{FieldKind, PDFFormField};
+use super::metadata::{encode_text_string, DocumentInfo};
+use super::signature::Signer;
 use super::template::PageTemplate;
 
+/// Name of the invisible field added when the document is signed without a signature field
+const SIGNATURE_FIELD: &str = "Signature";
+
 pub struct PDFDocument {
     pages: Vec<PDFPage>,
     page_counter: u16,
-    image_counter: u16,
+    images: Vec<PDFImage>,
+    /// Indexes of images by content hash, for sharing identical images
+    image_hashes: HashMap<u64, Vec<usize>>,
+    graphics_state_counter: u16,
+    shading_counter: u16,
+    color_space_counter: u16,
     fonts: HashSet<&'static Font>,
+    outline: Vec<PDFOutlineItem>,
+    /// Named destinations: name, page index and y position
+    anchors: Vec<(String, usize, f32)>,
 }
 
 impl PDFDocument {
@@ -18,8 +39,14 @@ impl PDFDocument {
         PDFDocument {
             pages: Vec::new(),
             page_counter: 1,
-            image_counter: 0,
+            images: Vec::new(),
+            image_hashes: HashMap::new(),
+            graphics_state_counter: 0,
+            shading_counter: 0,
+            color_space_counter: 0,
             fonts: HashSet::new(),
+            outline: Vec::new(),
+            anchors: Vec::new(),
         }
     }
     pub fn page_number(&self) -> u16 {
@@ -32,13 +59,95 @@ impl PDFDocument {
         self.pages.push(page);
         self.page_counter += 1;
     }
-    pub fn get_image_id(&mut self) -> u16 {
-        self.image_counter += 1;
-        self.image_counter
+    /// Add outline entry pointing to the y position of the page being drawn.
+    pub fn add_outline(&mut self, level: u8, title: &str, top: f32) {
+        self.outline.push(PDFOutlineItem {
+            level,
+            title: String::from(title),
+            page: self.pages.len(),
+            top,
+            object_id: 0,
+        });
+    }
+    pub fn get_outline_len(&self) -> usize {
+        self.outline.len()
+    }
+    /// Level, title and page (1 is the first page) of outline entries.
+    pub fn get_outline(&self) -> Vec<(u8, String, u16)> {
+        self.outline
+            .iter()
+            .map(|item| (item.level, item.title.clone(), item.page as u16 + 1))
+            .collect()
+    }
+    /// Add named destination at the y position of the page being drawn, the first one wins.
+    pub fn add_anchor(&mut self, name: &str, top: f32) {
+        if !self.anchors.iter().any(|(anchor, _, _)| anchor == name) {
+            self.anchors
+                .push((String::from(name), self.pages.len(), top));
+        }
+    }
+    /// Add image to the document and return its id.
+    /// Identical images are written once and shared by all pages using them.
+    pub fn add_image(&mut self, data: &Rc<ImageData>) -> u16 {
+        // images drawn on every page are the same instance
+        if let Some(image) = self
+            .images
+            .iter()
+            .find(|image| Rc::ptr_eq(&image.data, data))
+        {
+            return image.image_id;
+        }
+        let mut hasher = DefaultHasher::new();
+        data.data.hash(&mut hasher);
+        let indexes = self.image_hashes.entry(hasher.finish()).or_default();
+        for &index in indexes.iter() {
+            let image = &self.images[index];
+            if *image.data == **data {
+                return image.image_id;
+            }
+        }
+        indexes.push(self.images.len());
+        let image_id = self.images.len() as u16 + 1;
+        self.images.push(PDFImage::new(image_id, data));
+        image_id
+    }
+    pub fn get_graphics_state_id(&mut self) -> u16 {
+        self.graphics_state_counter += 1;
+        self.graphics_state_counter
+    }
+    pub fn get_shading_id(&mut self) -> u16 {
+        self.shading_counter += 1;
+        self.shading_counter
+    }
+    pub fn get_color_space_id(&mut self) -> u16 {
+        self.color_space_counter += 1;
+        self.color_space_counter
     }
     /// Build and return PDF bytes
-    pub fn save_document(&mut self, tpl: &PageTemplate) -> Result<Vec<u8>, &'static str> {
+    pub fn save_document(
+        &mut self,
+        tpl: &PageTemplate,
+        info: &DocumentInfo,
+        page_mode: PageMode,
+        encryption: Option<&Encryption>,
+        signer: Option<&Signer>,
+        attachments: &[EmbeddedFile],
+    ) -> Result<Vec<u8>, &'static str> {
+        if let Some(signer) = signer {
+            if signer.get_details().field.is_empty() {
+                self.add_invisible_signature_field();
+            }
+        }
         let mut pdf = PDFFile::new();
+        let mut version = "";
+        if let Some(encryption) = encryption {
+            let mut seed = random_bytes(16)?;
+            seed.extend_from_slice(info.get_dictionary().as_bytes());
+            let file_id = md5(&seed);
+            let security = SecurityHandler::new(encryption, &file_id)?;
+            version = security.get_catalog_entries();
+            pdf.set_security(security, file_id);
+        }
         let font_id = pdf.get_new_object_id();
         let mut font_resources = String::new();
         // delay adding font resources to document after root font object (1 0 R)
@@ -55,38 +164,171 @@ impl PDFDocument {
         }
         // Root font object
         let font_obj = PDFObject::new(&font_resources, font_id);
-        pdf.add_object(&font_obj);
+        pdf.add_object(&font_obj)?;
         // Font resource objects
         for font_resource_obj in font_resource_objects {
-            pdf.add_object(&font_resource_obj);
+            pdf.add_object(&font_resource_obj)?;
         }
-        // Link Annotations
+        // Embedded files, listed by name in the catalog and opened by file attachment annotations
+        let mut files: Vec<(&str, u16)> = Vec::new();
+        for attachment in attachments {
+            if files.iter().any(|(name, _)| *name == attachment.get_name()) {
+                return Err("Attachment names must be unique.");
+            }
+            let filespec_id = attachment.write(&mut pdf, &info.mod_date)?;
+            files.push((attachment.get_name(), filespec_id));
+        }
+        let mut embedded_files = String::new();
+        if !files.is_empty() {
+            let associated: Vec<String> =
+                files.iter().map(|(_, id)| format!("{} 0 R", id)).collect();
+            // name tree keys are sorted
+            let mut names = files.clone();
+            names.sort_by(|a, b| a.0.cmp(b.0));
+            let names: Vec<String> = names
+                .iter()
+                .map(|(name, id)| format!("{} {} 0 R", encode_text_string(name), id))
+                .collect();
+            embedded_files = format!(
+                " /Names << /EmbeddedFiles << /Names [ {} ] >> >> /AF [ {} ]",
+                names.join(" "),
+                associated.join(" ")
+            );
+        }
+        // Annotations, '#name' links go to named destinations. Links to missing
+        // anchors are left out, their text is drawn without a link.
+        let anchors = &self.anchors;
         for page in &mut self.pages {
-            for annot in &mut page.link_annotations {
-                annot.object_id = pdf.get_new_object_id();
-                let annot_resource_obj = PDFObject::new(
-                    &format!(
-                        "/A \
-                    << /S /URI /Type /Action /URI ({}) \
-                    >> /Border [ 0 0 0 ] /Rect [ {} {} {} {} ] /Subtype /Link /Type /Annot",
-                        annot.url, annot.x1, annot.y1, annot.x2, annot.y2
-                    ),
-                    annot.object_id,
-                );
-                pdf.add_object(&annot_resource_obj);
+            page.annotations.retain(|annot| annot.has_target(anchors));
+            for annot in &mut page.annotations {
+                annot.write(&mut pdf, font_id, &files)?;
+            }
+        }
+        // Form fields with their widgets and appearance streams
+        let mut field_names: HashSet<&str> = HashSet::new();
+        for field in self.pages.iter().flat_map(|page| &page.form_fields) {
+            if !field_names.insert(field.get_name()) {
+                return Err("Form field names must be unique.");
+            }
+        }
+        // Signature dictionary, /ByteRange and /Contents are filled after the file is complete
+        let mut signature_id = 0;
+        if let Some(signer) = signer {
+            let name = match signer.get_details().field.as_str() {
+                "" => SIGNATURE_FIELD,
+                name => name,
+            };
+            let field = self
+                .pages
+                .iter_mut()
+                .flat_map(|page| &mut page.form_fields)
+                .find(|field| field.is_signature() && field.get_name() == name)
+                .ok_or("Signature field doesn't exist.")?;
+            signature_id = pdf.get_new_object_id();
+            field.set_signature(signature_id);
+            pdf.add_object(&PDFObject::new(&signer.get_dictionary(), signature_id))?;
+        }
+        let mut fields: Vec<String> = Vec::new();
+        for page in &mut self.pages {
+            for field in &mut page.form_fields {
+                field.write(&mut pdf, font_id)?;
+                fields.push(format!("{} 0 R", field.get_field_id()));
             }
         }
+        let acro_form = if fields.is_empty() {
+            String::new()
+        } else {
+            // signatures exist and the file is only appended to
+            let sig_flags = if signer.is_some() { " /SigFlags 3" } else { "" };
+            format!(
+                " /AcroForm << /Fields [ {} ] /DR << /Font {} 0 R >>{} >>",
+                fields.join(" "),
+                font_id,
+                sig_flags
+            )
+        };
+        // Images are shared by pages, each one is written once
+        for image in &mut self.images {
+            image.object_id = pdf.get_new_object_id();
+            if image.data.smask.is_some() {
+                image.smask_id = pdf.get_new_object_id();
+            }
+            pdf.add_bytes(&PDFImage::write_object(
+                &image.data,
+                image.object_id,
+                image.smask_id,
+            ))?;
+            if let Some(smask) = &image.data.smask {
+                pdf.add_bytes(&PDFImage::write_object(smask, image.smask_id, 0))?;
+            }
+        }
+        // Document information and matching XMP metadata
+        let info_id = pdf.get_new_object_id();
+        pdf.add_object(&PDFObject::new(&info.get_dictionary(), info_id))?;
+        let metadata_id = pdf.get_new_object_id();
+        let xmp = info.get_xmp();
+        let mut output = Vec::new();
+        writeln!(
+            output,
+            "{} 0 obj\n<<\n/Type /Metadata /Subtype /XML /Length {}\n>>",
+            metadata_id,
+            xmp.len()
+        )
+        .unwrap();
+        writeln!(output, "stream").unwrap();
+        writeln!(output, "{}", xmp).unwrap();
+        writeln!(output, "endstream").unwrap();
+        writeln!(output, "endobj").unwrap();
+        pdf.add_bytes(&output)?;
         let root_id = pdf.get_new_object_id();
         let pages_id = pdf.get_new_object_id();
-        let root_obj = PDFObject::new(&format!("/Type /Catalog /Pages {} 0 R", pages_id), root_id);
-        pdf.add_object(&root_obj);
+        // outline is written after pages, when page ids are known
+        let mut outlines = String::new();
+        let mut outlines_id = 0;
+        if !self.outline.is_empty() {
+            outlines_id = pdf.get_new_object_id();
+            outlines = format!(" /Outlines {} 0 R", outlines_id);
+            for item in &mut self.outline {
+                item.object_id = pdf.get_new_object_id();
+            }
+        }
+        let mut dests = String::new();
+        let mut dests_id = 0;
+        if !self.anchors.is_empty() {
+            dests_id = pdf.get_new_object_id();
+            dests = format!(" /Dests {} 0 R", dests_id);
+        }
+        let root_obj = PDFObject::new(
+            &format!(
+                "/Type /Catalog{} /Pages {} 0 R /Metadata {} 0 R{}{}{}{}{} \
+                 /ViewerPreferences << /DisplayDocTitle true >>",
+                version,
+                pages_id,
+                metadata_id,
+                outlines,
+                dests,
+                embedded_files,
+                acro_form,
+                page_mode.get_entry()
+            ),
+            root_id,
+        );
+        pdf.add_object(&root_obj)?;
         let mut kids = String::new();
         kids += "[ ";
         // get page ids
         for page in &mut self.pages {
-            // page images
-            for image in &mut page.images {
-                image.object_id = pdf.get_new_object_id();
+            // page graphics states
+            for state in &mut page.graphics_states {
+                state.object_id = pdf.get_new_object_id();
+            }
+            // page shadings
+            for shading in &mut page.shadings {
+                shading.object_id = pdf.get_new_object_id();
+            }
+            // page colour spaces
+            for color_space in &mut page.color_spaces {
+                color_space.object_id = pdf.get_new_object_id();
             }
             let page_id = pdf.get_new_object_id();
             page.page_id = page_id;
@@ -98,7 +340,23 @@ impl PDFDocument {
             &format!("/Type /Pages /Kids {} /Count {}", kids, self.pages.len()),
             pages_id,
         );
-        pdf.add_object(&pages_obj);
+        pdf.add_object(&pages_obj)?;
+        self.write_outline(&mut pdf, outlines_id)?;
+        if !self.anchors.is_empty() {
+            let entries: Vec<String> = self
+                .anchors
+                .iter()
+                .map(|(name, page, top)| {
+                    format!(
+                        "/{} [{} 0 R /XYZ 0 {} null]",
+                        encode_name(name),
+                        self.get_page(*page).page_id,
+                        top
+                    )
+                })
+                .collect();
+            pdf.add_object(&PDFObject::new(&entries.join("\n"), dests_id))?;
+        }
         // Retrieve ids for page contents
         for page in &mut self.pages {
             let content_id = pdf.get_new_object_id();
@@ -106,49 +364,74 @@ impl PDFDocument {
         }
         for page in &self.pages {
             let mut x_objects: Vec<String> = Vec::new();
-            for image in &page.images {
-                // print all image resources here and get identifiers for next phase
-                let bytes = encoders::ascii85::encode(&image.contents).unwrap();
+            for image_id in &page.images {
+                let image = &self.images[*image_id as usize - 1];
+                x_objects.push(format!("/{} {} 0 R", image.get_uid(), image.object_id));
+            }
+            let x_object = if x_objects.is_empty() {
+                String::new()
+            } else {
+                format!("/XObject <<\n{}\n>>", x_objects.join(" "))
+            };
+            let mut graphics_states: Vec<String> = Vec::new();
+            for state in &page.graphics_states {
+                let state_obj = PDFObject::new(&state.get_dictionary(), state.object_id);
+                pdf.add_object(&state_obj)?;
+                graphics_states.push(format!("/{} {} 0 R", state.get_uid(), state.object_id));
+            }
+            let ext_g_state = if graphics_states.is_empty() {
+                String::new()
+            } else {
+                format!("/ExtGState <<\n{}\n>>", graphics_states.join(" "))
+            };
+            let mut shadings: Vec<String> = Vec::new();
+            for shading in &page.shadings {
+                let shading_obj = PDFObject::new(&shading.dictionary, shading.object_id);
+                pdf.add_object(&shading_obj)?;
+                shadings.push(format!("/{} {} 0 R", shading.get_uid(), shading.object_id));
+            }
+            let shading = if shadings.is_empty() {
+                String::new()
+            } else {
+                format!("/Shading <<\n{}\n>>", shadings.join(" "))
+            };
+            let mut color_spaces: Vec<String> = Vec::new();
+            for color_space in &page.color_spaces {
                 let mut output = Vec::new();
-                writeln!(output, "{} 0 obj", image.object_id).unwrap();
                 writeln!(
                     output,
-                    "<<  
-/Type /XObject
-/Subtype /Image 
-/Height {} 
-/Width {} 
-/ColorSpace /DeviceRGB
-/BitsPerComponent 8
-/Filter [/ASCII85Decode /DCTDecode]
-/Length {} 
->>",
-                    image.height,
-                    image.width,
-                    bytes.len() + 2 // ~> + 2
+                    "{} 0 obj\n{}\nendobj",
+                    color_space.object_id,
+                    color_space.get_array()
                 )
                 .unwrap();
-                writeln!(output, "stream").unwrap();
-                output.write_all(&bytes).unwrap();
-                write!(output, "~>").unwrap();
-                writeln!(output, "endstream").unwrap();
-                writeln!(output, "endobj").unwrap();
-                pdf.add_bytes(&output);
-                x_objects.push(format!("/{} {} 0 R", image.get_uid(), image.object_id));
+                pdf.add_bytes(&output)?;
+                color_spaces.push(format!(
+                    "/{} {} 0 R",
+                    color_space.get_uid(),
+                    color_space.object_id
+                ));
             }
-            let x_object = if x_objects.is_empty() {
+            let color_space = if color_spaces.is_empty() {
                 String::new()
             } else {
-                format!("/XObject <<\n{}\n>>", x_objects.join(" "))
+                format!("/ColorSpace <<\n{}\n>>", color_spaces.join(" "))
             };
-            let annots = if page.link_annotations.is_empty() {
+            let mut annotation_objects: Vec<String> = Vec::new();
+            for annot in &page.annotations {
+                for id in annot.get_ids() {
+                    annotation_objects.push(format!("{} 0 R", id));
+                }
+            }
+            for field in &page.form_fields {
+                for widget_id in field.get_widget_ids() {
+                    annotation_objects.push(format!("{} 0 R", widget_id));
+                }
+            }
+            let annots = if annotation_objects.is_empty() {
                 String::new()
             } else {
-                let mut link_annotation_objects: Vec<String> = Vec::new();
-                for annot in &page.link_annotations {
-                    link_annotation_objects.push(format!("{} 0 R", annot.object_id));
-                }
-                format!("/Annots [ {} ]", link_annotation_objects.join(" "))
+                format!("/Annots [ {} ]", annotation_objects.join(" "))
             };
             let content_id = page.content_id;
             let page_obj = PDFObject::new(
@@ -159,6 +442,9 @@ impl PDFDocument {
     /Font {} 0 R 
     /ProcSet [ /PDF /Text {}] 
     {}
+    {}
+    {}
+    {}
 >> 
 /MediaBox [0 0 {} {}] {} 
 /Contents {} 0 R",
@@ -166,6 +452,9 @@ impl PDFDocument {
                     font_id,
                     if x_objects.is_empty() { "" } else { "/ImageC" },
                     x_object,
+                    ext_g_state,
+                    shading,
+                    color_space,
                     tpl.get_size().0,
                     tpl.get_size().1,
                     annots,
@@ -173,7 +462,7 @@ impl PDFDocument {
                 ),
                 page.page_id,
             );
-            pdf.add_object(&page_obj);
+            pdf.add_object(&page_obj)?;
         }
         // add page contents here
         for page in &self.pages {
@@ -193,19 +482,190 @@ impl PDFDocument {
             write!(output, "~>").unwrap(); // ascii85 stream end marker
             writeln!(output, "endstream").unwrap();
             writeln!(output, "endobj").unwrap();
-            pdf.add_bytes(&output);
+            pdf.add_bytes(&output)?;
+        }
+        pdf.add_trailer(root_id, info_id)?;
+        if let Some(signer) = signer {
+            // objects are written in id order, like for the cross-reference table
+            let offset = pdf.offsets[signature_id as usize - 1] as usize;
+            signer.sign(&mut pdf.contents, offset)?;
         }
-        pdf.add_trailer(root_id);
         Ok(pdf.contents)
     }
 }
 
+impl PDFDocument {
+    /// Add an invisible signature field to the first page for signing without a visible field.
+    fn add_invisible_signature_field(&mut self) {
+        let font = get_font("helvetica");
+        self.add_font(font);
+        if let Some(page) = self.pages.first_mut() {
+            page.form_fields.push(PDFFormField::new(
+                SIGNATURE_FIELD,
+                &FieldKind::Signature,
+                false,
+                false,
+                font,
+                12.0,
+                vec![[0.0, 0.0, 0.0, 0.0]],
+            ));
+        }
+    }
+    /// Page by index, destinations added after the last page point to the last page.
+    fn get_page(&self, index: usize) -> &PDFPage {
+        &self.pages[index.min(self.pages.len() - 1)]
+    }
+    /// Write the outline root and its items, nested by level.
+    fn write_outline(&self, pdf: &mut PDFFile, outlines_id: u16) -> Result<(), &'static str> {
+        if self.outline.is_empty() {
+            return Ok(());
+        }
+        let levels: Vec<u8> = self.outline.iter().map(|item| item.level).collect();
+        let tree = get_outline_tree(&levels);
+        let id = |index: Option<usize>| index.map_or(outlines_id, |i| self.outline[i].object_id);
+        let top_level: Vec<usize> = (0..tree.len())
+            .filter(|&i| tree[i].parent.is_none())
+            .collect();
+        pdf.add_object(&PDFObject::new(
+            &format!(
+                "/Type /Outlines /First {} 0 R /Last {} 0 R /Count {}",
+                id(top_level.first().copied()),
+                id(top_level.last().copied()),
+                self.outline.len()
+            ),
+            outlines_id,
+        ))?;
+        for (item, node) in self.outline.iter().zip(tree.iter()) {
+            let mut entries = format!(
+                "/Title {} /Parent {} 0 R",
+                encode_text_string(&item.title),
+                id(node.parent)
+            );
+            if let Some(prev) = node.prev {
+                entries += &format!(" /Prev {} 0 R", id(Some(prev)));
+            }
+            if let Some(next) = node.next {
+                entries += &format!(" /Next {} 0 R", id(Some(next)));
+            }
+            if let (Some(first), Some(last)) = (node.first, node.last) {
+                entries += &format!(
+                    " /First {} 0 R /Last {} 0 R /Count {}",
+                    id(Some(first)),
+                    id(Some(last)),
+                    node.count
+                );
+            }
+            entries += &format!(
+                " /Dest [{} 0 R /XYZ 0 {} null]",
+                self.get_page(item.page).page_id,
+                item.top
+            );
+            pdf.add_object(&PDFObject::new(&entries, item.object_id))?;
+        }
+        Ok(())
+    }
+}
+
+/// Panel shown when the document is opened.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageMode {
+    None,
+    Outline,
+    Attachments,
+}
+
+impl PageMode {
+    pub fn from_name(name: &str) -> Result<PageMode, String> {
+        match name.to_lowercase().as_str() {
+            "" | "none" => Ok(PageMode::None),
+            "outline" | "outlines" | "bookmarks" => Ok(PageMode::Outline),
+            "attachments" => Ok(PageMode::Attachments),
+            _ => Err(format!(
+                "Invalid page mode '{}', expected \"none\", \"outline\" or \"attachments\".",
+                name
+            )),
+        }
+    }
+    fn get_entry(self) -> &'static str {
+        match self {
+            PageMode::None => "",
+            PageMode::Outline => " /PageMode /UseOutlines",
+            PageMode::Attachments => " /PageMode /UseAttachments",
+        }
+    }
+}
+
+struct PDFOutlineItem {
+    level: u8,
+    title: String,
+    /// Index of the page in the document
+    page: usize,
+    top: f32,
+    object_id: u16,
+}
+
+/// Links between outline items, given as indexes of the items.
+#[derive(Debug, Default, PartialEq)]
+struct OutlineNode {
+    parent: Option<usize>,
+    prev: Option<usize>,
+    next: Option<usize>,
+    first: Option<usize>,
+    last: Option<usize>,
+    /// Number of descendants, all items are open
+    count: usize,
+}
+
+/// Nest outline items by level, an item is a child of the closest preceding item with a lower level.
+fn get_outline_tree(levels: &[u8]) -> Vec<OutlineNode> {
+    let mut nodes: Vec<OutlineNode> = levels.iter().map(|_| OutlineNode::default()).collect();
+    let mut stack: Vec<usize> = Vec::new();
+    let mut last_top_level: Option<usize> = None;
+    for (index, &level) in levels.iter().enumerate() {
+        while let Some(&open) = stack.last() {
+            if levels[open] < level {
+                break;
+            }
+            stack.pop();
+        }
+        let parent = stack.last().copied();
+        let prev = match parent {
+            Some(parent) => nodes[parent].last,
+            None => last_top_level,
+        };
+        if let Some(prev) = prev {
+            nodes[prev].next = Some(index);
+        }
+        match parent {
+            Some(parent) => {
+                if nodes[parent].first.is_none() {
+                    nodes[parent].first = Some(index);
+                }
+                nodes[parent].last = Some(index);
+            }
+            None => last_top_level = Some(index),
+        }
+        for &ancestor in &stack {
+            nodes[ancestor].count += 1;
+        }
+        nodes[index].parent = parent;
+        nodes[index].prev = prev;
+        stack.push(index);
+    }
+    nodes
+}
+
 pub struct PDFPage {
     contents: Vec<u8>,
     page_id: u16,
     content_id: u16,
-    images: Vec<PDFImage>,
-    link_annotations: Vec<LinkAnnotation>,
+    /// Ids of document images used on this page
+    images: Vec<u16>,
+    graphics_states: Vec<PDFGraphicsState>,
+    shadings: Vec<PDFShading>,
+    color_spaces: Vec<PDFColorSpace>,
+    annotations: Vec<Annotation>,
+    form_fields: Vec<PDFFormField>,
 }
 
 impl PDFPage {
@@ -215,17 +675,33 @@ impl PDFPage {
             page_id: 0,
             content_id: 0,
             images: Vec::new(),
-            link_annotations: Vec::new(),
+            graphics_states: Vec::new(),
+            shadings: Vec::new(),
+            color_spaces: Vec::new(),
+            annotations: Vec::new(),
+            form_fields: Vec::new(),
         }
     }
     pub fn set_contents(&mut self, input: &[u8]) {
         self.contents = input.to_vec();
     }
-    pub fn set_images(&mut self, input: &[PDFImage]) {
+    pub fn set_images(&mut self, input: &[u16]) {
         self.images = input.to_vec();
     }
-    pub fn set_link_annotations(&mut self, input: &[LinkAnnotation]) {
-        self.link_annotations = input.to_vec();
+    pub fn set_graphics_states(&mut self, input: &[PDFGraphicsState]) {
+        self.graphics_states = input.to_vec();
+    }
+    pub fn set_shadings(&mut self, input: &[PDFShading]) {
+        self.shadings = input.to_vec();
+    }
+    pub fn set_color_spaces(&mut self, input: &[PDFColorSpace]) {
+        self.color_spaces = input.to_vec();
+    }
+    pub fn set_annotations(&mut self, input: &[Annotation]) {
+        self.annotations = input.to_vec();
+    }
+    pub fn set_form_fields(&mut self, input: &[PDFFormField]) {
+        self.form_fields = input.to_vec();
     }
 }
 
@@ -251,6 +727,9 @@ pub struct PDFFile {
     contents: Vec<u8>,
     object_counter: u16,
     offsets: Vec<u32>,
+    /// Encrypts objects of a password protected document
+    security: Option<SecurityHandler>,
+    file_id: Vec<u8>,
 }
 
 impl PDFFile {
@@ -264,12 +743,27 @@ impl PDFFile {
             contents: output,
             object_counter: 1,
             offsets: Vec::new(),
+            security: None,
+            file_id: Vec::new(),
         }
     }
+    /// Encrypt strings and streams of objects added after this. The encryption
+    /// dictionary and the file identifier are written with the trailer.
+    pub fn set_security(&mut self, security: SecurityHandler, file_id: Vec<u8>) {
+        self.security = Some(security);
+        self.file_id = file_id;
+    }
     /// Append bytes to contents and current offset
-    pub fn add_bytes(&mut self, bytes: &[u8]) {
+    pub fn add_bytes(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
         self.offsets.push(self.contents.len() as u32);
-        self.contents.write_all(bytes).unwrap();
+        match &mut self.security {
+            Some(security) => {
+                let bytes = security.encrypt_object(bytes)?;
+                self.contents.write_all(&bytes).unwrap();
+            }
+            None => self.contents.write_all(bytes).unwrap(),
+        }
+        Ok(())
     }
     /// Give new object id (this id should be consumed)
     pub fn get_new_object_id(&mut self) -> u16 {
@@ -277,16 +771,26 @@ impl PDFFile {
         self.object_counter += 1;
         id
     }
-    pub fn add_object(&mut self, obj: &PDFObject) {
-        self.offsets.push(self.contents.len() as u32);
-        self.contents.write_all(&obj.contents[..]).unwrap();
+    pub fn add_object(&mut self, obj: &PDFObject) -> Result<(), &'static str> {
+        self.add_bytes(&obj.contents)
     }
     fn add_cross_reference_table(&mut self) {
         for offset in &self.offsets {
             writeln!(self.contents, "{:010} 00000 n ", offset).unwrap();
         }
     }
-    fn add_trailer(&mut self, root_id: u16) {
+    fn add_trailer(&mut self, root_id: u16, info_id: u16) -> Result<(), &'static str> {
+        let mut encrypt = String::new();
+        // encryption dictionary itself is not encrypted
+        if let Some(security) = self.security.take() {
+            let encrypt_id = self.get_new_object_id();
+            self.add_object(&PDFObject::new(security.get_dictionary(), encrypt_id))?;
+            let file_id = to_hex(&self.file_id);
+            encrypt = format!(
+                " /Encrypt {} 0 R /ID [<{}> <{}>]",
+                encrypt_id, file_id, file_id
+            );
+        }
         //let num_objects = self.offsets.len() + 1;
         let num_objects = self.object_counter;
         let xref_start_offset = self.contents.len();
@@ -296,60 +800,331 @@ impl PDFFile {
         self.add_cross_reference_table();
         writeln!(
             self.contents,
-            "trailer <</Size {} /Root {} 0 R>>",
-            num_objects, root_id
+            "trailer <</Size {} /Root {} 0 R /Info {} 0 R{}>>",
+            num_objects, root_id, info_id, encrypt
         )
         .unwrap();
         writeln!(self.contents, "startxref").unwrap();
         writeln!(self.contents, "{}", xref_start_offset).unwrap();
         writeln!(self.contents, "%%EOF").unwrap();
+        Ok(())
     }
 }
 
-#[derive(Clone)]
 pub struct PDFImage {
-    width: f32,
-    height: f32,
-    contents: Vec<u8>,
+    data: Rc<ImageData>,
     image_id: u16,
     // image identifier
     object_id: u16, // pdf object id
+    smask_id: u16,  // soft mask object id, if image has alpha
 }
 
 impl PDFImage {
-    pub fn new(image_id: u16, width: f32, height: f32, bytes: &[u8]) -> PDFImage {
+    pub fn new(image_id: u16, data: &Rc<ImageData>) -> PDFImage {
         PDFImage {
-            width,
-            height,
-            contents: bytes.to_vec(),
+            data: Rc::clone(data),
             image_id,
             object_id: 0,
+            smask_id: 0,
         }
     }
     pub fn get_uid(&self) -> String {
         format!("Im{}", self.image_id)
     }
+    /// Image XObject with optional soft mask reference.
+    fn write_object(data: &ImageData, object_id: u16, smask_id: u16) -> Vec<u8> {
+        let bytes = encoders::ascii85::encode(&data.data).unwrap();
+        let mut output = Vec::new();
+        writeln!(output, "{} 0 obj", object_id).unwrap();
+        writeln!(
+            output,
+            "<<
+/Type /XObject
+/Subtype /Image
+/Height {}
+/Width {}
+/ColorSpace {}
+/BitsPerComponent {}
+/Filter [/ASCII85Decode {}]",
+            data.height, data.width, data.color_space, data.bits_per_component, data.filter
+        )
+        .unwrap();
+        if let Some(decode) = &data.decode {
+            writeln!(output, "/Decode {}", decode).unwrap();
+        }
+        if let Some(parms) = &data.decode_parms {
+            writeln!(output, "/DecodeParms [null << {} >>]", parms).unwrap();
+        }
+        if smask_id > 0 {
+            writeln!(output, "/SMask {} 0 R", smask_id).unwrap();
+        }
+        writeln!(output, "/Length {}\n>>", bytes.len() + 2).unwrap(); // ~> + 2
+        writeln!(output, "stream").unwrap();
+        output.write_all(&bytes).unwrap();
+        write!(output, "~>").unwrap();
+        writeln!(output, "endstream").unwrap();
+        writeln!(output, "endobj").unwrap();
+        output
+    }
+}
+
+/// External graphics state (ExtGState) holding opacity and blend mode.
+#[derive(Clone)]
+pub struct PDFGraphicsState {
+    fill_opacity: f32,
+    stroke_opacity: f32,
+    blend_mode: &'static str,
+    state_id: u16,
+    object_id: u16,
+}
+
+impl PDFGraphicsState {
+    pub fn new(
+        state_id: u16,
+        fill_opacity: f32,
+        stroke_opacity: f32,
+        blend_mode: &'static str,
+    ) -> PDFGraphicsState {
+        PDFGraphicsState {
+            fill_opacity,
+            stroke_opacity,
+            blend_mode,
+            state_id,
+            object_id: 0,
+        }
+    }
+    pub fn get_uid(&self) -> String {
+        format!("GS{}", self.state_id)
+    }
+    /// Check if this state has the given parameters
+    pub fn matches(&self, fill_opacity: f32, stroke_opacity: f32, blend_mode: &str) -> bool {
+        self.fill_opacity == fill_opacity
+            && self.stroke_opacity == stroke_opacity
+            && self.blend_mode == blend_mode
+    }
+    fn get_dictionary(&self) -> String {
+        format!(
+            "/Type /ExtGState /ca {} /CA {} /BM /{}",
+            self.fill_opacity, self.stroke_opacity, self.blend_mode
+        )
+    }
+}
+
+/// Smooth shading (gradient) painted with the `sh` operator.
+/// Colour stops are given as (offset, colour components) pairs.
+#[derive(Clone)]
+pub struct PDFShading {
+    dictionary: String,
+    shading_id: u16,
+    object_id: u16,
+}
+
+impl PDFShading {
+    /// Axial (type 2) or radial (type 3) shading with given coordinates and colour stops.
+    pub fn new(
+        shading_id: u16,
+        shading_type: u8,
+        coords: &[f32],
+        stops: &[(f32, Vec<f32>)],
+        color_space: &str,
+    ) -> PDFShading {
+        let coords: Vec<String> = coords.iter().map(|c| c.to_string()).collect();
+        let dictionary = format!(
+            "/ShadingType {} /ColorSpace {} /Coords [{}] /Function {} /Extend [true true]",
+            shading_type,
+            color_space,
+            coords.join(" "),
+            PDFShading::get_function(stops)
+        );
+        PDFShading {
+            dictionary,
+            shading_id,
+            object_id: 0,
+        }
+    }
+    pub fn get_uid(&self) -> String {
+        format!("Sh{}", self.shading_id)
+    }
+    /// Check if this shading paints the same as the other one
+    pub fn matches(&self, other: &PDFShading) -> bool {
+        self.dictionary == other.dictionary
+    }
+    /// Exponential interpolation function for two stops,
+    /// stitching function of several exponential functions for more.
+    fn get_function(stops: &[(f32, Vec<f32>)]) -> String {
+        let mut stops = stops.to_vec();
+        if let Some(first) = stops.first().cloned() {
+            if first.0 > 0.0 {
+                stops.insert(0, (0.0, first.1));
+            }
+        }
+        if let Some(last) = stops.last().cloned() {
+            if last.0 < 1.0 || stops.len() == 1 {
+                stops.push((1.0, last.1));
+            }
+        }
+        let functions: Vec<String> = stops
+            .windows(2)
+            .map(|pair| {
+                format!(
+                    "<< /FunctionType 2 /Domain [0 1] /C0 [{}] /C1 [{}] /N 1 >>",
+                    PDFShading::get_components(&pair[0].1),
+                    PDFShading::get_components(&pair[1].1)
+                )
+            })
+            .collect();
+        if functions.len() == 1 {
+            return functions[0].clone();
+        }
+        let bounds: Vec<String> = stops[1..stops.len() - 1]
+            .iter()
+            .map(|stop| stop.0.to_string())
+            .collect();
+        let encode: Vec<&str> = functions.iter().map(|_| "0 1").collect();
+        format!(
+            "<< /FunctionType 3 /Domain [0 1] /Functions [ {} ] /Bounds [{}] /Encode [{}] >>",
+            functions.join(" "),
+            bounds.join(" "),
+            encode.join(" ")
+        )
+    }
+    fn get_components(components: &[f32]) -> String {
+        let components: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+        components.join(" ")
+    }
 }
 
+/// Separation colour space for a named spot colour, with a CMYK alternate.
 #[derive(Clone)]
-pub struct LinkAnnotation {
-    url: String,
-    x1: f32,
-    y1: f32,
-    x2: f32,
-    y2: f32,
+pub struct PDFColorSpace {
+    name: String,
+    cmyk: [f32; 4],
+    color_space_id: u16,
     object_id: u16,
 }
 
-impl LinkAnnotation {
-    pub fn new(url: &str, x1: f32, y1: f32, x2: f32, y2: f32) -> LinkAnnotation {
-        LinkAnnotation {
-            url: url.into(),
-            x1,
-            y1,
-            x2,
-            y2,
+impl PDFColorSpace {
+    pub fn new(color_space_id: u16, name: &str, cmyk: [f32; 4]) -> PDFColorSpace {
+        PDFColorSpace {
+            name: name.into(),
+            cmyk,
+            color_space_id,
             object_id: 0,
         }
     }
+    pub fn get_uid(&self) -> String {
+        format!("CS{}", self.color_space_id)
+    }
+    /// Same spot colour, the name and the CMYK fallback are equal.
+    pub fn matches(&self, name: &str, cmyk: &[f32; 4]) -> bool {
+        self.name == name && self.cmyk == *cmyk
+    }
+    fn get_array(&self) -> String {
+        format!(
+            "[/Separation /{} /DeviceCMYK << /FunctionType 2 /Domain [0 1] /C0 [0 0 0 0] /C1 [{} {} {} {}] /N 1 >>]",
+            encode_name(&self.name),
+            self.cmyk[0],
+            self.cmyk[1],
+            self.cmyk[2],
+            self.cmyk[3]
+        )
+    }
+}
+
+/// Encode text as a PDF name (without the leading slash),
+/// escaping delimiters, whitespace and non-ASCII bytes as #xx.
+pub fn encode_name(name: &str) -> String {
+    let mut output = String::new();
+    for byte in name.bytes() {
+        match byte {
+            b'!'..=b'~' if !b"#()<>[]{}/%".contains(&byte) => output.push(byte as char),
+            _ => output += &format!("#{:02X}", byte),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shading_stitching_function() {
+        let stops = vec![
+            (0.0, vec![1.0, 0.0, 0.0]),
+            (0.5, vec![0.0, 1.0, 0.0]),
+            (1.0, vec![0.0, 0.0, 1.0]),
+        ];
+        let shading = PDFShading::new(1, 2, &[0.0, 0.0, 1.0, 0.0], &stops, "/DeviceRGB");
+        assert_eq!(shading.get_uid(), "Sh1");
+        assert!(shading.dictionary.contains("/FunctionType 3"));
+        assert!(shading
+            .dictionary
+            .contains("/Bounds [0.5] /Encode [0 1 0 1]"));
+        let two_stops = PDFShading::new(2, 2, &[0.0, 0.0, 1.0, 0.0], &stops[1..], "/DeviceRGB");
+        // first stop at 0.5 gets padded to start of the domain
+        assert!(two_stops.dictionary.contains("/Bounds [0.5]"));
+        let single = PDFShading::new(
+            3,
+            3,
+            &[0.5, 0.5, 0.0, 0.5, 0.5, 0.5],
+            &stops[..1],
+            "/DeviceRGB",
+        );
+        assert!(single.dictionary.contains("/Function << /FunctionType 2"));
+    }
+
+    #[test]
+    fn test_identical_images_are_shared() {
+        let image = ImageData {
+            width: 1,
+            height: 1,
+            color_space: String::from("/DeviceGray"),
+            bits_per_component: 8,
+            filter: "/FlateDecode",
+            decode_parms: None,
+            decode: None,
+            data: vec![1, 2, 3],
+            smask: None,
+            orientation: 1,
+            dpi: None,
+        };
+        let mut other = image.clone();
+        other.data = vec![3, 2, 1];
+        let mut doc = PDFDocument::new();
+        let first = doc.add_image(&Rc::new(image.clone()));
+        assert_eq!(doc.add_image(&Rc::new(image)), first);
+        assert_ne!(doc.add_image(&Rc::new(other)), first);
+        assert_eq!(doc.images.len(), 2);
+    }
+
+    #[test]
+    fn test_separation_color_space() {
+        let color_space = PDFColorSpace::new(1, "PANTONE 185 C", [0.0, 0.91, 0.76, 0.0]);
+        assert_eq!(color_space.get_uid(), "CS1");
+        assert!(color_space
+            .get_array()
+            .starts_with("[/Separation /PANTONE#20185#20C /DeviceCMYK"));
+        assert_eq!(encode_name("A/B(1)"), "A#2FB#281#29");
+    }
+
+    #[test]
+    fn test_outline_tree() {
+        // 1, 1.1, 1.2, 1.2.1, 2 (a level 3 heading right after level 1 is nested too)
+        let tree = get_outline_tree(&[1, 2, 2, 3, 1, 3]);
+        assert_eq!(tree[0].parent, None);
+        assert_eq!(
+            (tree[0].first, tree[0].last, tree[0].count),
+            (Some(1), Some(2), 3)
+        );
+        assert_eq!((tree[1].parent, tree[1].next), (Some(0), Some(2)));
+        assert_eq!(
+            (tree[2].prev, tree[2].first, tree[2].count),
+            (Some(1), Some(3), 1)
+        );
+        assert_eq!(tree[3].parent, Some(2));
+        assert_eq!((tree[4].parent, tree[4].prev), (None, Some(0)));
+        assert_eq!(tree[0].next, Some(4));
+        assert_eq!(tree[5].parent, Some(4));
+    }
 }
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
-use super::json::{get_bool_from_js, get_number_from_js, JsContent, JsParamValue};
+use super::json::{get_bool_from_js, get_number_from_js, to_js_string, JsContent, JsParamValue};
+use super::svg::{parse_path, PathSegment};
+use super::units::{Point, Rect};
 
 #[derive(Debug, Clone, Copy)]
 pub enum VerticalAlign {
@@ -34,7 +36,7 @@ impl HorizontalAlign {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct TableStyle {
     pub grid_visible: bool,
     pub grid_width: f32,
@@ -59,13 +61,13 @@ impl TableStyle {
             vertical_align: VerticalAlign::Top,
         }
     }
-    pub fn from_content(content: &JsContent) -> TableStyle {
+    pub fn from_content(content: &JsContent) -> Result<TableStyle, String> {
         let mut table_style = TableStyle::new();
         if let Some(style) = content.params.get("style") {
             if let JsParamValue::Object(style) = style {
                 //json_out(&JsValue::from_serde(style).unwrap());
                 if let Some(grid) = style.get("grid") {
-                    TableStyle::get_grid(&mut table_style, grid);
+                    TableStyle::get_grid(&mut table_style, grid)?;
                 }
                 if let Some(padding) = style.get("padding") {
                     TableStyle::get_table_padding(&mut table_style, padding);
@@ -75,7 +77,7 @@ impl TableStyle {
                 }
             }
         }
-        table_style
+        Ok(table_style)
     }
     fn get_table_padding(table_style: &mut TableStyle, padding: &JsParamValue) {
         if let JsParamValue::Object(padding) = padding {
@@ -122,7 +124,7 @@ impl TableStyle {
         }
     }
 
-    fn get_grid(table_style: &mut TableStyle, grid: &JsParamValue) {
+    fn get_grid(table_style: &mut TableStyle, grid: &JsParamValue) -> Result<(), String> {
         table_style.grid_visible = true;
         if let JsParamValue::Object(grid) = grid {
             if let Some(width) = grid.get("width") {
@@ -132,26 +134,76 @@ impl TableStyle {
                 }
             }
             if let Some(color) = grid.get("color") {
-                if let Some(rgb_color) = Color::from_param(color) {
-                    table_style.grid_color = rgb_color;
-                }
+                table_style.grid_color = Color::from_param(color)?;
             }
         }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How the image is placed in its box when the aspect ratios differ.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFit {
+    /// Stretch to fill the box
+    Fill,
+    /// Scale to fit inside the box, centered
+    Contain,
+    /// Scale to cover the box, centered and cropped
+    Cover,
+}
+
+impl ImageFit {
+    pub fn from_content(content: &JsContent) -> Result<ImageFit, String> {
+        match content.params.get("fit") {
+            Some(JsParamValue::Text(fit)) => match fit.to_lowercase().as_str() {
+                "fill" => Ok(ImageFit::Fill),
+                "contain" => Ok(ImageFit::Contain),
+                "cover" => Ok(ImageFit::Cover),
+                _ => Err(format!(
+                    "Invalid image fit '{}', expected contain, cover or fill.",
+                    fit
+                )),
+            },
+            _ => Ok(ImageFit::Fill),
+        }
+    }
+    /// Position and size (x, y, width, height) of an image with the given aspect ratio
+    /// (width / height) in a box, relative to the bottom left corner of the box.
+    pub fn get_image_rect(self, aspect: f32, width: f32, height: f32) -> (f32, f32, f32, f32) {
+        let (image_width, image_height) = match self {
+            ImageFit::Fill => return (0.0, 0.0, width, height),
+            ImageFit::Contain if width / height > aspect => (height * aspect, height),
+            ImageFit::Contain => (width, width / aspect),
+            ImageFit::Cover if width / height > aspect => (width, width / aspect),
+            ImageFit::Cover => (height * aspect, height),
+        };
+        (
+            (width - image_width) / 2.0,
+            (height - image_height) / 2.0,
+            image_width,
+            image_height,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ImageStyle {
     pub horizontal_align: HorizontalAlign,
+    pub transparency: Transparency,
+    pub clip: Option<Clip>,
+    pub fit: ImageFit,
 }
 
 impl ImageStyle {
     pub fn new() -> ImageStyle {
         ImageStyle {
             horizontal_align: HorizontalAlign::Center,
+            transparency: Transparency::new(),
+            clip: None,
+            fit: ImageFit::Fill,
         }
     }
-    pub fn from_content(content: &JsContent) -> ImageStyle {
+    pub fn from_content(content: &JsContent) -> Result<ImageStyle, String> {
         let mut image_style = ImageStyle::new();
         if let Some(align) = content.params.get("align") {
             image_style.horizontal_align = if let JsParamValue::Text(image_align) = align {
@@ -164,22 +216,29 @@ impl ImageStyle {
                 HorizontalAlign::Left
             }
         }
-        image_style
+        image_style.transparency = Transparency::from_content(content)?;
+        if let Some(clip) = content.params.get("clip") {
+            image_style.clip = Clip::from_param(clip)?;
+        }
+        image_style.fit = ImageFit::from_content(content)?;
+        Ok(image_style)
     }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct PathStyle {
     pub horizontal_align: HorizontalAlign,
+    pub transparency: Transparency,
 }
 
 impl PathStyle {
     pub fn new() -> PathStyle {
         PathStyle {
             horizontal_align: HorizontalAlign::Center,
+            transparency: Transparency::new(),
         }
     }
-    pub fn from_content(content: &JsContent) -> PathStyle {
+    pub fn from_content(content: &JsContent) -> Result<PathStyle, String> {
         let mut path_style = PathStyle::new();
         if let Some(align) = content.params.get("align") {
             path_style.horizontal_align = if let JsParamValue::Text(path_align) = align {
@@ -192,67 +251,946 @@ impl PathStyle {
                 HorizontalAlign::Left
             }
         }
-        path_style
+        path_style.transparency = Transparency::from_content(content)?;
+        Ok(path_style)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct CellStyle {
-    pub background_color: Option<Color>,
+    pub background_color: Option<Fill>,
+    pub clip: Option<Clip>,
 }
 
 impl CellStyle {
     pub fn new() -> CellStyle {
         CellStyle {
             background_color: None,
+            clip: None,
+        }
+    }
+}
+
+/// Blend modes supported by the PDF transparency model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
+impl BlendMode {
+    /// Parse blend mode name, e.g. "multiply", "color-dodge" or "HardLight".
+    pub fn from_name(name: &str) -> Option<BlendMode> {
+        let name: String = name
+            .chars()
+            .filter(|c| *c != '-' && *c != '_' && *c != ' ')
+            .collect();
+        match name.to_lowercase().as_str() {
+            "normal" => Some(BlendMode::Normal),
+            "multiply" => Some(BlendMode::Multiply),
+            "screen" => Some(BlendMode::Screen),
+            "overlay" => Some(BlendMode::Overlay),
+            "darken" => Some(BlendMode::Darken),
+            "lighten" => Some(BlendMode::Lighten),
+            "colordodge" => Some(BlendMode::ColorDodge),
+            "colorburn" => Some(BlendMode::ColorBurn),
+            "hardlight" => Some(BlendMode::HardLight),
+            "softlight" => Some(BlendMode::SoftLight),
+            "difference" => Some(BlendMode::Difference),
+            "exclusion" => Some(BlendMode::Exclusion),
+            "hue" => Some(BlendMode::Hue),
+            "saturation" => Some(BlendMode::Saturation),
+            "color" => Some(BlendMode::Color),
+            "luminosity" => Some(BlendMode::Luminosity),
+            _ => None,
+        }
+    }
+    /// PDF name of the blend mode (without the leading slash).
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            BlendMode::Normal => "Normal",
+            BlendMode::Multiply => "Multiply",
+            BlendMode::Screen => "Screen",
+            BlendMode::Overlay => "Overlay",
+            BlendMode::Darken => "Darken",
+            BlendMode::Lighten => "Lighten",
+            BlendMode::ColorDodge => "ColorDodge",
+            BlendMode::ColorBurn => "ColorBurn",
+            BlendMode::HardLight => "HardLight",
+            BlendMode::SoftLight => "SoftLight",
+            BlendMode::Difference => "Difference",
+            BlendMode::Exclusion => "Exclusion",
+            BlendMode::Hue => "Hue",
+            BlendMode::Saturation => "Saturation",
+            BlendMode::Color => "Color",
+            BlendMode::Luminosity => "Luminosity",
+        }
+    }
+}
+
+/// Opacity and blending of an element, written to the page as an ExtGState resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transparency {
+    pub fill_opacity: f32,
+    pub stroke_opacity: f32,
+    pub blend_mode: BlendMode,
+}
+
+impl Transparency {
+    pub fn new() -> Transparency {
+        Transparency {
+            fill_opacity: 1.0,
+            stroke_opacity: 1.0,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+    pub fn from_content(content: &JsContent) -> Result<Transparency, String> {
+        let fill_opacity = get_number_from_js(content.params.get("fill_opacity"), 1.0);
+        let stroke_opacity = get_number_from_js(content.params.get("stroke_opacity"), 1.0);
+        let blend_mode = match content.params.get("blend_mode") {
+            Some(JsParamValue::Text(name)) => BlendMode::from_name(name)
+                .ok_or_else(|| format!("Unknown blend mode '{}'.", name))?,
+            Some(value) => {
+                return Err(format!("Invalid blend mode {}.", to_js_string(value)));
+            }
+            None => BlendMode::Normal,
+        };
+        Ok(Transparency {
+            fill_opacity: fill_opacity.clamp(0.0, 1.0),
+            stroke_opacity: stroke_opacity.clamp(0.0, 1.0),
+            blend_mode,
+        })
+    }
+    /// Fully opaque with normal blending, no graphics state needed.
+    pub fn is_opaque(&self) -> bool {
+        self.fill_opacity >= 1.0
+            && self.stroke_opacity >= 1.0
+            && self.blend_mode == BlendMode::Normal
+    }
+}
+
+/// Rotation (degrees, counterclockwise), scaling and skew (degrees) applied
+/// around the centre of an element.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub rotate: f32,
+    pub scale: (f32, f32),
+    pub skew: (f32, f32),
+}
+
+impl Transform {
+    pub fn new() -> Transform {
+        Transform {
+            rotate: 0.0,
+            scale: (1.0, 1.0),
+            skew: (0.0, 0.0),
+        }
+    }
+    /// Reads `rotate`, `scale` (number or [x, y]) and `skew` (x angle or [x, y]) params.
+    pub fn from_content(content: &JsContent) -> Transform {
+        let rotate = get_number_from_js(content.params.get("rotate"), 0.0);
+        let scale = Transform::get_pair(content.params.get("scale"), 1.0, true);
+        let skew = Transform::get_pair(content.params.get("skew"), 0.0, false);
+        Transform {
+            rotate,
+            scale,
+            skew,
         }
     }
+    fn get_pair(value: Option<&JsParamValue>, default: f32, uniform: bool) -> (f32, f32) {
+        match value {
+            Some(JsParamValue::Number(n)) => (*n, if uniform { *n } else { default }),
+            Some(JsParamValue::Array(values)) => {
+                let x = get_number_from_js(values.first(), default);
+                let y = get_number_from_js(values.get(1), if uniform { x } else { default });
+                (x, y)
+            }
+            _ => (default, default),
+        }
+    }
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::new()
+    }
+    /// Linear part of the transformation matrix as [a b c d] of the `cm` operator.
+    /// Skew is applied first, then scaling and rotation.
+    pub fn get_matrix(&self) -> [f32; 4] {
+        let (sin, cos) = self.rotate.to_radians().sin_cos();
+        let (sx, sy) = self.scale;
+        let (tan_x, tan_y) = (
+            self.skew.0.to_radians().tan(),
+            self.skew.1.to_radians().tan(),
+        );
+        // rows of rotate * scale * skew (column vectors)
+        let m00 = cos * sx - sin * sy * tan_y;
+        let m01 = cos * sx * tan_x - sin * sy;
+        let m10 = sin * sx + cos * sy * tan_y;
+        let m11 = sin * sx * tan_x + cos * sy;
+        // drop rounding noise of quarter turns, e.g. cos(90°)
+        let clean = |value: f32| if value.abs() < 1e-6 { 0.0 } else { value };
+        [clean(m00), clean(m10), clean(m01), clean(m11)]
+    }
+    /// Size of the bounding box of a width x height box after transformation.
+    pub fn get_bounds(&self, width: f32, height: f32) -> (f32, f32) {
+        let [a, b, c, d] = self.get_matrix();
+        (
+            a.abs() * width + c.abs() * height,
+            b.abs() * width + d.abs() * height,
+        )
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Color {
-    pub r: f32,
-    pub g: f32,
-    pub b: f32,
+/// Colour in one of the supported colour spaces. Spot colours are written
+/// as Separation colour spaces with a CMYK fallback for devices lacking the ink.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Color {
+    Rgb {
+        r: f32,
+        g: f32,
+        b: f32,
+        a: f32,
+    },
+    Cmyk {
+        c: f32,
+        m: f32,
+        y: f32,
+        k: f32,
+    },
+    Gray(f32),
+    Spot {
+        name: String,
+        tint: f32,
+        cmyk: [f32; 4],
+    },
 }
 
 impl Color {
     pub fn new(r: f32, g: f32, b: f32) -> Color {
-        Color { r, g, b }
+        Color::Rgb { r, g, b, a: 1.0 }
+    }
+    /// Parse optional colour parameter, missing parameter gives the default.
+    pub fn from_param_or_default(
+        value: Option<&JsParamValue>,
+        default: Color,
+    ) -> Result<Color, String> {
+        match value {
+            Some(value) => Color::from_param(value),
+            None => Ok(default),
+        }
     }
-    pub fn from_param_or_default(value: Option<&JsParamValue>, default: Color) -> Color {
-        if let Some(value) = value {
-            if let Some(color) = Color::from_param(value) {
-                return color;
+    /// Parse colour from `[r, g, b]` (0.0-1.0 or 0-255), `"#rrggbb"`, `"#rgb"`,
+    /// `"#rrggbbaa"`, `"rgb(r, g, b)"`, `"rgba(r, g, b, a)"`, CSS colour names,
+    /// `{"cmyk": [c, m, y, k]}`, `{"gray": g}` or `{"spot": "Name", "cmyk": [c, m, y, k], "tint": t}`.
+    pub fn from_param(value: &JsParamValue) -> Result<Color, String> {
+        match value {
+            JsParamValue::Array(color_arr) => Color::from_array(color_arr),
+            JsParamValue::Text(text) => Color::from_text(text),
+            JsParamValue::Object(params) => {
+                let cmyk = match params.get("cmyk") {
+                    Some(JsParamValue::Array(cmyk)) => Some(Color::get_unit_components(cmyk, 4)?),
+                    Some(value) => {
+                        return Err(format!(
+                            "Invalid CMYK colour {}: expected [c, m, y, k] with values between 0.0 and 1.0.",
+                            to_js_string(value)
+                        ))
+                    }
+                    None => None,
+                };
+                if let Some(name) = params.get("spot") {
+                    let name = match name {
+                        JsParamValue::Text(name) if !name.is_empty() => name,
+                        _ => {
+                            return Err(format!("Invalid spot colour name {}.", to_js_string(name)))
+                        }
+                    };
+                    let cmyk = cmyk.ok_or_else(|| {
+                        format!(
+                            "Spot colour '{}' needs a CMYK fallback (\"cmyk\": [c, m, y, k]).",
+                            name
+                        )
+                    })?;
+                    let tint = get_number_from_js(params.get("tint"), 1.0);
+                    return Ok(Color::Spot {
+                        name: name.to_owned(),
+                        tint: tint.clamp(0.0, 1.0),
+                        cmyk: [cmyk[0], cmyk[1], cmyk[2], cmyk[3]],
+                    });
+                }
+                if let Some(cmyk) = cmyk {
+                    return Ok(Color::Cmyk {
+                        c: cmyk[0],
+                        m: cmyk[1],
+                        y: cmyk[2],
+                        k: cmyk[3],
+                    });
+                }
+                match params.get("gray") {
+                    Some(JsParamValue::Number(gray)) if (0.0..=1.0).contains(gray) => {
+                        Ok(Color::Gray(*gray))
+                    }
+                    Some(gray) => Err(format!(
+                        "Invalid gray level {}: expected a number between 0.0 and 1.0.",
+                        to_js_string(gray)
+                    )),
+                    None => Err(format!(
+                        "Invalid colour {}: expected \"cmyk\", \"gray\" or \"spot\".",
+                        to_js_string(value)
+                    )),
+                }
             }
+            _ => Err(format!(
+                "Invalid colour {}: expected an array, a string or an object.",
+                to_js_string(value)
+            )),
         }
-        default
     }
-    pub fn from_param(color_arr: &JsParamValue) -> Option<Color> {
-        let mut rgb_color = Color::new(0.0, 0.0, 0.0);
-        if let JsParamValue::Array(color_arr) = color_arr {
-            if color_arr.len() != 3 {
+    /// `[r, g, b]` with values between 0.0 and 1.0, or between 0 and 255
+    /// if any of the values is greater than 1.
+    fn from_array(values: &[JsParamValue]) -> Result<Color, String> {
+        let error = || {
+            format!(
+                "Invalid colour {}: expected [r, g, b] with values between 0.0 and 1.0 or 0 and 255.",
+                to_js_string(&JsParamValue::Array(values.to_vec()))
+            )
+        };
+        if values.len() != 3 {
+            return Err(error());
+        }
+        let mut rgb: Vec<f32> = Vec::new();
+        for value in values {
+            match value {
+                JsParamValue::Number(n) if *n >= 0.0 && *n <= 255.0 => rgb.push(*n),
+                _ => return Err(error()),
+            }
+        }
+        if rgb.iter().any(|c| *c > 1.0) {
+            rgb = rgb.iter().map(|c| c / 255.0).collect();
+        }
+        Ok(Color::new(rgb[0], rgb[1], rgb[2]))
+    }
+    fn from_text(text: &str) -> Result<Color, String> {
+        let value = text.trim().to_lowercase();
+        if let Some(hex) = value.strip_prefix('#') {
+            return Color::from_hex(hex).ok_or_else(|| {
+                format!(
+                    "Invalid colour '{}': expected #rgb, #rrggbb or #rrggbbaa.",
+                    text
+                )
+            });
+        }
+        if value.starts_with("rgb") {
+            return Color::from_css_rgb(&value).ok_or_else(|| {
+                format!(
+                    "Invalid colour '{}': expected rgb(r, g, b) or rgba(r, g, b, a) with values between 0 and 255.",
+                    text
+                )
+            });
+        }
+        if value == "transparent" {
+            return Ok(Color::Rgb {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 0.0,
+            });
+        }
+        match NAMED_COLORS.iter().find(|(name, _)| *name == value) {
+            Some((_, rgb)) => Ok(Color::new(
+                ((rgb >> 16) & 0xFF) as f32 / 255.0,
+                ((rgb >> 8) & 0xFF) as f32 / 255.0,
+                (rgb & 0xFF) as f32 / 255.0,
+            )),
+            None => Err(format!("Unknown colour name '{}'.", text)),
+        }
+    }
+    /// Hex digits of #rgb, #rgba, #rrggbb or #rrggbbaa, without the hash.
+    fn from_hex(hex: &str) -> Option<Color> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digits: Vec<u8> = match hex.len() {
+            3 | 4 => hex
+                .chars()
+                .map(|c| u8::from_str_radix(&format!("{}{}", c, c), 16).unwrap())
+                .collect(),
+            6 | 8 => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect(),
+            _ => return None,
+        };
+        Some(Color::Rgb {
+            r: f32::from(digits[0]) / 255.0,
+            g: f32::from(digits[1]) / 255.0,
+            b: f32::from(digits[2]) / 255.0,
+            a: digits.get(3).map_or(1.0, |a| f32::from(*a) / 255.0),
+        })
+    }
+    /// CSS functional notation, e.g. rgb(26, 115, 232) or rgba(26, 115, 232, 0.5).
+    fn from_css_rgb(value: &str) -> Option<Color> {
+        let args = value
+            .strip_prefix("rgba")
+            .or_else(|| value.strip_prefix("rgb"))?
+            .trim()
+            .strip_prefix('(')?
+            .strip_suffix(')')?;
+        let args: Vec<&str> = args
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .collect();
+        if args.len() != 3 && args.len() != 4 {
+            return None;
+        }
+        let mut rgb: Vec<f32> = Vec::new();
+        for arg in &args[..3] {
+            let component = match arg.strip_suffix('%') {
+                Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+                None => arg.parse::<f32>().ok()? / 255.0,
+            };
+            if !(0.0..=1.0).contains(&component) {
                 return None;
             }
-            for (index, color) in color_arr.iter().enumerate() {
-                if let JsParamValue::Number(color) = color {
-                    match index {
-                        0 => {
-                            rgb_color.r = *color;
+            rgb.push(component);
+        }
+        let a = match args.get(3) {
+            Some(arg) => match arg.strip_suffix('%') {
+                Some(percent) => percent.parse::<f32>().ok()? / 100.0,
+                None => arg.parse::<f32>().ok()?,
+            },
+            None => 1.0,
+        };
+        if !(0.0..=1.0).contains(&a) {
+            return None;
+        }
+        Some(Color::Rgb {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+            a,
+        })
+    }
+    fn get_unit_components(values: &[JsParamValue], len: usize) -> Result<Vec<f32>, String> {
+        let mut components: Vec<f32> = Vec::new();
+        for value in values {
+            match value {
+                JsParamValue::Number(n) if (0.0..=1.0).contains(n) => components.push(*n),
+                _ => components.clear(),
+            }
+        }
+        if components.len() != len || values.len() != len {
+            return Err(format!(
+                "Invalid colour components {}: expected {} values between 0.0 and 1.0.",
+                to_js_string(&JsParamValue::Array(values.to_vec())),
+                len
+            ));
+        }
+        Ok(components)
+    }
+    /// Opacity of the colour, 1.0 unless given with alpha (e.g. #rrggbbaa).
+    pub fn get_alpha(&self) -> f32 {
+        match self {
+            Color::Rgb { a, .. } => *a,
+            _ => 1.0,
+        }
+    }
+    /// Device colour space name and colour components. Spot colours give
+    /// their CMYK fallback, multiplied by the tint.
+    pub fn get_device_components(&self) -> (&'static str, Vec<f32>) {
+        match self {
+            Color::Rgb { r, g, b, .. } => ("DeviceRGB", vec![*r, *g, *b]),
+            Color::Cmyk { c, m, y, k } => ("DeviceCMYK", vec![*c, *m, *y, *k]),
+            Color::Gray(gray) => ("DeviceGray", vec![*gray]),
+            Color::Spot { tint, cmyk, .. } => {
+                ("DeviceCMYK", cmyk.iter().map(|c| c * tint).collect())
+            }
+        }
+    }
+    /// Approximate the colour in DeviceRGB.
+    pub fn to_rgb(&self) -> (f32, f32, f32) {
+        let (_, components) = self.get_device_components();
+        match components.len() {
+            1 => (components[0], components[0], components[0]),
+            4 => {
+                let k = 1.0 - components[3];
+                (
+                    (1.0 - components[0]) * k,
+                    (1.0 - components[1]) * k,
+                    (1.0 - components[2]) * k,
+                )
+            }
+            _ => (components[0], components[1], components[2]),
+        }
+    }
+}
+
+/// CSS named colours.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientKind {
+    Linear,
+    Radial,
+}
+
+/// Axial or radial colour gradient. Coordinates are relative to the bounding box
+/// of the filled element: (0, 0) is the top left and (1, 1) the bottom right corner.
+#[derive(Debug, Clone)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    pub coords: Vec<f32>,
+    pub stops: Vec<(f32, Color)>,
+}
+
+impl Gradient {
+    /// Parse gradient definition, e.g.
+    /// `{"linear": [0, 0, 1, 0], "stops": [[0, [1, 0, 0]], [1, [0, 0, 1]]]}` or
+    /// `{"radial": [0.5, 0.5, 0, 0.5, 0.5, 0.5], "stops": [[1, 1, 1], [0, 0, 0]]}`.
+    /// Stops given as plain colours are spread evenly. Returns None if the value
+    /// is not a gradient definition.
+    pub fn from_param(value: &JsParamValue) -> Result<Option<Gradient>, String> {
+        if let JsParamValue::Object(params) = value {
+            let (kind, coords) = if let Some(coords) = params.get("linear") {
+                (GradientKind::Linear, Gradient::get_coords(coords, 4)?)
+            } else if let Some(coords) = params.get("radial") {
+                (GradientKind::Radial, Gradient::get_coords(coords, 6)?)
+            } else {
+                return Ok(None);
+            };
+            let coords = coords.unwrap_or_else(|| match kind {
+                GradientKind::Linear => vec![0.0, 0.0, 1.0, 0.0],
+                GradientKind::Radial => vec![0.5, 0.5, 0.0, 0.5, 0.5, 0.5],
+            });
+            let stops = match params.get("stops") {
+                Some(JsParamValue::Array(stops)) => Gradient::get_stops(stops)?,
+                _ => Vec::new(),
+            };
+            if stops.is_empty() {
+                return Err(String::from("Gradient needs at least one colour stop."));
+            }
+            return Ok(Some(Gradient {
+                kind,
+                coords,
+                stops,
+            }));
+        }
+        Ok(None)
+    }
+    /// Gradient coordinates, `true` gives the default coordinates.
+    fn get_coords(value: &JsParamValue, len: usize) -> Result<Option<Vec<f32>>, String> {
+        match value {
+            JsParamValue::Boolean(true) => Ok(None),
+            JsParamValue::Array(values) => {
+                let coords: Vec<f32> = values
+                    .iter()
+                    .filter_map(|v| match v {
+                        JsParamValue::Number(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                if coords.len() == len && values.len() == len {
+                    Ok(Some(coords))
+                } else {
+                    Err(format!(
+                        "Invalid gradient coordinates {}: expected {} numbers.",
+                        to_js_string(value),
+                        len
+                    ))
+                }
+            }
+            _ => Err(format!(
+                "Invalid gradient coordinates {}: expected {} numbers.",
+                to_js_string(value),
+                len
+            )),
+        }
+    }
+    fn get_stops(values: &[JsParamValue]) -> Result<Vec<(f32, Color)>, String> {
+        let mut stops: Vec<(f32, Color)> = Vec::new();
+        let last_index = (values.len() as f32 - 1.0).max(1.0);
+        for (index, stop) in values.iter().enumerate() {
+            if let JsParamValue::Array(stop_arr) = stop {
+                // [offset, color] or just color
+                if let (2, Some(JsParamValue::Number(offset))) = (stop_arr.len(), stop_arr.first())
+                {
+                    let color = Color::from_param(&stop_arr[1])?;
+                    stops.push((offset.clamp(0.0, 1.0), color));
+                    continue;
+                }
+            }
+            let color = Color::from_param(stop)?;
+            stops.push((index as f32 / last_index, color));
+        }
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(stops)
+    }
+}
+
+/// Fill of a shape: solid colour or gradient.
+#[derive(Debug, Clone)]
+pub enum Fill {
+    Solid(Color),
+    Gradient(Gradient),
+}
+
+impl Fill {
+    pub fn from_param(value: &JsParamValue) -> Result<Fill, String> {
+        if let Some(gradient) = Gradient::from_param(value)? {
+            return Ok(Fill::Gradient(gradient));
+        }
+        Color::from_param(value).map(Fill::Solid)
+    }
+}
+
+/// Bezier control point distance for quarter circles
+const KAPPA: f32 = 0.552_284_8;
+
+/// Clipping shape of an image or a table cell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Clip {
+    Rect,
+    Rounded(f32),
+    Circle,
+    Ellipse,
+    /// SVG path relative to the top left corner, optionally scaled from a view box.
+    Path {
+        segments: Vec<PathSegment>,
+        viewbox: Option<(f32, f32)>,
+    },
+}
+
+impl Clip {
+    /// Parse `"clip"` param: true, "rect", "rounded", "circle", "ellipse", SVG path data,
+    /// `{"rounded": radius}` or `{"path": "M ...", "viewbox": [width, height]}`.
+    pub fn from_param(value: &JsParamValue) -> Result<Option<Clip>, String> {
+        let error = || format!("Invalid clip {}.", to_js_string(value));
+        match value {
+            JsParamValue::Boolean(clip) => Ok(if *clip { Some(Clip::Rect) } else { None }),
+            JsParamValue::Text(text) => match text.trim().to_lowercase().as_str() {
+                "rect" => Ok(Some(Clip::Rect)),
+                "rounded" => Ok(Some(Clip::Rounded(8.0))),
+                "circle" => Ok(Some(Clip::Circle)),
+                "ellipse" => Ok(Some(Clip::Ellipse)),
+                _ => Ok(Some(Clip::Path {
+                    segments: parse_path(text)?,
+                    viewbox: None,
+                })),
+            },
+            JsParamValue::Object(clip) => {
+                if let Some(radius) = clip.get("rounded") {
+                    match radius {
+                        JsParamValue::Number(radius) if *radius >= 0.0 => {
+                            Ok(Some(Clip::Rounded(*radius)))
                         }
-                        1 => {
-                            rgb_color.g = *color;
+                        _ => Err(error()),
+                    }
+                } else if let Some(JsParamValue::Text(data)) = clip.get("path") {
+                    let viewbox = match clip.get("viewbox") {
+                        Some(JsParamValue::Array(size)) => match size.as_slice() {
+                            [JsParamValue::Number(w), JsParamValue::Number(h)]
+                                if *w > 0.0 && *h > 0.0 =>
+                            {
+                                Some((*w, *h))
+                            }
+                            _ => return Err(error()),
+                        },
+                        Some(_) => return Err(error()),
+                        None => None,
+                    };
+                    Ok(Some(Clip::Path {
+                        segments: parse_path(data)?,
+                        viewbox,
+                    }))
+                } else {
+                    Err(error())
+                }
+            }
+            _ => Err(error()),
+        }
+    }
+    /// Path operators of the shape in the rect (top left corner at rect.x, rect.y).
+    pub fn get_path_ops(&self, rect: Rect) -> String {
+        let (x, y, w, h) = (rect.x, rect.y, rect.w, rect.h);
+        match self {
+            Clip::Rect => format!("{} {} {} {} re", x, y, w, -h),
+            Clip::Rounded(radius) => {
+                let r = radius.min(w / 2.0).min(h / 2.0);
+                let k = r * (1.0 - KAPPA);
+                let (right, bottom) = (x + w, y - h);
+                let mut ops = format!("{} {} m\n", x + r, y);
+                ops += &format!("{} {} l\n", right - r, y);
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    right - k,
+                    y,
+                    right,
+                    y - k,
+                    right,
+                    y - r
+                );
+                ops += &format!("{} {} l\n", right, bottom + r);
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    right,
+                    bottom + k,
+                    right - k,
+                    bottom,
+                    right - r,
+                    bottom
+                );
+                ops += &format!("{} {} l\n", x + r, bottom);
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    x + k,
+                    bottom,
+                    x,
+                    bottom + k,
+                    x,
+                    bottom + r
+                );
+                ops += &format!("{} {} l\n", x, y - r);
+                ops += &format!("{} {} {} {} {} {} c\nh", x, y - k, x + k, y, x + r, y);
+                ops
+            }
+            Clip::Circle | Clip::Ellipse => {
+                let (rx, ry) = if let Clip::Circle = self {
+                    let r = w.min(h) / 2.0;
+                    (r, r)
+                } else {
+                    (w / 2.0, h / 2.0)
+                };
+                let (cx, cy) = (x + w / 2.0, y - h / 2.0);
+                let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+                let mut ops = format!("{} {} m\n", cx + rx, cy);
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    cx + rx,
+                    cy + ky,
+                    cx + kx,
+                    cy + ry,
+                    cx,
+                    cy + ry
+                );
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    cx - kx,
+                    cy + ry,
+                    cx - rx,
+                    cy + ky,
+                    cx - rx,
+                    cy
+                );
+                ops += &format!(
+                    "{} {} {} {} {} {} c\n",
+                    cx - rx,
+                    cy - ky,
+                    cx - kx,
+                    cy - ry,
+                    cx,
+                    cy - ry
+                );
+                ops += &format!(
+                    "{} {} {} {} {} {} c\nh",
+                    cx + kx,
+                    cy - ry,
+                    cx + rx,
+                    cy - ky,
+                    cx + rx,
+                    cy
+                );
+                ops
+            }
+            Clip::Path { segments, viewbox } => {
+                let (sx, sy) = match viewbox {
+                    Some((vw, vh)) => (w / vw, h / vh),
+                    None => (1.0, 1.0),
+                };
+                // SVG y axis points down from the top left corner
+                let map = |p: &Point| (x + p.x * sx, y - p.y * sy);
+                let mut ops: Vec<String> = Vec::new();
+                for segment in segments {
+                    ops.push(match segment {
+                        PathSegment::MoveTo(p) => {
+                            let (px, py) = map(p);
+                            format!("{} {} m", px, py)
                         }
-                        2 => {
-                            rgb_color.b = *color;
+                        PathSegment::LineTo(p) => {
+                            let (px, py) = map(p);
+                            format!("{} {} l", px, py)
                         }
-                        _ => (),
-                    }
+                        PathSegment::CurveTo(c1, c2, p) => {
+                            let (x1, y1) = map(c1);
+                            let (x2, y2) = map(c2);
+                            let (px, py) = map(p);
+                            format!("{} {} {} {} {} {} c", x1, y1, x2, y2, px, py)
+                        }
+                        PathSegment::Close => String::from("h"),
+                    });
                 }
+                ops.join("\n")
             }
-            return Some(rgb_color);
         }
-        None
     }
 }
 
@@ -265,6 +1203,7 @@ pub struct ParagraphStyle {
     pub wrap: bool,
     pub color: Color,
     pub link_color: Color,
+    pub transparency: Transparency,
 }
 
 impl ParagraphStyle {
@@ -282,9 +1221,10 @@ impl ParagraphStyle {
             wrap: true,
             color: Color::new(0.0, 0.0, 0.0),
             link_color: Color::new(1.0, 0.0, 0.0),
+            transparency: Transparency::new(),
         }
     }
-    pub fn from_content(content: &JsContent, p_font_size: f32) -> ParagraphStyle {
+    pub fn from_content(content: &JsContent, p_font_size: f32) -> Result<ParagraphStyle, String> {
         let p_leading = get_number_from_js(content.params.get("leading"), p_font_size + 2.0);
         let p_padding = ParagraphStyle::get_paragraph_padding(&content, p_font_size);
         let p_align = HorizontalAlign::from_content(&content);
@@ -297,26 +1237,15 @@ impl ParagraphStyle {
             None
         };
         let p_bullet_indent = get_number_from_js(content.params.get("bullet_indent"), 0.0);
-        let p_color = if let Some(color) = content.params.get("color") {
-            if let Some(rgb_color) = Color::from_param(color) {
-                rgb_color
-            } else {
-                Color::new(0.0, 0.0, 0.0)
-            }
-        } else {
-            Color::new(0.0, 0.0, 0.0)
-        };
-        let link_color = if let Some(color) = content.params.get("link_color") {
-            if let Some(rgb_color) = Color::from_param(color) {
-                rgb_color
-            } else {
-                Color::new(0.0, 0.0, 0.0)
-            }
-        } else {
-            Color::new(0.0, 0.0, 0.0)
-        };
+        let p_color =
+            Color::from_param_or_default(content.params.get("color"), Color::new(0.0, 0.0, 0.0))?;
+        let link_color = Color::from_param_or_default(
+            content.params.get("link_color"),
+            Color::new(0.0, 0.0, 0.0),
+        )?;
         let wrap = get_bool_from_js(content.params.get("wrap"), true);
-        ParagraphStyle {
+        let transparency = Transparency::from_content(content)?;
+        Ok(ParagraphStyle {
             leading: p_leading,
             align: p_align,
             bullet: p_bullet,
@@ -325,7 +1254,8 @@ impl ParagraphStyle {
             wrap,
             color: p_color,
             link_color,
-        }
+            transparency,
+        })
     }
     fn get_paragraph_padding(content: &JsContent, font_size: f32) -> (f32, f32, f32, f32) {
         let mut padding_top = font_size / 2.0;
@@ -382,3 +1312,147 @@ impl ParagraphStyle {
         (padding_top, padding_left, padding_bottom, padding_right)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn parse_color(json: &str) -> Option<Color> {
+        let value: JsParamValue = serde_json::from_str(json).unwrap();
+        Color::from_param(&value).ok()
+    }
+
+    #[test]
+    fn test_color_spaces() {
+        assert_eq!(parse_color("[1, 0.5, 0]"), Some(Color::new(1.0, 0.5, 0.0)));
+        assert_eq!(
+            parse_color(r#"{"cmyk": [0, 1, 1, 0.2]}"#),
+            Some(Color::Cmyk {
+                c: 0.0,
+                m: 1.0,
+                y: 1.0,
+                k: 0.2
+            })
+        );
+        assert_eq!(parse_color(r#"{"gray": 0.5}"#), Some(Color::Gray(0.5)));
+        let spot = parse_color(r#"{"spot": "PANTONE 185 C", "cmyk": [0, 0.91, 0.76, 0]}"#);
+        assert_eq!(
+            spot.unwrap().get_device_components(),
+            ("DeviceCMYK", vec![0.0, 0.91, 0.76, 0.0])
+        );
+        // spot colour needs a fallback
+        assert_eq!(parse_color(r#"{"spot": "Gold"}"#), None);
+        assert_eq!(Color::Gray(0.25).to_rgb(), (0.25, 0.25, 0.25));
+    }
+
+    #[test]
+    fn test_color_notations() {
+        let blue = Some(Color::new(26.0 / 255.0, 115.0 / 255.0, 232.0 / 255.0));
+        assert_eq!(parse_color(r##""#1a73e8""##), blue);
+        assert_eq!(parse_color(r#""rgb(26, 115, 232)""#), blue);
+        assert_eq!(parse_color("[26, 115, 232]"), blue);
+        assert_eq!(
+            parse_color(r#""Navy""#),
+            Some(Color::new(0.0, 0.0, 128.0 / 255.0))
+        );
+        assert_eq!(parse_color(r##""#f00""##), Some(Color::new(1.0, 0.0, 0.0)));
+        let translucent = parse_color(r##""#ff000080""##).unwrap();
+        assert_eq!(translucent.get_alpha(), 128.0 / 255.0);
+        assert_eq!(
+            parse_color(r#""rgba(0, 0, 0, 0.5)""#).unwrap().get_alpha(),
+            0.5
+        );
+    }
+
+    #[test]
+    fn test_invalid_colors() {
+        let invalid = |json: &str| {
+            let value: JsParamValue = serde_json::from_str(json).unwrap();
+            Color::from_param(&value).unwrap_err()
+        };
+        assert_eq!(
+            invalid(r#""nosuchcolor""#),
+            "Unknown colour name 'nosuchcolor'."
+        );
+        assert!(invalid(r##""#12""##).starts_with("Invalid colour '#12'"));
+        assert!(invalid("[1, 2]").starts_with("Invalid colour [1, 2]"));
+        assert!(invalid("[0, 0, 300]").starts_with("Invalid colour [0, 0, 300]"));
+        assert!(invalid(r#""rgb(0, 0)""#).starts_with("Invalid colour 'rgb(0, 0)'"));
+    }
+
+    #[test]
+    fn test_blend_modes() {
+        let content = |json: &str| -> JsContent { serde_json::from_str(json).unwrap() };
+        let transparency =
+            Transparency::from_content(&content(r#"{"params": {"blend_mode": "color-dodge"}}"#));
+        assert_eq!(transparency.unwrap().blend_mode, BlendMode::ColorDodge);
+        assert!(Transparency::from_content(&content(r#"{"params": {}}"#))
+            .unwrap()
+            .is_opaque());
+        assert_eq!(
+            Transparency::from_content(&content(r#"{"params": {"blend_mode": "mix"}}"#))
+                .unwrap_err(),
+            "Unknown blend mode 'mix'."
+        );
+        assert!(Transparency::from_content(&content(r#"{"params": {"blend_mode": 1}}"#)).is_err());
+    }
+
+    #[test]
+    fn test_transform_bounds() {
+        let mut transform = Transform::new();
+        assert!(transform.is_identity());
+        transform.rotate = 90.0;
+        let (width, height) = transform.get_bounds(100.0, 20.0);
+        assert!((width - 20.0).abs() < 1e-4 && (height - 100.0).abs() < 1e-4);
+        transform.rotate = 0.0;
+        transform.scale = (2.0, 0.5);
+        assert_eq!(transform.get_bounds(100.0, 20.0), (200.0, 10.0));
+        transform.scale = (1.0, 1.0);
+        transform.skew = (45.0, 0.0);
+        let (width, height) = transform.get_bounds(100.0, 20.0);
+        assert!((width - 120.0).abs() < 1e-4 && (height - 20.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_image_fit_rect() {
+        // 2:1 image in a 100 x 100 box
+        assert_eq!(
+            ImageFit::Fill.get_image_rect(2.0, 100.0, 100.0),
+            (0.0, 0.0, 100.0, 100.0)
+        );
+        assert_eq!(
+            ImageFit::Contain.get_image_rect(2.0, 100.0, 100.0),
+            (0.0, 25.0, 100.0, 50.0)
+        );
+        assert_eq!(
+            ImageFit::Cover.get_image_rect(2.0, 100.0, 100.0),
+            (-50.0, 0.0, 200.0, 100.0)
+        );
+    }
+
+    #[test]
+    fn test_clip_shapes() {
+        let parse_clip = |json: &str| {
+            let value: JsParamValue = serde_json::from_str(json).unwrap();
+            Clip::from_param(&value)
+        };
+        assert_eq!(parse_clip("true"), Ok(Some(Clip::Rect)));
+        assert_eq!(parse_clip("false"), Ok(None));
+        assert_eq!(
+            parse_clip(r#"{"rounded": 4}"#),
+            Ok(Some(Clip::Rounded(4.0)))
+        );
+        assert!(parse_clip(r#"{"rounded": "big"}"#).is_err());
+        assert!(parse_clip(r#""star""#).is_err());
+        let rect = Rect::new(10.0, 100.0, 50.0, 20.0);
+        assert_eq!(Clip::Rect.get_path_ops(rect), "10 100 50 -20 re");
+        let triangle = parse_clip(r#"{"path": "M0 0 L2 0 L1 2 Z", "viewbox": [2, 2]}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            triangle.get_path_ops(rect),
+            "10 100 m\n60 100 l\n35 80 l\nh"
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use super::units::Point;
+
+/// Path segment in absolute coordinates. SVG lines, quadratic curves
+/// and arcs are converted to the segments supported by PDF path operators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathSegment {
+    MoveTo(Point),
+    LineTo(Point),
+    CurveTo(Point, Point, Point),
+    Close,
+}
+
+/// Parse SVG path data (`d` attribute), e.g. "M 0 0 L 10 0 A 5 5 0 0 1 10 10 Z".
+/// Coordinates are kept as in SVG, y axis pointing down.
+pub fn parse_path(data: &str) -> Result<Vec<PathSegment>, String> {
+    let mut parser = PathParser {
+        chars: data.chars().collect(),
+        pos: 0,
+    };
+    let error = |message: &str| format!("Invalid SVG path '{}': {}.", data, message);
+    let mut segments: Vec<PathSegment> = Vec::new();
+    let mut current = Point { x: 0.0, y: 0.0 };
+    let mut start = current;
+    // last control point for smooth curves (S and T)
+    let mut last_cubic: Option<Point> = None;
+    let mut last_quad: Option<Point> = None;
+    let mut command: Option<char> = None;
+    loop {
+        parser.skip_separators();
+        let next = match parser.peek() {
+            Some(c) => c,
+            None => break,
+        };
+        if next.is_ascii_alphabetic() {
+            parser.pos += 1;
+            command = Some(next);
+        } else if command.is_none() {
+            return Err(error("expected a command"));
+        }
+        let cmd = command.unwrap();
+        let relative = cmd.is_ascii_lowercase();
+        let offset = if relative {
+            current
+        } else {
+            Point { x: 0.0, y: 0.0 }
+        };
+        let mut cubic = None;
+        let mut quad = None;
+        let upper = cmd.to_ascii_uppercase();
+        match upper {
+            'M' => {
+                let point = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                segments.push(PathSegment::MoveTo(point));
+                current = point;
+                start = point;
+                // following coordinate pairs are implicit line commands
+                command = Some(if relative { 'l' } else { 'L' });
+            }
+            'L' => {
+                current = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                segments.push(PathSegment::LineTo(current));
+            }
+            'H' => {
+                let x = parser.number().ok_or_else(|| error("expected number"))?;
+                current = Point {
+                    x: x + offset.x,
+                    y: current.y,
+                };
+                segments.push(PathSegment::LineTo(current));
+            }
+            'V' => {
+                let y = parser.number().ok_or_else(|| error("expected number"))?;
+                current = Point {
+                    x: current.x,
+                    y: y + offset.y,
+                };
+                segments.push(PathSegment::LineTo(current));
+            }
+            'C' | 'S' => {
+                let c1 = if upper == 'C' {
+                    parser
+                        .point(offset)
+                        .ok_or_else(|| error("expected point"))?
+                } else {
+                    reflect(last_cubic, current)
+                };
+                let c2 = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                let end = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                segments.push(PathSegment::CurveTo(c1, c2, end));
+                cubic = Some(c2);
+                current = end;
+            }
+            'Q' | 'T' => {
+                let control = if upper == 'Q' {
+                    parser
+                        .point(offset)
+                        .ok_or_else(|| error("expected point"))?
+                } else {
+                    reflect(last_quad, current)
+                };
+                let end = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                segments.push(quad_to_cubic(current, control, end));
+                quad = Some(control);
+                current = end;
+            }
+            'A' => {
+                let rx = parser.number().ok_or_else(|| error("expected radius"))?;
+                let ry = parser.number().ok_or_else(|| error("expected radius"))?;
+                let rotation = parser.number().ok_or_else(|| error("expected angle"))?;
+                let large_arc = parser.flag().ok_or_else(|| error("expected flag"))?;
+                let sweep = parser.flag().ok_or_else(|| error("expected flag"))?;
+                let end = parser
+                    .point(offset)
+                    .ok_or_else(|| error("expected point"))?;
+                let arc = Arc {
+                    rx,
+                    ry,
+                    rotation,
+                    large_arc,
+                    sweep,
+                };
+                arc_to_curves(current, end, &arc, &mut segments);
+                current = end;
+            }
+            'Z' => {
+                segments.push(PathSegment::Close);
+                current = start;
+                command = None;
+            }
+            _ => return Err(error(&format!("unknown command '{}'", cmd))),
+        }
+        last_cubic = cubic;
+        last_quad = quad;
+    }
+    match segments.first() {
+        Some(PathSegment::MoveTo(_)) => Ok(segments),
+        Some(_) => Err(error("path has to start with a move command")),
+        None => Err(error("path is empty")),
+    }
+}
+
+struct PathParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl PathParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    fn skip_separators(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    fn number(&mut self) -> Option<f32> {
+        self.skip_separators();
+        let begin = self.pos;
+        if let Some('+') | Some('-') = self.peek() {
+            self.pos += 1;
+        }
+        let mut seen_dot = false;
+        let mut seen_exp = false;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() {
+                self.pos += 1;
+            } else if c == '.' && !seen_dot && !seen_exp {
+                seen_dot = true;
+                self.pos += 1;
+            } else if (c == 'e' || c == 'E') && !seen_exp && self.pos > begin {
+                seen_exp = true;
+                self.pos += 1;
+                if let Some('+') | Some('-') = self.peek() {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[begin..self.pos].iter().collect();
+        text.parse::<f32>().ok()
+    }
+    fn point(&mut self, offset: Point) -> Option<Point> {
+        let x = self.number()?;
+        let y = self.number()?;
+        Some(Point {
+            x: x + offset.x,
+            y: y + offset.y,
+        })
+    }
+    /// Arc flags may be written without separators, e.g. "a5 5 0 01 10 0".
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.peek()? {
+            '0' => false,
+            '1' => true,
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(flag)
+    }
+}
+
+fn reflect(control: Option<Point>, current: Point) -> Point {
+    match control {
+        Some(control) => Point {
+            x: 2.0 * current.x - control.x,
+            y: 2.0 * current.y - control.y,
+        },
+        None => current,
+    }
+}
+
+fn quad_to_cubic(start: Point, control: Point, end: Point) -> PathSegment {
+    let c1 = Point {
+        x: start.x + 2.0 / 3.0 * (control.x - start.x),
+        y: start.y + 2.0 / 3.0 * (control.y - start.y),
+    };
+    let c2 = Point {
+        x: end.x + 2.0 / 3.0 * (control.x - end.x),
+        y: end.y + 2.0 / 3.0 * (control.y - end.y),
+    };
+    PathSegment::CurveTo(c1, c2, end)
+}
+
+struct Arc {
+    rx: f32,
+    ry: f32,
+    rotation: f32,
+    large_arc: bool,
+    sweep: bool,
+}
+
+/// Converts an elliptical arc to cubic curves, one curve per quarter turn at most.
+/// Follows the endpoint to centre conversion of the SVG specification (F.6.5).
+fn arc_to_curves(start: Point, end: Point, arc: &Arc, segments: &mut Vec<PathSegment>) {
+    let (mut rx, mut ry) = (arc.rx.abs(), arc.ry.abs());
+    if rx == 0.0 || ry == 0.0 || (start.x == end.x && start.y == end.y) {
+        segments.push(PathSegment::LineTo(end));
+        return;
+    }
+    let (sin_phi, cos_phi) = arc.rotation.to_radians().sin_cos();
+    let dx = (start.x - end.x) / 2.0;
+    let dy = (start.y - end.y) / 2.0;
+    let x1 = cos_phi * dx + sin_phi * dy;
+    let y1 = -sin_phi * dx + cos_phi * dy;
+    // scale up radii that are too small to reach the end point
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coefficient = (numerator / denominator).max(0.0).sqrt();
+    if arc.large_arc == arc.sweep {
+        coefficient = -coefficient;
+    }
+    let cx1 = coefficient * rx * y1 / ry;
+    let cy1 = -coefficient * ry * x1 / rx;
+    let cx = cos_phi * cx1 - sin_phi * cy1 + (start.x + end.x) / 2.0;
+    let cy = sin_phi * cx1 + cos_phi * cy1 + (start.y + end.y) / 2.0;
+    let angle = |ux: f32, uy: f32, vx: f32, vy: f32| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let theta = angle(1.0, 0.0, (x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut delta = angle(
+        (x1 - cx1) / rx,
+        (y1 - cy1) / ry,
+        (-x1 - cx1) / rx,
+        (-y1 - cy1) / ry,
+    );
+    let tau = std::f32::consts::PI * 2.0;
+    if !arc.sweep && delta > 0.0 {
+        delta -= tau;
+    } else if arc.sweep && delta < 0.0 {
+        delta += tau;
+    }
+    let count = (delta.abs() / (tau / 4.0)).ceil().max(1.0) as usize;
+    let step = delta / count as f32;
+    let kappa = 4.0 / 3.0 * (step / 4.0).tan();
+    let point_at = |t: f32, scale: f32, derivative: bool| {
+        let (sin_t, cos_t) = t.sin_cos();
+        let (ex, ey) = if derivative {
+            (-rx * sin_t * scale, ry * cos_t * scale)
+        } else {
+            (rx * cos_t, ry * sin_t)
+        };
+        (cos_phi * ex - sin_phi * ey, sin_phi * ex + cos_phi * ey)
+    };
+    let mut t = theta;
+    for index in 0..count {
+        let t2 = t + step;
+        let (x, y) = point_at(t, 1.0, false);
+        let (dx1, dy1) = point_at(t, kappa, true);
+        let (x2, y2) = point_at(t2, 1.0, false);
+        let (dx2, dy2) = point_at(t2, kappa, true);
+        let c1 = Point {
+            x: cx + x + dx1,
+            y: cy + y + dy1,
+        };
+        let c2 = Point {
+            x: cx + x2 - dx2,
+            y: cy + y2 - dy2,
+        };
+        // end exactly at the requested point
+        let end_point = if index == count - 1 {
+            end
+        } else {
+            Point {
+                x: cx + x2,
+                y: cy + y2,
+            }
+        };
+        segments.push(PathSegment::CurveTo(c1, c2, end_point));
+        t = t2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path() {
+        let segments = parse_path("M10,10 h 20 v20 Q 10 40 10 30 z").unwrap();
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[1], PathSegment::LineTo(Point { x: 30.0, y: 10.0 }));
+        assert_eq!(segments[2], PathSegment::LineTo(Point { x: 30.0, y: 30.0 }));
+        if let PathSegment::CurveTo(_, _, end) = segments[3] {
+            assert_eq!(end, Point { x: 10.0, y: 30.0 });
+        } else {
+            panic!("expected curve");
+        }
+        assert_eq!(segments[4], PathSegment::Close);
+        // half circle in two quarter curves
+        let arc = parse_path("M0 0 a5 5 0 01 10 0").unwrap();
+        assert_eq!(arc.len(), 3);
+        if let PathSegment::CurveTo(_, _, middle) = arc[1] {
+            assert!((middle.x - 5.0).abs() < 1e-4 && (middle.y + 5.0).abs() < 1e-4);
+        }
+        assert!(parse_path("L 10 10").is_err());
+        assert!(parse_path("M 10").is_err());
+        assert!(parse_path("M 0 0 X 1").is_err());
+    }
+}
@@ -1,11 +1,17 @@
-use super::canvas::Canvas;
-use super::models::{Document, Stationary};
+use super::canvas::{Canvas, PageInfo};
+use super::json::JsParamValue;
+use super::models::{ContentType, Document, Stationary};
+
+/// Layout passes before giving up on stable page numbers, the last pass is used.
+const MAX_PASSES: usize = 5;
 
 #[derive(Debug, Clone)]
 pub struct PageTemplate {
     page_size: (f32, f32),
     frame: Frame,
-    stationary: Vec<Stationary>,
+    stationary: Vec<(Stationary, PageFilter)>,
+    page_numbering: PageNumbering,
+    notes: NotesMode,
 }
 
 impl PageTemplate {
@@ -26,14 +32,58 @@ impl PageTemplate {
             page_size: size,
             frame,
             stationary: Vec::new(),
+            page_numbering: PageNumbering::new(),
+            notes: NotesMode::Foot,
         }
     }
     pub fn build(&self, doc: &Document) -> Result<Vec<u8>, &'static str> {
-        let mut canvas = Canvas::new(&self);
+        let has_toc = doc
+            .get_content()
+            .iter()
+            .any(|element| matches!(element.content_type(), ContentType::TableOfContents));
+        let page_info = PageInfo {
+            title: String::from(doc.get_title()),
+            date: String::from(doc.get_date()),
+            total_pages: None,
+            toc: if has_toc { Some(Vec::new()) } else { None },
+        };
+        let uses_page_count = self.uses_page_count();
+        let mut canvas = lay_out(
+            page_info,
+            |page_info| self.draw(doc, page_info),
+            |canvas, page_info| {
+                let mut next_info = page_info.clone();
+                if uses_page_count {
+                    next_info.total_pages = Some(canvas.page_number());
+                }
+                if has_toc {
+                    next_info.toc = Some(canvas.get_toc_entries());
+                }
+                next_info
+            },
+        )?;
+        canvas.build(
+            doc.get_info(),
+            doc.get_page_mode(),
+            doc.get_encryption(),
+            doc.get_signer(),
+            doc.get_attachments(),
+        )
+    }
+    fn draw(&self, doc: &Document, page_info: &PageInfo) -> Result<Canvas, &'static str> {
+        let mut canvas = Canvas::new(&self, page_info.clone());
         for element in doc.get_content() {
             element.draw(&mut canvas, self.frame.width)?;
         }
-        canvas.build()
+        canvas.draw_endnotes(self.frame.width)?;
+        Ok(canvas)
+    }
+    /// Check if stationary text shows the total page count.
+    fn uses_page_count(&self) -> bool {
+        self.stationary.iter().any(|(element, _)| match element {
+            Stationary::Text { text, .. } => text.contains("{pages}"),
+            _ => false,
+        })
     }
     pub fn get_size(&self) -> (f32, f32) {
         self.page_size
@@ -41,11 +91,189 @@ impl PageTemplate {
     pub fn get_frame(&self) -> Frame {
         self.frame
     }
-    pub fn add_stationary(&mut self, object: Stationary) {
-        self.stationary.push(object);
+    /// Add stationary element drawn on pages matching the filter.
+    pub fn add_stationary(&mut self, object: Stationary, pages: PageFilter) {
+        self.stationary.push((object, pages));
+    }
+    /// Stationary elements of the page (1 is the first page of the document).
+    pub fn stationary(&self, page: u16) -> Vec<Stationary> {
+        self.stationary
+            .iter()
+            .filter(|(_, pages)| pages.matches(page))
+            .map(|(element, _)| element.clone())
+            .collect()
+    }
+    pub fn set_notes(&mut self, notes: NotesMode) {
+        self.notes = notes;
+    }
+    pub fn get_notes(&self) -> NotesMode {
+        self.notes
+    }
+    pub fn set_page_numbering(&mut self, page_numbering: PageNumbering) {
+        self.page_numbering = page_numbering;
     }
-    pub fn stationary(&self) -> Vec<Stationary> {
-        self.stationary.clone()
+    pub fn get_page_numbering(&self) -> PageNumbering {
+        self.page_numbering
+    }
+}
+
+/// Pages of the document a stationary element is drawn on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageFilter {
+    All,
+    First,
+    NotFirst,
+    Odd,
+    Even,
+    /// Inclusive page ranges, open ended when there's no last page
+    Ranges(Vec<(u16, Option<u16>)>),
+}
+
+impl PageFilter {
+    /// Filter from `pages` param: "all", "first", "not-first", "odd", "even" or ranges
+    /// as an array of page numbers, "2-5" or "3-" strings, or a comma separated string.
+    pub fn from_param(param: Option<&JsParamValue>) -> Result<PageFilter, String> {
+        match param {
+            None | Some(JsParamValue::Null) => Ok(PageFilter::All),
+            Some(JsParamValue::Text(text)) => match text.to_lowercase().as_str() {
+                "all" => Ok(PageFilter::All),
+                "first" => Ok(PageFilter::First),
+                "not-first" => Ok(PageFilter::NotFirst),
+                "odd" => Ok(PageFilter::Odd),
+                "even" => Ok(PageFilter::Even),
+                _ => {
+                    let ranges = text
+                        .split(',')
+                        .map(|range| PageFilter::parse_range(range.trim()))
+                        .collect::<Result<_, _>>()?;
+                    Ok(PageFilter::Ranges(ranges))
+                }
+            },
+            Some(JsParamValue::Number(page)) => {
+                let page = PageFilter::page_number(*page)?;
+                Ok(PageFilter::Ranges(vec![(page, Some(page))]))
+            }
+            Some(JsParamValue::Array(values)) => {
+                let mut ranges = Vec::new();
+                for value in values {
+                    match value {
+                        JsParamValue::Number(page) => {
+                            let page = PageFilter::page_number(*page)?;
+                            ranges.push((page, Some(page)))
+                        }
+                        JsParamValue::Text(range) => ranges.push(PageFilter::parse_range(range)?),
+                        _ => return Err("Invalid page range in pages.".to_string()),
+                    }
+                }
+                Ok(PageFilter::Ranges(ranges))
+            }
+            _ => Err("Invalid pages, expected a name or page ranges.".to_string()),
+        }
+    }
+    fn page_number(page: f32) -> Result<u16, String> {
+        if page < 1.0 || page > f32::from(u16::MAX) || page.fract() != 0.0 {
+            return Err(format!("Invalid page {} in pages.", page));
+        }
+        Ok(page as u16)
+    }
+    fn parse_range(range: &str) -> Result<(u16, Option<u16>), String> {
+        let error = || format!("Invalid page range '{}'.", range);
+        let parse = |page: &str| match page.trim().parse::<u16>() {
+            Ok(page) if page >= 1 => Ok(page),
+            _ => Err(error()),
+        };
+        match range.split_once('-') {
+            Some((first, "")) => Ok((parse(first)?, None)),
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if last < first {
+                    return Err(error());
+                }
+                Ok((first, Some(last)))
+            }
+            None => {
+                let page = parse(range)?;
+                Ok((page, Some(page)))
+            }
+        }
+    }
+    pub fn matches(&self, page: u16) -> bool {
+        match self {
+            PageFilter::All => true,
+            PageFilter::First => page == 1,
+            PageFilter::NotFirst => page > 1,
+            PageFilter::Odd => page & 1 == 1,
+            PageFilter::Even => page & 1 == 0,
+            PageFilter::Ranges(ranges) => ranges
+                .iter()
+                .any(|(first, last)| page >= *first && page <= last.unwrap_or(u16::MAX)),
+        }
+    }
+}
+
+/// Lays out with `draw` again with the page count and table of contents entries
+/// of the previous pass, given by `next`, until they don't change. Fails if they
+/// still change after `MAX_PASSES`, the numbers would not match the pages.
+fn lay_out<T>(
+    mut page_info: PageInfo,
+    mut draw: impl FnMut(&PageInfo) -> Result<T, &'static str>,
+    next: impl Fn(&T, &PageInfo) -> PageInfo,
+) -> Result<T, &'static str> {
+    let mut result = draw(&page_info)?;
+    for _ in 1..MAX_PASSES {
+        let next_info = next(&result, &page_info);
+        if next_info == page_info {
+            return Ok(result);
+        }
+        page_info = next_info;
+        result = draw(&page_info)?;
+    }
+    if next(&result, &page_info) != page_info {
+        return Err("Page count and table of contents don't settle between layout passes.");
+    }
+    Ok(result)
+}
+
+/// Displayed page numbers: the first `skip` pages (e.g. cover) are not numbered
+/// and numbering starts from `start` on the page after them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageNumbering {
+    pub start: i32,
+    pub skip: u16,
+}
+
+impl PageNumbering {
+    pub fn new() -> PageNumbering {
+        PageNumbering { start: 1, skip: 0 }
+    }
+    /// Displayed number of a page (1 is the first page of the document), None for skipped pages.
+    pub fn get_number(&self, page: u16) -> Option<i32> {
+        if page <= self.skip {
+            None
+        } else {
+            Some(self.start + i32::from(page - self.skip) - 1)
+        }
+    }
+}
+
+/// Where note bodies are placed: at the bottom of the page (footnotes) or
+/// collected to an endnotes section.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NotesMode {
+    Foot,
+    End,
+}
+
+impl NotesMode {
+    pub fn from_name(name: &str) -> Result<NotesMode, String> {
+        match name.to_lowercase().as_str() {
+            "" | "foot" => Ok(NotesMode::Foot),
+            "end" => Ok(NotesMode::End),
+            _ => Err(format!(
+                "Invalid notes '{}', expected \"foot\" or \"end\".",
+                name
+            )),
+        }
     }
 }
 
@@ -70,3 +298,105 @@ impl Frame {
         (self.x, self.y, self.width, self.height)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_filters() {
+        let filter = |json: &str| {
+            let value: JsParamValue = serde_json::from_str(json).unwrap();
+            PageFilter::from_param(Some(&value)).unwrap()
+        };
+        let pages = |filter: PageFilter| -> Vec<u16> {
+            (1..=8).filter(|page| filter.matches(*page)).collect()
+        };
+        assert_eq!(pages(filter(r#""first""#)), vec![1]);
+        assert_eq!(pages(filter(r#""not-first""#)), vec![2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(pages(filter(r#""even""#)), vec![2, 4, 6, 8]);
+        assert_eq!(pages(filter(r#"[1, "3-4", "7-"]"#)), vec![1, 3, 4, 7, 8]);
+        assert_eq!(pages(filter(r#""2-3, 5""#)), vec![2, 3, 5]);
+        assert!(PageFilter::from_param(Some(&JsParamValue::Text("odds".into()))).is_err());
+        assert_eq!(pages(filter(r#""odd""#)), vec![1, 3, 5, 7]);
+        assert_eq!(pages(filter("3")), vec![3]);
+        for invalid in &[
+            "-1",
+            "0",
+            "1.5",
+            "[2, -3]",
+            "70000",
+            r#""0""#,
+            r#""0-3""#,
+            r#""5-2""#,
+            r#"["1", "4-3"]"#,
+        ] {
+            let value: JsParamValue = serde_json::from_str(invalid).unwrap();
+            assert!(PageFilter::from_param(Some(&value)).is_err());
+        }
+        assert_eq!(
+            PageFilter::parse_range("5-2"),
+            Err(String::from("Invalid page range '5-2'."))
+        );
+        assert_eq!(pages(filter(r#""3-3""#)), vec![3]);
+    }
+
+    #[test]
+    fn test_lay_out_passes() {
+        // the page count settles on the second pass
+        let mut passes = 0;
+        let pages = lay_out(
+            PageInfo::default(),
+            |info| {
+                passes += 1;
+                Ok(info.total_pages.unwrap_or(0) / 2 + 2)
+            },
+            |pages, info| PageInfo {
+                total_pages: Some(*pages),
+                ..info.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!((pages, passes), (3, 3));
+        let next = |pages: &u16, info: &PageInfo| PageInfo {
+            total_pages: Some(*pages),
+            ..info.clone()
+        };
+        // settling on the last pass is accepted
+        let mut passes = 0;
+        let pages = lay_out(
+            PageInfo::default(),
+            |info| {
+                passes += 1;
+                Ok((info.total_pages.unwrap_or(0) + 1).min(MAX_PASSES as u16 - 1))
+            },
+            next,
+        )
+        .unwrap();
+        assert_eq!((pages, passes), (4, MAX_PASSES));
+        // a page count alternating between passes never settles
+        let mut passes = 0;
+        let result = lay_out(
+            PageInfo::default(),
+            |info| {
+                passes += 1;
+                Ok(if info.total_pages == Some(4) { 5 } else { 4 })
+            },
+            next,
+        );
+        assert_eq!(
+            result,
+            Err("Page count and table of contents don't settle between layout passes.")
+        );
+        assert_eq!(passes, MAX_PASSES);
+    }
+
+    #[test]
+    fn test_page_numbering_skips_cover() {
+        let numbering = PageNumbering { start: 1, skip: 1 };
+        assert_eq!(numbering.get_number(1), None);
+        assert_eq!(numbering.get_number(2), Some(1));
+        let numbering = PageNumbering { start: 5, skip: 0 };
+        assert_eq!(numbering.get_number(3), Some(7));
+    }
+}
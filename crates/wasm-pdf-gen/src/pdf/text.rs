@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use super::annotations::Markup;
 use super::encoders::winansi;
 use super::font::{get_font, Font};
 use regex::Regex;
@@ -8,10 +9,30 @@ use std::io::Write;
 #[derive(Debug, Clone)]
 pub enum Tag {
     Span,
-    Link { url: String },
+    Link {
+        url: String,
+    },
+    /// Named destination, links to it with href='#name'
+    Anchor {
+        name: String,
+    },
     Bold,
+    /// Note reference, numbered when drawn. The text is the note body.
+    Footnote {
+        text: String,
+    },
+    /// Small raised text, e.g. note numbers
+    Superscript,
+    /// Text with a review comment (<annot type='highlight' contents='...'>)
+    Annotation {
+        markup: Markup,
+    },
 }
 
+/// Size and rise of superscript text relative to the font size
+pub const SUPERSCRIPT_SCALE: f32 = 0.6;
+pub const SUPERSCRIPT_RISE: f32 = 0.35;
+
 /// TextSpan contains a fragment of paragraph text,
 /// that may have some attributes/tags.
 #[derive(Debug, Clone)]
@@ -29,10 +50,13 @@ impl TextSpan {
     }
     /// Generate all spans for given text.
     /// Combines <a> and <b> tags into one regex to get capture groups.
+    /// Anchors (<a name='...'>) are kept even without text, footnote (<footnote>) spans
+    /// have no text of their own. Annotations with invalid attributes are plain text,
+    /// see check_annotations.
     pub fn extract_spans(p_text: &str) -> Vec<TextSpan> {
         lazy_static! {
             static ref RE: Regex = Regex::new(
-                r"(<a[\s]+href='(?P<url>[^']+)'[^>]*?>(?P<a_text>.*?)</a>)|(<b>(?P<b_text>.*?)</b>)"
+                r"(<a[\s]+(?:href='(?P<url>[^']+)'|name='(?P<name>[^']+)')[^>]*?>(?P<a_text>.*?)</a>)|(<b>(?P<b_text>.*?)</b>)|(<footnote>(?P<f_text>.*?)</footnote>)|(<annot(?P<attributes>(?:\s+\w+='[^']*')*)\s*>(?P<annot_text>.*?)</annot>)"
             )
             .unwrap();
         }
@@ -58,12 +82,38 @@ impl TextSpan {
                         );
                         text_parts.push(span);
                     }
+                } else if let (Some(name), Some(text)) =
+                    (capture.name("name"), capture.name("a_text"))
+                {
+                    let span = TextSpan::new(
+                        text.as_str(),
+                        Tag::Anchor {
+                            name: String::from(name.as_str()),
+                        },
+                    );
+                    text_parts.push(span);
                 } else if let Some(text) = capture.name("b_text") {
                     let text = text.as_str();
                     if !text.is_empty() {
                         let span = TextSpan::new(text, Tag::Bold);
                         text_parts.push(span);
                     }
+                } else if let (Some(attributes), Some(text)) =
+                    (capture.name("attributes"), capture.name("annot_text"))
+                {
+                    let tag = match Markup::from_attributes(attributes.as_str()) {
+                        Ok(markup) => Tag::Annotation { markup },
+                        Err(_) => Tag::Span,
+                    };
+                    text_parts.push(TextSpan::new(text.as_str(), tag));
+                } else if let Some(text) = capture.name("f_text") {
+                    let span = TextSpan::new(
+                        "",
+                        Tag::Footnote {
+                            text: String::from(text.as_str()),
+                        },
+                    );
+                    text_parts.push(span);
                 }
                 current_index = end_index;
             }
@@ -84,6 +134,13 @@ impl TextSpan {
     /// Get width of text
     pub fn get_width(&self, font: &'static Font, font_size: f32) -> f32 {
         match self.tag {
+            // note references are numbered when they are drawn
+            Tag::Footnote { .. } if self.text.is_empty() => {
+                font.get_width(font_size * SUPERSCRIPT_SCALE, "0")
+            }
+            Tag::Superscript | Tag::Footnote { .. } => {
+                font.get_width(font_size * SUPERSCRIPT_SCALE, &self.text)
+            }
             Tag::Bold => {
                 let font_name = font.get_name().to_lowercase();
                 let new_font_name = if !font_name.ends_with("bold") {
@@ -123,10 +180,21 @@ impl TextSpan {
     }
 }
 
+/// Check the attributes of annotation tags in paragraph text.
+pub fn check_annotations(text: &str) -> Result<(), String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"<annot((?:\s+\w+='[^']*')*)\s*>").unwrap();
+    }
+    for capture in RE.captures_iter(text) {
+        Markup::from_attributes(&capture[1])?;
+    }
+    Ok(())
+}
+
 pub fn extract_links(text: &str) -> String {
     lazy_static! {
         static ref RE: Regex =
-            Regex::new(r"<a[\s]+href='(?P<url>[^']+)'[^>]*?>(?P<link>.*?)</a>").unwrap();
+            Regex::new(r"<a[\s]+(?:href|name)='[^']+'[^>]*?>(?P<link>.*?)</a>").unwrap();
     }
     RE.replace_all(text, "$link").into()
 }
@@ -136,21 +204,30 @@ mod tests {
     use super::*;
     use crate::pdf::models::Paragraph;
     use crate::pdf::styles::{HorizontalAlign, ParagraphStyle};
-    use lazy_static;
 
     #[test]
     fn test_link_removal() {
-        lazy_static! {
-            static ref RE: Regex =
-                Regex::new(r"<a[\s]+href='(?P<url>[^']+)'[^>]*?>(?P<link>.*?)</a>").unwrap();
-        }
-        let output: String = RE
-            .replace_all(
-                "<a href='https://www.google.com'>A Link to Google</a>",
-                "$link",
-            )
-            .into();
-        assert_eq!(output.as_str(), "A Link to Google");
+        assert_eq!(
+            extract_links("<a href='https://www.google.com'>A Link to Google</a>"),
+            "A Link to Google"
+        );
+        assert_eq!(
+            extract_links("<a name='results'>Results</a> and <a href='#end'>the end</a>."),
+            "Results and the end."
+        );
+    }
+
+    #[test]
+    fn test_anchor_and_footnote_spans() {
+        let spans = TextSpan::extract_spans(
+            "<a name='intro'></a>Intro<footnote>See <b>notes</b>.</footnote> ends.",
+        );
+        assert_eq!(spans.len(), 4);
+        assert!(matches!(&spans[0].tag, Tag::Anchor { name } if name == "intro"));
+        assert_eq!(spans[1].text, "Intro");
+        assert!(matches!(&spans[2].tag, Tag::Footnote { text } if text == "See <b>notes</b>."));
+        assert_eq!(spans[2].text, "");
+        assert_eq!(spans[3].text, " ends.");
     }
 
     #[test]
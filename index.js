@@ -2,7 +2,11 @@ const wasm_pdf = import('./pkg');
 
 const createPDF = (jsDocument) => {
     wasm_pdf.then(pdf => {
-        const imagePaths = parseJsDoc(jsDocument.contents);
+        const imagePaths = parseJsDoc([jsDocument.contents, jsDocument.stationary]);
+        const watermark = jsDocument.template && jsDocument.template.watermark;
+        if (watermark && watermark.src) {
+            imagePaths.push(watermark.src);
+        }
         fetchImagePaths(imagePaths).then((imgData) => {
             // add base64 encoded bytes to document
             jsDocument.image_data = {};
@@ -34,7 +38,10 @@ const imageBytes = (url) => {
             canvas.height = img.height;
             let ctx = canvas.getContext("2d");
             ctx.drawImage(img, 0, 0);
-            let dataURL = canvas.toDataURL("image/jpeg", 0.8);
+            // PNG keeps transparency, other formats are converted to JPEG
+            let dataURL = url.toLowerCase().endsWith(".png")
+                ? canvas.toDataURL("image/png")
+                : canvas.toDataURL("image/jpeg", 0.8);
             resolve({
                 path: url,
                 data: stripDataURI(dataURL),
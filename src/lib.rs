@@ -19,10 +19,20 @@ extern "C" {
 pub fn run(json: &JsValue) -> Result<(), JsValue> {
     // output panics to console.error
     console_error_panic_hook::set_once();
-    let js_doc = get_js_doc(&json)?;
+    let mut js_doc = get_js_doc(&json)?;
+    if js_doc.date.is_empty() {
+        // system time is not available in wasm, use local date from JavaScript
+        let now = js_sys::Date::new_0();
+        js_doc.date = format!(
+            "{:04}-{:02}-{:02}",
+            now.get_full_year(),
+            now.get_month() + 1,
+            now.get_date()
+        );
+    }
     let bytes = match create(&js_doc) {
         Ok(b) => b,
-        Err(s) => return Err(JsValue::from_str(s)),
+        Err(s) => return Err(JsValue::from_str(&s)),
     };
     generate_file(&bytes);
     Ok(())